@@ -2,6 +2,8 @@
 library;
 
 import 'dart:ffi';
+import 'dart:io' show Platform;
+import 'dart:typed_data';
 
 import 'package:d_bincode/d_bincode.dart';
 
@@ -11,73 +13,1275 @@ final nativeLib = DynamicLibrary.open(
   'native/target/release/libnative.so',
 ); // NOTE: FOR LINUX ONLY
 
+/// The magic bytes (`VRGL`) at the start of every buffer exchanged with the [nativeLib].
+const _bufferMagic = [0x56, 0x52, 0x47, 0x4C];
+
+/// Identifies the bincode config in buffer headers (must match `BINCODE_CONFIG_TAG` in Rust).
+const _bincodeConfigTag = 1;
+
+/// Encodes the value, prefixed with the header expected by the [nativeLib].
+Uint8List encodeMessage(BincodeCodable value) {
+  final encoded = BincodeWriter.encode(value);
+  return Uint8List.fromList([..._bufferMagic, _bincodeConfigTag, ...encoded]);
+}
+
+/// Decodes a buffer returned by the [nativeLib] into [value], after validating its header.
+///
+/// Throws a [FormatException] if the header is missing, or was written with an incompatible
+/// bincode config.
+T decodeMessage<T extends BincodeCodable>(Uint8List bytes, T value) {
+  final headerLen = _bufferMagic.length + 1;
+  if (bytes.length < headerLen) {
+    throw FormatException('Buffer too short for header (${bytes.length} bytes)');
+  }
+  for (var i = 0; i < _bufferMagic.length; i++) {
+    if (bytes[i] != _bufferMagic[i]) {
+      throw FormatException('Buffer is missing the magic bytes');
+    }
+  }
+  final tag = bytes[_bufferMagic.length];
+  if (tag != _bincodeConfigTag) {
+    throw FormatException(
+      'Incompatible bincode config (expected tag $_bincodeConfigTag, found $tag)',
+    );
+  }
+  return BincodeReader.decode(Uint8List.sublistView(bytes, headerLen), value);
+}
+
 // ==================================================================
 // Native `Message` types
 // ==================================================================
 
-/// The log level for the [nativeLib].
-enum LogLevel { trace, debug, info, warn, error }
+/// The log level for the [nativeLib].
+enum LogLevel { trace, debug, info, warn, error }
+
+/// The status of a call to the [nativeLib].
+enum MessageStatus { success, error, alreadyListening, alreadyLoaded, queued }
+
+/// The state of the listening pipeline of the [nativeLib].
+enum ListeningState {
+  /// No session is running.
+  idle,
+
+  /// The microphone is capturing audio, waiting for a wake word.
+  listening,
+
+  /// A wake word was detected (or wake words are ignored), so commands are being captured.
+  armed,
+
+  /// A command is being transcribed.
+  transcribing,
+
+  /// The microphone is paused.
+  paused,
+
+  /// The session stopped because of an error (until the next session starts).
+  error,
+}
+
+/// What happens when listening is requested while a session is already running.
+enum OverlapPolicy {
+  /// The request is rejected with [MessageStatus.alreadyListening].
+  reject,
+
+  /// The request is queued ([MessageStatus.queued]), and starts once the running session ends.
+  queue,
+
+  /// The running session is stopped and replaced.
+  replaceLatest,
+}
+
+/// How multiple audio sources are transcribed.
+enum SourceMode {
+  /// Each source is transcribed on its own, producing a transcript per source.
+  perSource,
+
+  /// The sources are mixed to mono (averaged, aligned at their start), producing a single
+  /// transcript.
+  mix,
+}
+
+/// How live audio is accumulated into windows before it's transcribed.
+enum AccumulationMode {
+  /// Audio is transcribed in fixed-length windows, ending early when the speaker pauses.
+  window,
+
+  /// Wake words are ignored, and each utterance is transcribed as a whole and sent as an
+  /// [Utterance] event, for live dictation.
+  dictation,
+}
+
+/// A sample format the microphone can capture in.
+enum InputSampleFormat { f32, i16, u16 }
+
+/// The context used for the [nativeLib].
+class Context implements BincodeCodable {
+  Context({
+    required this.modelPath,
+    this.assetDir,
+    required this.wakeWords,
+    ListenConfig? listen,
+    this.warmupModel = true,
+    this.language,
+    TranscriptionConfig? transcription,
+    int? detectNThreads,
+    this.commitIntervalMs = 0,
+    this.compressionRatioThreshold = 2.4,
+    this.ignoreWakeWords = false,
+    this.detectEveryNWindows = 1,
+    this.wakeWordEvents = false,
+    this.firstWordEvents = false,
+    this.noSpeechEvents = false,
+    this.noSpeechIntervalMs = 1000,
+    this.minConfidence = 0.0,
+    this.trimSegments = true,
+    this.maxSegments = 0,
+    this.mergeSentences = false,
+    this.sendRawAudio = false,
+    this.rawAudioIntervalMs = 0,
+    this.includeRawTranscript = false,
+    this.reportLanguage = false,
+    JsonEvents? jsonEvents,
+    this.retryEmptyTranscripts = false,
+    this.useGpu = true,
+    this.gpuFallback = true,
+    this.mmapModel = false,
+    this.inputChannel,
+    List<InputSampleFormat>? sampleFormats,
+    this.correctRateDrift = false,
+    this.audioCtx,
+    this.maxPoolWorkers = 2,
+    this.overlapPolicy = OverlapPolicy.reject,
+    this.maxAudioSources = 4,
+    this.sourceMode = SourceMode.perSource,
+    this.nBest = 1,
+    this.accumulationMode = AccumulationMode.window,
+    this.streaming = false,
+    this.wakeWordMaxDistance = 1,
+    this.stripAnnotations = true,
+  }) : listen = listen ?? ListenConfig(),
+       transcription = transcription ?? TranscriptionConfig(),
+       jsonEvents = jsonEvents ?? JsonEvents(),
+       sampleFormats =
+           sampleFormats ??
+           [InputSampleFormat.f32, InputSampleFormat.i16, InputSampleFormat.u16],
+       detectNThreads = detectNThreads ?? Platform.numberOfProcessors;
+
+  Context.empty()
+    : modelPath = '',
+      wakeWords = [],
+      listen = ListenConfig.empty(),
+      warmupModel = true,
+      transcription = TranscriptionConfig.empty(),
+      detectNThreads = 0,
+      commitIntervalMs = 0,
+      compressionRatioThreshold = 2.4,
+      ignoreWakeWords = false,
+      detectEveryNWindows = 1,
+      wakeWordEvents = false,
+      firstWordEvents = false,
+      noSpeechEvents = false,
+      noSpeechIntervalMs = 1000,
+      minConfidence = 0.0,
+      trimSegments = true,
+      maxSegments = 0,
+      mergeSentences = false,
+      sendRawAudio = false,
+      rawAudioIntervalMs = 0,
+      includeRawTranscript = false,
+      reportLanguage = false,
+      jsonEvents = JsonEvents(),
+      retryEmptyTranscripts = false,
+      useGpu = true,
+      gpuFallback = true,
+      mmapModel = false,
+      sampleFormats = [],
+      correctRateDrift = false,
+      maxPoolWorkers = 2,
+      overlapPolicy = OverlapPolicy.reject,
+      maxAudioSources = 4,
+      sourceMode = SourceMode.perSource,
+      nBest = 1,
+      accumulationMode = AccumulationMode.window,
+      streaming = false,
+      wakeWordMaxDistance = 1,
+      stripAnnotations = true;
+
+  /// The path to the `Whisper` model; relative paths are resolved against [assetDir] (if set).
+  String modelPath;
+
+  /// The directory that relative asset paths (e.g. [modelPath]) are resolved against, such as an
+  /// app-specific directory on mobile.
+  String? assetDir;
+
+  /// The list of wake words to listen for/wake to.
+  List<String> wakeWords;
+
+  /// The audio/listening settings.
+  ListenConfig listen;
+
+  /// Determines if a warmup inference is run after the model is loaded.
+  bool warmupModel;
+
+  /// The language spoken in the audio (uses the model's default if `null`).
+  ///
+  /// This is a language code (e.g. `'es'`), or `'auto'` to detect the language of each window
+  /// (which needs a multilingual model).
+  String? language;
+
+  /// The sampling settings (and number of threads) used for transcription.
+  TranscriptionConfig transcription;
+
+  /// The number of threads used for wake word detection (uses `Whisper`'s default if `0`).
+  int detectNThreads;
+
+  /// The minimum time between partial transcripts sent from the [nativeLib] when [streaming] (in
+  /// milliseconds).
+  ///
+  /// Segments decoded within the interval are coalesced, while final transcripts are always sent
+  /// immediately.
+  int commitIntervalMs;
+
+  /// The compression ratio above which a transcribed segment is dropped (disabled if `0`).
+  double compressionRatioThreshold;
+
+  /// Determines if all audio is transcribed, without waiting for a wake word (for debugging).
+  bool ignoreWakeWords;
+
+  /// Wake words are only detected on every `n`th window while waiting for one, to save CPU (on
+  /// every window if `0` or `1`).
+  int detectEveryNWindows;
+
+  /// Determines if a [WakeWordDetected] event is sent when a wake word is detected.
+  bool wakeWordEvents;
+
+  /// Determines if a [FirstWord] event is sent as soon as the first word of each utterance is
+  /// recognized (before the whole command is transcribed).
+  bool firstWordEvents;
+
+  /// Determines if a [EventKind.noSpeech] event is sent when no speech was recognized in a
+  /// transcribed window.
+  bool noSpeechEvents;
+
+  /// The minimum time between [EventKind.noSpeech] events (in milliseconds); events within the
+  /// interval are dropped (unthrottled if `0`).
+  int noSpeechIntervalMs;
+
+  /// The mean segment confidence (from `0` to `1`) below which a transcript is sent as a
+  /// [LowConfidence] event instead (disabled if `0`).
+  double minConfidence;
+
+  /// Determines if transcribed segments are trimmed and joined with a single space (collapsing any
+  /// other runs of whitespace too).
+  bool trimSegments;
+
+  /// The maximum number of segments included in a transcript (unlimited if `0`).
+  int maxSegments;
+
+  /// Determines if consecutive segments are merged into sentences (cut at 200 characters if
+  /// there's no sentence-ending punctuation).
+  bool mergeSentences;
+
+  /// Determines if every window of audio is sent (as a `Float32List` of 16kHz samples) before
+  /// it's transcribed (for debugging; this sends a lot of data).
+  bool sendRawAudio;
+
+  /// The minimum time between raw audio windows (in milliseconds); windows within the interval
+  /// are dropped (unthrottled if `0`).
+  int rawAudioIntervalMs;
+
+  /// Determines if the raw transcript is sent alongside the post-processed one, as a
+  /// [Transcript] event (for debugging).
+  bool includeRawTranscript;
+
+  /// Determines if the detected language is sent with each transcript, as a [Transcript] event.
+  bool reportLanguage;
+
+  /// The kinds of events sent as JSON strings instead of encoded bytes.
+  JsonEvents jsonEvents;
+
+  /// Determines if transcription is retried once (with a higher temperature) when the transcript
+  /// only contains whitespace/punctuation.
+  bool retryEmptyTranscripts;
+
+  /// Determines if the model runs on the GPU.
+  bool useGpu;
+
+  /// Determines if the model is reloaded on the CPU when inference on the GPU fails.
+  bool gpuFallback;
+
+  /// Determines if the model file is memory-mapped while the model is built, instead of being
+  /// read from the file (can help with large models on low-memory devices).
+  bool mmapModel;
+
+  /// The input channel used on multi-channel devices (all channels are downmixed if `null`).
+  int? inputChannel;
+
+  /// The sample formats the microphone may capture in, from most to least preferred (samples
+  /// are converted to `f32` regardless).
+  List<InputSampleFormat> sampleFormats;
+
+  /// Determines if audio is resampled from the rate the microphone actually delivers, when it
+  /// drifts from the rate it reports (drift is logged either way).
+  bool correctRateDrift;
+
+  /// The size of `Whisper`'s audio context, from `1` to `1500` (uses the full context if
+  /// `null`).
+  int? audioCtx;
+
+  /// The maximum number of workers used to transcribe multiple recordings concurrently (each
+  /// worker adds to the memory usage).
+  int maxPoolWorkers;
+
+  /// What happens when listening is requested while a session is already running.
+  OverlapPolicy overlapPolicy;
+
+  /// The maximum number of audio sources (e.g. recordings) transcribed together (unlimited if
+  /// `0`).
+  int maxAudioSources;
+
+  /// Determines if audio sources are transcribed separately, or mixed into one.
+  SourceMode sourceMode;
+
+  /// The number of alternative transcriptions decoded for each utterance (see
+  /// [Transcript.alternatives]); only the best one is decoded if this is `1`.
+  int nBest;
+
+  /// How live audio is accumulated into windows before it's transcribed.
+  AccumulationMode accumulationMode;
+
+  /// Determines if each segment of a live session's transcripts is sent as a [Partial] event as
+  /// soon as it's decoded (the final transcript is still sent once the window is done).
+  bool streaming;
+
+  /// The maximum edit distance (in characters) between a wake word and the words heard for it to
+  /// be detected, so slight mishearings (e.g. "Hey Vergil" for "Hey Virgil") still count.
+  ///
+  /// Wake words must be heard exactly if this is `0`.
+  int wakeWordMaxDistance;
+
+  /// Determines if bracketed annotations (e.g. `[BLANK_AUDIO]` or `(music)`) are removed from
+  /// transcripts, so they don't count as speech.
+  bool stripAnnotations;
+
+  @override
+  void decode(BincodeReader reader) {
+    modelPath = reader.readString();
+    assetDir = reader.readOptionString();
+    wakeWords = reader.readList(reader.readString);
+    listen.decode(reader);
+    warmupModel = reader.readBool();
+    language = reader.readOptionString();
+    transcription.decode(reader);
+    detectNThreads = reader.readU64();
+    commitIntervalMs = reader.readU64();
+    compressionRatioThreshold = reader.readF32();
+    ignoreWakeWords = reader.readBool();
+    detectEveryNWindows = reader.readU64();
+    wakeWordEvents = reader.readBool();
+    firstWordEvents = reader.readBool();
+    noSpeechEvents = reader.readBool();
+    noSpeechIntervalMs = reader.readU64();
+    minConfidence = reader.readF32();
+    trimSegments = reader.readBool();
+    maxSegments = reader.readU64();
+    mergeSentences = reader.readBool();
+    sendRawAudio = reader.readBool();
+    rawAudioIntervalMs = reader.readU64();
+    includeRawTranscript = reader.readBool();
+    reportLanguage = reader.readBool();
+    jsonEvents.decode(reader);
+    retryEmptyTranscripts = reader.readBool();
+    useGpu = reader.readBool();
+    gpuFallback = reader.readBool();
+    mmapModel = reader.readBool();
+    inputChannel = reader.readOptionU64();
+    sampleFormats = reader.readList(
+      () => InputSampleFormat.values[reader.readU32()],
+    );
+    correctRateDrift = reader.readBool();
+    audioCtx = reader.readOptionI32();
+    maxPoolWorkers = reader.readU64();
+    overlapPolicy = OverlapPolicy.values[reader.readU32()];
+    maxAudioSources = reader.readU64();
+    sourceMode = SourceMode.values[reader.readU32()];
+    nBest = reader.readU64();
+    accumulationMode = AccumulationMode.values[reader.readU32()];
+    streaming = reader.readBool();
+    wakeWordMaxDistance = reader.readU64();
+    stripAnnotations = reader.readBool();
+  }
+
+  @override
+  void encode(BincodeWriter writer) {
+    writer.writeString(modelPath);
+    writer.writeOptionString(assetDir);
+    writer.writeList(wakeWords, writer.writeString);
+    listen.encode(writer);
+    writer.writeBool(warmupModel);
+    writer.writeOptionString(language);
+    transcription.encode(writer);
+    writer.writeU64(detectNThreads);
+    writer.writeU64(commitIntervalMs);
+    writer.writeF32(compressionRatioThreshold);
+    writer.writeBool(ignoreWakeWords);
+    writer.writeU64(detectEveryNWindows);
+    writer.writeBool(wakeWordEvents);
+    writer.writeBool(firstWordEvents);
+    writer.writeBool(noSpeechEvents);
+    writer.writeU64(noSpeechIntervalMs);
+    writer.writeF32(minConfidence);
+    writer.writeBool(trimSegments);
+    writer.writeU64(maxSegments);
+    writer.writeBool(mergeSentences);
+    writer.writeBool(sendRawAudio);
+    writer.writeU64(rawAudioIntervalMs);
+    writer.writeBool(includeRawTranscript);
+    writer.writeBool(reportLanguage);
+    jsonEvents.encode(writer);
+    writer.writeBool(retryEmptyTranscripts);
+    writer.writeBool(useGpu);
+    writer.writeBool(gpuFallback);
+    writer.writeBool(mmapModel);
+    writer.writeOptionU64(inputChannel);
+    writer.writeList(sampleFormats, (format) => writer.writeU32(format.index));
+    writer.writeBool(correctRateDrift);
+    writer.writeOptionI32(audioCtx);
+    writer.writeU64(maxPoolWorkers);
+    writer.writeU32(overlapPolicy.index);
+    writer.writeU64(maxAudioSources);
+    writer.writeU32(sourceMode.index);
+    writer.writeU64(nBest);
+    writer.writeU32(accumulationMode.index);
+    writer.writeBool(streaming);
+    writer.writeU64(wakeWordMaxDistance);
+    writer.writeBool(stripAnnotations);
+  }
+}
+
+/// The kinds of events sent from the [nativeLib] as JSON strings (with a `type` field, e.g.
+/// `{"type": "noSpeech"}`) instead of encoded bytes.
+class JsonEvents implements BincodeCodable {
+  JsonEvents({
+    this.wakeWordDetected = false,
+    this.transcript = false,
+    this.noSpeech = false,
+    this.lowConfidence = false,
+    this.firstWord = false,
+    this.utterance = false,
+    this.sessionError = false,
+    this.partial = false,
+  });
+
+  bool wakeWordDetected;
+  bool transcript;
+  bool noSpeech;
+  bool lowConfidence;
+  bool firstWord;
+  bool utterance;
+  bool sessionError;
+  bool partial;
+
+  @override
+  void decode(BincodeReader reader) {
+    wakeWordDetected = reader.readBool();
+    transcript = reader.readBool();
+    noSpeech = reader.readBool();
+    lowConfidence = reader.readBool();
+    firstWord = reader.readBool();
+    utterance = reader.readBool();
+    sessionError = reader.readBool();
+    partial = reader.readBool();
+  }
+
+  @override
+  void encode(BincodeWriter writer) {
+    writer.writeBool(wakeWordDetected);
+    writer.writeBool(transcript);
+    writer.writeBool(noSpeech);
+    writer.writeBool(lowConfidence);
+    writer.writeBool(firstWord);
+    writer.writeBool(utterance);
+    writer.writeBool(sessionError);
+    writer.writeBool(partial);
+  }
+}
+
+/// The settings the model decodes with.
+class TranscriptionConfig implements BincodeCodable {
+  TranscriptionConfig({
+    this.bestOf = 1,
+    this.beamSize = 0,
+    int? nThreads,
+    this.temperature = 0.0,
+  }) : nThreads = nThreads ?? Platform.numberOfProcessors;
+
+  TranscriptionConfig.empty()
+    : bestOf = 1,
+      beamSize = 0,
+      nThreads = 0,
+      temperature = 0.0;
+
+  /// The number of candidates sampled when decoding greedily (the most likely one is kept).
+  int bestOf;
+
+  /// The number of beams used for beam search (decodes greedily if `0`).
+  int beamSize;
+
+  /// The number of threads used for inference (uses `Whisper`'s default if `0`).
+  int nThreads;
+
+  /// The temperature used when sampling.
+  double temperature;
+
+  @override
+  void decode(BincodeReader reader) {
+    bestOf = reader.readU64();
+    beamSize = reader.readU64();
+    nThreads = reader.readU64();
+    temperature = reader.readF32();
+  }
+
+  @override
+  void encode(BincodeWriter writer) {
+    writer.writeU64(bestOf);
+    writer.writeU64(beamSize);
+    writer.writeU64(nThreads);
+    writer.writeF32(temperature);
+  }
+}
+
+/// The settings for how audio is captured and split into windows.
+class ListenConfig implements BincodeCodable {
+  ListenConfig({
+    this.maxWindowMs = 30000,
+    this.overlapMs = 500,
+    this.vadThreshold = 0.01,
+    this.shortPauseMs = 300,
+    this.endpointSilenceMs = 1000,
+    this.maxUtteranceMs = 0,
+    this.agcEnabled = false,
+    this.agcTargetRms = 0.1,
+    this.agcAttack = 0.5,
+    this.agcRelease = 0.05,
+    this.prerollMs = 300,
+  });
+
+  ListenConfig.empty()
+    : maxWindowMs = 0,
+      overlapMs = 0,
+      vadThreshold = 0.01,
+      shortPauseMs = 300,
+      endpointSilenceMs = 1000,
+      maxUtteranceMs = 0,
+      agcEnabled = false,
+      agcTargetRms = 0.1,
+      agcAttack = 0.5,
+      agcRelease = 0.05,
+      prerollMs = 0;
+
+  /// The maximum length of a single transcription window (in milliseconds).
+  int maxWindowMs;
+
+  /// The amount of audio carried over into the next window when a window is cut off
+  /// (in milliseconds).
+  int overlapMs;
+
+  /// The RMS energy above which audio is considered speech.
+  double vadThreshold;
+
+  /// The length of silence considered a short pause (in milliseconds).
+  int shortPauseMs;
+
+  /// The length of silence after speech that ends the utterance (in milliseconds).
+  int endpointSilenceMs;
+
+  /// The maximum length of an utterance in [AccumulationMode.dictation] (in milliseconds),
+  /// limited to [maxWindowMs] (which is used if this is `0`).
+  int maxUtteranceMs;
+
+  /// Determines if automatic gain control is applied to the audio.
+  bool agcEnabled;
+
+  /// The RMS level the automatic gain control adjusts speech towards.
+  double agcTargetRms;
+
+  /// How quickly (from `0` to `1`) the gain is lowered when the audio gets louder.
+  double agcAttack;
+
+  /// How quickly (from `0` to `1`) the gain is raised when the audio gets quieter.
+  double agcRelease;
+
+  /// The amount of audio (in milliseconds) from before a wake word's window that's included in
+  /// the command (disabled if `0`).
+  int prerollMs;
+
+  @override
+  void decode(BincodeReader reader) {
+    maxWindowMs = reader.readU64();
+    overlapMs = reader.readU64();
+    vadThreshold = reader.readF32();
+    shortPauseMs = reader.readU64();
+    endpointSilenceMs = reader.readU64();
+    maxUtteranceMs = reader.readU64();
+    agcEnabled = reader.readBool();
+    agcTargetRms = reader.readF32();
+    agcAttack = reader.readF32();
+    agcRelease = reader.readF32();
+    prerollMs = reader.readU64();
+  }
+
+  @override
+  void encode(BincodeWriter writer) {
+    writer.writeU64(maxWindowMs);
+    writer.writeU64(overlapMs);
+    writer.writeF32(vadThreshold);
+    writer.writeU64(shortPauseMs);
+    writer.writeU64(endpointSilenceMs);
+    writer.writeU64(maxUtteranceMs);
+    writer.writeBool(agcEnabled);
+    writer.writeF32(agcTargetRms);
+    writer.writeF32(agcAttack);
+    writer.writeF32(agcRelease);
+    writer.writeU64(prerollMs);
+  }
+}
+
+/// Overrides for [Context] fields that only apply to a single request.
+class ContextOverrides implements BincodeCodable {
+  ContextOverrides({this.language, this.temperature, this.nThreads});
+
+  ContextOverrides.empty();
+
+  String? language;
+  double? temperature;
+  int? nThreads;
+
+  @override
+  void decode(BincodeReader reader) {
+    language = reader.readOptionString();
+    temperature = reader.readOptionF32();
+    nThreads = reader.readOptionU64();
+  }
+
+  @override
+  void encode(BincodeWriter writer) {
+    writer.writeOptionString(language);
+    writer.writeOptionF32(temperature);
+    writer.writeOptionU64(nThreads);
+  }
+}
+
+/// The [ListeningState] received from the [nativeLib].
+class ListeningStateMessage implements BincodeCodable {
+  ListeningStateMessage.empty() : state = ListeningState.idle;
+
+  ListeningState state;
+
+  @override
+  void decode(BincodeReader reader) {
+    state = ListeningState.values[reader.readU32()];
+  }
+
+  @override
+  void encode(BincodeWriter writer) {
+    writer.writeU32(state.index);
+  }
+}
+
+/// The model path to be sent to the [nativeLib];
+class ModelPath implements BincodeCodable {
+  ModelPath({required this.path});
+
+  ModelPath.empty() : path = '';
+
+  String path;
+
+  @override
+  void decode(BincodeReader reader) {
+    path = reader.readString();
+  }
+
+  @override
+  void encode(BincodeWriter writer) {
+    writer.writeString(path);
+  }
+}
+
+/// The name of a wake word profile to be sent to the [nativeLib].
+class ProfileName implements BincodeCodable {
+  ProfileName({required this.name});
+
+  ProfileName.empty() : name = '';
+
+  String name;
+
+  @override
+  void decode(BincodeReader reader) {
+    name = reader.readString();
+  }
+
+  @override
+  void encode(BincodeWriter writer) {
+    writer.writeString(name);
+  }
+}
+
+/// An error message received from the [nativeLib].
+class ErrorMessage implements BincodeCodable {
+  ErrorMessage({required this.message});
+
+  ErrorMessage.empty() : message = '';
+
+  String message;
+
+  @override
+  void decode(BincodeReader reader) {
+    message = reader.readString();
+  }
+
+  @override
+  void encode(BincodeWriter writer) {
+    writer.writeString(message);
+  }
+}
+
+/// The kinds of events sent from the [nativeLib] (in the same order as the Rust `Event` enum).
+enum EventKind {
+  wakeWordDetected,
+  transcript,
+
+  /// No speech was recognized in a transcribed window (the event has no payload).
+  noSpeech,
+  lowConfidence,
+  firstWord,
+  utterance,
+
+  /// A session failed after its call returned (see [SessionError]).
+  sessionError,
+  partial;
+
+  /// Returns the kind of the encoded event.
+  static EventKind of(Uint8List bytes) {
+    final index = ByteData.sublistView(bytes).getUint32(0, Endian.little);
+    return EventKind.values[index];
+  }
+}
+
+/// Sent from the [nativeLib] when a wake word is detected, before the command is transcribed.
+class WakeWordDetected implements BincodeCodable {
+  WakeWordDetected({required this.word, required this.confidence});
+
+  WakeWordDetected.empty() : word = '', confidence = 0.0;
+
+  /// The wake word that was detected.
+  String word;
+
+  /// The mean confidence of the segments the wake word was detected in.
+  double confidence;
+
+  @override
+  void decode(BincodeReader reader) {
+    reader.readU32(); // EventKind.wakeWordDetected
+    word = reader.readString();
+    confidence = reader.readF32();
+  }
+
+  @override
+  void encode(BincodeWriter writer) {
+    writer.writeU32(EventKind.wakeWordDetected.index);
+    writer.writeString(word);
+    writer.writeF32(confidence);
+  }
+}
+
+/// Sent from the [nativeLib] with each transcript, if raw transcripts or languages are requested.
+class Transcript implements BincodeCodable {
+  Transcript({
+    required this.raw,
+    required this.clean,
+    this.language,
+    this.languageProbability = 0.0,
+    this.confidence = 0.0,
+    List<Alternative>? alternatives,
+  }) : alternatives = alternatives ?? [];
+
+  Transcript.empty()
+    : raw = '',
+      clean = '',
+      languageProbability = 0.0,
+      confidence = 0.0,
+      alternatives = [];
+
+  /// The unprocessed output of the model.
+  String raw;
+
+  /// The post-processed transcript.
+  String clean;
+
+  /// The detected language (if requested).
+  String? language;
+
+  /// The probability of the detected language.
+  double languageProbability;
+
+  /// The mean confidence of the transcribed segments.
+  double confidence;
+
+  /// The distinct candidate transcriptions, most likely first (only set if [Context.nBest] is
+  /// greater than `1`).
+  List<Alternative> alternatives;
+
+  @override
+  void decode(BincodeReader reader) {
+    reader.readU32(); // EventKind.transcript
+    raw = reader.readString();
+    clean = reader.readString();
+    language = reader.readOptionString();
+    languageProbability = reader.readF32();
+    confidence = reader.readF32();
+    alternatives = reader.readList(
+      () => Alternative.empty()..decode(reader),
+    );
+  }
+
+  @override
+  void encode(BincodeWriter writer) {
+    writer.writeU32(EventKind.transcript.index);
+    writer.writeString(raw);
+    writer.writeString(clean);
+    writer.writeOptionString(language);
+    writer.writeF32(languageProbability);
+    writer.writeF32(confidence);
+    writer.writeList(alternatives, (alternative) => alternative.encode(writer));
+  }
+}
+
+/// A candidate transcription of an utterance (see [Transcript.alternatives]).
+class Alternative implements BincodeCodable {
+  Alternative({required this.text, required this.score});
+
+  Alternative.empty() : text = '', score = 0.0;
+
+  /// The post-processed text.
+  String text;
+
+  /// The mean confidence of the post-processed segments.
+  double score;
+
+  @override
+  void decode(BincodeReader reader) {
+    text = reader.readString();
+    score = reader.readF32();
+  }
+
+  @override
+  void encode(BincodeWriter writer) {
+    writer.writeString(text);
+    writer.writeF32(score);
+  }
+}
+
+/// Sent from the [nativeLib] instead of a transcript whose confidence is below
+/// [Context.minConfidence].
+class LowConfidence implements BincodeCodable {
+  LowConfidence({required this.text, required this.confidence});
+
+  LowConfidence.empty() : text = '', confidence = 0.0;
+
+  /// The suppressed transcript.
+  String text;
+
+  /// The mean confidence of the transcribed segments.
+  double confidence;
+
+  @override
+  void decode(BincodeReader reader) {
+    reader.readU32(); // EventKind.lowConfidence
+    text = reader.readString();
+    confidence = reader.readF32();
+  }
+
+  @override
+  void encode(BincodeWriter writer) {
+    writer.writeU32(EventKind.lowConfidence.index);
+    writer.writeString(text);
+    writer.writeF32(confidence);
+  }
+}
+
+/// Sent from the [nativeLib] (once per utterance) as soon as the first word of an utterance is
+/// recognized.
+class FirstWord implements BincodeCodable {
+  FirstWord({required this.word});
+
+  FirstWord.empty() : word = '';
+
+  /// The first recognized word.
+  String word;
+
+  @override
+  void decode(BincodeReader reader) {
+    reader.readU32(); // EventKind.firstWord
+    word = reader.readString();
+  }
+
+  @override
+  void encode(BincodeWriter writer) {
+    writer.writeU32(EventKind.firstWord.index);
+    writer.writeString(word);
+  }
+}
+
+/// Sent from the [nativeLib] for each utterance transcribed in [AccumulationMode.dictation].
+class Utterance implements BincodeCodable {
+  Utterance({required this.text, required this.startMs, required this.endMs});
+
+  Utterance.empty() : text = '', startMs = 0, endMs = 0;
+
+  /// The post-processed transcript of the utterance.
+  String text;
+
+  /// The start of the utterance (in milliseconds since the session started).
+  int startMs;
+
+  /// The end of the utterance (in milliseconds since the session started).
+  int endMs;
+
+  @override
+  void decode(BincodeReader reader) {
+    reader.readU32(); // EventKind.utterance
+    text = reader.readString();
+    startMs = reader.readU64();
+    endMs = reader.readU64();
+  }
+
+  @override
+  void encode(BincodeWriter writer) {
+    writer.writeU32(EventKind.utterance.index);
+    writer.writeString(text);
+    writer.writeU64(startMs);
+    writer.writeU64(endMs);
+  }
+}
+
+/// Sent from the [nativeLib] when a session fails to start, or fails to process a window, after its
+/// call returned (so the failure can't be reported through its [MessageStatus]).
+class SessionError implements BincodeCodable {
+  SessionError({required this.status, required this.detail});
+
+  SessionError.empty() : status = MessageStatus.error, detail = '';
+
+  /// The status of the failed operation.
+  MessageStatus status;
+
+  /// What went wrong.
+  String detail;
+
+  @override
+  void decode(BincodeReader reader) {
+    reader.readU32(); // EventKind.sessionError
+    status = MessageStatus.values[reader.readU32()];
+    detail = reader.readString();
+  }
+
+  @override
+  void encode(BincodeWriter writer) {
+    writer.writeU32(EventKind.sessionError.index);
+    writer.writeU32(status.index);
+    writer.writeString(detail);
+  }
+}
+
+/// Sent from the [nativeLib] for each segment of a transcript as soon as it's decoded, when
+/// [Context.streaming] is set.
+class Partial implements BincodeCodable {
+  Partial({required this.text});
+
+  Partial.empty() : text = '';
+
+  /// The raw text of the segment (the post-processed transcript follows once the window is done).
+  String text;
+
+  @override
+  void decode(BincodeReader reader) {
+    reader.readU32(); // EventKind.partial
+    text = reader.readString();
+  }
+
+  @override
+  void encode(BincodeWriter writer) {
+    writer.writeU32(EventKind.partial.index);
+    writer.writeString(text);
+  }
+}
+
+/// The wake words to be sent to the [nativeLib].
+class WakeWords implements BincodeCodable {
+  WakeWords({required this.wakeWords});
+
+  WakeWords.empty() : wakeWords = [];
+
+  List<String> wakeWords;
+
+  @override
+  void decode(BincodeReader reader) {
+    wakeWords = reader.readList(reader.readString);
+  }
+
+  @override
+  void encode(BincodeWriter writer) {
+    writer.writeList(wakeWords, writer.writeString);
+  }
+}
+
+/// The codes of the languages supported by the model loaded by the [nativeLib].
+class SupportedLanguages implements BincodeCodable {
+  SupportedLanguages.empty() : languages = [];
+
+  List<String> languages;
+
+  @override
+  void decode(BincodeReader reader) {
+    languages = reader.readList(reader.readString);
+  }
+
+  @override
+  void encode(BincodeWriter writer) {
+    writer.writeList(languages, writer.writeString);
+  }
+}
+
+/// The paths of the WAV files passed to the [nativeLib].
+class WavPaths implements BincodeCodable {
+  WavPaths({required this.wavPaths});
+
+  WavPaths.empty() : wavPaths = [];
+
+  List<String> wavPaths;
+
+  @override
+  void decode(BincodeReader reader) {
+    wavPaths = reader.readList(reader.readString);
+  }
+
+  @override
+  void encode(BincodeWriter writer) {
+    writer.writeList(wavPaths, writer.writeString);
+  }
+}
 
-/// The context used for the [nativeLib].
-class Context implements BincodeCodable {
-  Context({required this.modelPath, required this.wakeWords});
+/// The estimated memory usage of the model loaded by the [nativeLib].
+class ModelMemoryEstimate implements BincodeCodable {
+  ModelMemoryEstimate.empty() : modelBytes = 0, workingBytes = 0;
+
+  /// The size of the model weights (in bytes).
+  int modelBytes;
+
+  /// The estimated size of the buffers used during inference (in bytes).
+  int workingBytes;
+
+  /// The total estimated memory usage (in bytes).
+  int get totalBytes => modelBytes + workingBytes;
+
+  @override
+  void decode(BincodeReader reader) {
+    modelBytes = reader.readU64();
+    workingBytes = reader.readU64();
+  }
+
+  @override
+  void encode(BincodeWriter writer) {
+    writer.writeU64(modelBytes);
+    writer.writeU64(workingBytes);
+  }
+}
 
-  Context.empty() : modelPath = '', wakeWords = [];
+/// Diagnostic information about the [nativeLib], used for bug reports.
+class Diagnostics implements BincodeCodable {
+  Diagnostics.empty()
+    : crateVersion = '',
+      whisperCppVersion = '',
+      modelPath = '',
+      gpuEnabled = false,
+      inputDevice = '',
+      sampleRate = 0,
+      detectedRate = 0,
+      errorCount = 0;
 
-  /// The path to the `Whisper` model.
+  String crateVersion;
+  String whisperCppVersion;
   String modelPath;
+  bool gpuEnabled;
+  String inputDevice;
+  int sampleRate;
 
-  /// The list of wake words to listen for/wake to.
-  List<String> wakeWords;
+  /// The sample rate the microphone actually delivers (`0` if unknown).
+  int detectedRate;
+  int errorCount;
 
   @override
   void decode(BincodeReader reader) {
+    crateVersion = reader.readString();
+    whisperCppVersion = reader.readString();
     modelPath = reader.readString();
-    wakeWords = reader.readList(reader.readString);
+    gpuEnabled = reader.readBool();
+    inputDevice = reader.readString();
+    sampleRate = reader.readU32();
+    detectedRate = reader.readU32();
+    errorCount = reader.readU64();
   }
 
   @override
   void encode(BincodeWriter writer) {
+    writer.writeString(crateVersion);
+    writer.writeString(whisperCppVersion);
     writer.writeString(modelPath);
-    writer.writeList(wakeWords, writer.writeString);
+    writer.writeBool(gpuEnabled);
+    writer.writeString(inputDevice);
+    writer.writeU32(sampleRate);
+    writer.writeU32(detectedRate);
+    writer.writeU64(errorCount);
   }
 }
 
-/// The model path to be sent to the [nativeLib];
-class ModelPath implements BincodeCodable {
-  ModelPath({required this.path});
+/// The kinds of events recorded in the event log (in the same order as the Rust `LifecycleEvent`
+/// enum).
+enum LifecycleEventKind {
+  sessionStarted,
+  wakeWordDetected,
+  transcribed,
+  noSpeech,
+  lowConfidence,
+  sessionStopped,
+}
 
-  ModelPath.empty() : path = '';
+/// An event recorded in the event log of the [nativeLib].
+class EventLogEntry implements BincodeCodable {
+  EventLogEntry.empty()
+    : timestampMs = 0,
+      kind = LifecycleEventKind.sessionStarted;
 
-  String path;
+  /// When the event happened (in milliseconds since the Unix epoch).
+  int timestampMs;
+
+  LifecycleEventKind kind;
+
+  /// The detected wake word (only set for [LifecycleEventKind.wakeWordDetected]).
+  String? word;
+
+  /// The transcribed text (only set for [LifecycleEventKind.transcribed] and
+  /// [LifecycleEventKind.lowConfidence]).
+  String? text;
+
+  /// The confidence of the detection (only set for [LifecycleEventKind.wakeWordDetected] and
+  /// [LifecycleEventKind.lowConfidence]).
+  double? confidence;
 
   @override
   void decode(BincodeReader reader) {
-    path = reader.readString();
+    timestampMs = reader.readU64();
+    kind = LifecycleEventKind.values[reader.readU32()];
+    switch (kind) {
+      case LifecycleEventKind.wakeWordDetected:
+        word = reader.readString();
+        confidence = reader.readF32();
+      case LifecycleEventKind.transcribed:
+        text = reader.readString();
+      case LifecycleEventKind.lowConfidence:
+        text = reader.readString();
+        confidence = reader.readF32();
+      case LifecycleEventKind.sessionStarted ||
+          LifecycleEventKind.noSpeech ||
+          LifecycleEventKind.sessionStopped:
+        break;
+    }
   }
 
   @override
   void encode(BincodeWriter writer) {
-    writer.writeString(path);
+    writer.writeU64(timestampMs);
+    writer.writeU32(kind.index);
+    switch (kind) {
+      case LifecycleEventKind.wakeWordDetected:
+        writer.writeString(word!);
+        writer.writeF32(confidence!);
+      case LifecycleEventKind.transcribed:
+        writer.writeString(text!);
+      case LifecycleEventKind.lowConfidence:
+        writer.writeString(text!);
+        writer.writeF32(confidence!);
+      case LifecycleEventKind.sessionStarted ||
+          LifecycleEventKind.noSpeech ||
+          LifecycleEventKind.sessionStopped:
+        break;
+    }
   }
 }
 
-/// The wake words to be sent to the [nativeLib].
-class WakeWords implements BincodeCodable {
-  WakeWords({required this.wakeWords});
+/// The event log of the [nativeLib] (oldest first).
+class EventLog implements BincodeCodable {
+  EventLog.empty() : entries = [];
 
-  WakeWords.empty() : wakeWords = [];
+  List<EventLogEntry> entries;
 
-  List<String> wakeWords;
+  @override
+  void decode(BincodeReader reader) {
+    entries = reader.readList(
+      () => EventLogEntry.empty()..decode(reader),
+    );
+  }
+
+  @override
+  void encode(BincodeWriter writer) {
+    writer.writeList(entries, (entry) => entry.encode(writer));
+  }
+}
+
+/// A transcribed word, with the time it's spoken (e.g. for karaoke-style highlighting).
+class WordTiming implements BincodeCodable {
+  WordTiming({required this.word, required this.startMs, required this.endMs});
+
+  WordTiming.empty() : word = '', startMs = 0, endMs = 0;
+
+  /// The word, with any punctuation that follows it.
+  String word;
+
+  /// The start of the word (in milliseconds).
+  int startMs;
+
+  /// The end of the word (in milliseconds).
+  int endMs;
 
   @override
   void decode(BincodeReader reader) {
-    wakeWords = reader.readList(reader.readString);
+    word = reader.readString();
+    startMs = reader.readU64();
+    endMs = reader.readU64();
   }
 
   @override
   void encode(BincodeWriter writer) {
-    writer.writeList(wakeWords, writer.writeString);
+    writer.writeString(word);
+    writer.writeU64(startMs);
+    writer.writeU64(endMs);
+  }
+}
+
+/// The timed words of a transcript, in the order they're spoken.
+class WordTimings implements BincodeCodable {
+  WordTimings.empty() : words = [];
+
+  List<WordTiming> words;
+
+  @override
+  void decode(BincodeReader reader) {
+    words = reader.readList(() => WordTiming.empty()..decode(reader));
+  }
+
+  @override
+  void encode(BincodeWriter writer) {
+    writer.writeList(words, (word) => word.encode(writer));
   }
 }
 
@@ -128,16 +1332,253 @@ typedef _InitDartPortFn = void Function(int port);
 // fn transcribe_speech(
 //   ctx: *mut ffi::c_void,
 //   ctx_len: usize,
-//   listen_duration_ms: usize
-// )
+//   listen_duration_ms: usize,
+//   overrides: *mut ffi::c_void,
+//   overrides_len: usize,
+// ) -> MessageStatus
 typedef _TranscribeSpeechNativeFn =
-    Void Function(Pointer<Void> ctx, UintPtr ctxLen, UintPtr listenDurationMs);
+    Uint8 Function(
+      Pointer<Void> ctx,
+      UintPtr ctxLen,
+      UintPtr listenDurationMs,
+      Pointer<Void> overrides,
+      UintPtr overridesLen,
+    );
 typedef _TranscribeSpeechFn =
-    void Function(Pointer<Void> ctx, int ctxLen, int listenDurationMs);
+    int Function(
+      Pointer<Void> ctx,
+      int ctxLen,
+      int listenDurationMs,
+      Pointer<Void> overrides,
+      int overridesLen,
+    );
+
+// pub fn stop_mic() -> MessageStatus
+typedef _StopMicNativeFn = Uint8 Function();
+typedef _StopMicFn = int Function();
+
+// fn get_audio_duration_ms(num_samples: usize, sample_rate: usize) -> u64
+typedef _GetAudioDurationMsNativeFn =
+    Uint64 Function(UintPtr numSamples, UintPtr sampleRate);
+typedef _GetAudioDurationMsFn = int Function(int numSamples, int sampleRate);
+
+// fn model_memory_estimate(estimate_len_out: *mut usize) -> *mut ffi::c_void
+typedef _ModelMemoryEstimateNativeFn =
+    Pointer<Void> Function(Pointer<UintPtr> estimateLenOut);
+typedef _ModelMemoryEstimateFn =
+    Pointer<Void> Function(Pointer<UintPtr> estimateLenOut);
+
+// fn dump_diagnostics(diagnostics_len_out: *mut usize) -> *mut ffi::c_void
+typedef _DumpDiagnosticsNativeFn =
+    Pointer<Void> Function(Pointer<UintPtr> diagnosticsLenOut);
+typedef _DumpDiagnosticsFn =
+    Pointer<Void> Function(Pointer<UintPtr> diagnosticsLenOut);
+
+// fn last_error_message(msg_len_out: *mut usize) -> *mut ffi::c_void
+typedef _LastErrorMessageNativeFn =
+    Pointer<Void> Function(Pointer<UintPtr> msgLenOut);
+typedef _LastErrorMessageFn = Pointer<Void> Function(Pointer<UintPtr> msgLenOut);
+
+// fn replay_file(
+//   ctx: *mut ffi::c_void,
+//   ctx_len: usize,
+//   wav_path: *mut ffi::c_void,
+//   wav_path_len: usize,
+//   overrides: *mut ffi::c_void,
+//   overrides_len: usize,
+// ) -> MessageStatus
+typedef _ReplayFileNativeFn =
+    Uint8 Function(
+      Pointer<Void> ctx,
+      UintPtr ctxLen,
+      Pointer<Void> wavPath,
+      UintPtr wavPathLen,
+      Pointer<Void> overrides,
+      UintPtr overridesLen,
+    );
+typedef _ReplayFileFn =
+    int Function(
+      Pointer<Void> ctx,
+      int ctxLen,
+      Pointer<Void> wavPath,
+      int wavPathLen,
+      Pointer<Void> overrides,
+      int overridesLen,
+    );
+
+// fn set_wake_words(wake_words: *mut ffi::c_void, wake_words_len: usize) -> MessageStatus
+typedef _SetWakeWordsNativeFn =
+    Uint8 Function(Pointer<Void> wakeWords, UintPtr wakeWordsLen);
+typedef _SetWakeWordsFn = int Function(Pointer<Void> wakeWords, int wakeWordsLen);
+
+// fn pause_listening() -> MessageStatus
+typedef _PauseListeningNativeFn = Uint8 Function();
+typedef _PauseListeningFn = int Function();
+
+// fn resume_listening() -> MessageStatus
+typedef _ResumeListeningNativeFn = Uint8 Function();
+typedef _ResumeListeningFn = int Function();
+
+// fn get_transcript_slice(start: usize, len: usize, slice_len_out: *mut usize) -> *mut ffi::c_void
+typedef _GetTranscriptSliceNativeFn =
+    Pointer<Void> Function(
+      UintPtr start,
+      UintPtr len,
+      Pointer<UintPtr> sliceLenOut,
+    );
+typedef _GetTranscriptSliceFn =
+    Pointer<Void> Function(int start, int len, Pointer<UintPtr> sliceLenOut);
+
+// fn load_model(ctx: *mut ffi::c_void, ctx_len: usize, force_reload: bool) -> MessageStatus
+typedef _LoadModelNativeFn =
+    Uint8 Function(Pointer<Void> ctx, UintPtr ctxLen, Bool forceReload);
+typedef _LoadModelFn =
+    int Function(Pointer<Void> ctx, int ctxLen, bool forceReload);
+
+// fn transcribe_files(
+//   ctx: *mut ffi::c_void,
+//   ctx_len: usize,
+//   wav_paths: *mut ffi::c_void,
+//   wav_paths_len: usize,
+//   overrides: *mut ffi::c_void,
+//   overrides_len: usize,
+// ) -> MessageStatus
+typedef _TranscribeFilesNativeFn =
+    Uint8 Function(
+      Pointer<Void> ctx,
+      UintPtr ctxLen,
+      Pointer<Void> wavPaths,
+      UintPtr wavPathsLen,
+      Pointer<Void> overrides,
+      UintPtr overridesLen,
+    );
+typedef _TranscribeFilesFn =
+    int Function(
+      Pointer<Void> ctx,
+      int ctxLen,
+      Pointer<Void> wavPaths,
+      int wavPathsLen,
+      Pointer<Void> overrides,
+      int overridesLen,
+    );
+
+// fn supported_languages(languages_len_out: *mut usize) -> *mut ffi::c_void
+typedef _SupportedLanguagesNativeFn =
+    Pointer<Void> Function(Pointer<UintPtr> languagesLenOut);
+typedef _SupportedLanguagesFn =
+    Pointer<Void> Function(Pointer<UintPtr> languagesLenOut);
+
+// fn update_listen_config(cfg: *mut ffi::c_void, cfg_len: usize) -> MessageStatus
+typedef _UpdateListenConfigNativeFn =
+    Uint8 Function(Pointer<Void> cfg, UintPtr cfgLen);
+typedef _UpdateListenConfigFn = int Function(Pointer<Void> cfg, int cfgLen);
 
-// pub fn stop_mic()
-typedef _StopMicNativeFn = Void Function();
-typedef _StopMicFn = void Function();
+// fn add_wake_word_profile(name: *mut ffi::c_void, name_len: usize, wake_words: *mut ffi::c_void, wake_words_len: usize) -> MessageStatus
+typedef _AddWakeWordProfileNativeFn =
+    Uint8 Function(
+      Pointer<Void> name,
+      UintPtr nameLen,
+      Pointer<Void> wakeWords,
+      UintPtr wakeWordsLen,
+    );
+typedef _AddWakeWordProfileFn =
+    int Function(
+      Pointer<Void> name,
+      int nameLen,
+      Pointer<Void> wakeWords,
+      int wakeWordsLen,
+    );
+
+// fn activate_profile(name: *mut ffi::c_void, name_len: usize) -> MessageStatus
+typedef _ActivateProfileNativeFn =
+    Uint8 Function(Pointer<Void> name, UintPtr nameLen);
+typedef _ActivateProfileFn = int Function(Pointer<Void> name, int nameLen);
+
+// fn get_event_log(log_len_out: *mut usize) -> *mut ffi::c_void
+typedef _GetEventLogNativeFn = Pointer<Void> Function(Pointer<UintPtr> logLenOut);
+typedef _GetEventLogFn = Pointer<Void> Function(Pointer<UintPtr> logLenOut);
+
+// fn listening_state(state_len_out: *mut usize) -> *mut ffi::c_void
+typedef _ListeningStateNativeFn =
+    Pointer<Void> Function(Pointer<UintPtr> stateLenOut);
+typedef _ListeningStateFn = Pointer<Void> Function(Pointer<UintPtr> stateLenOut);
+
+// fn reset_session_state() -> MessageStatus
+typedef _ResetSessionStateNativeFn = Uint8 Function();
+typedef _ResetSessionStateFn = int Function();
+
+// fn set_max_payload_size(max_bytes: usize) -> MessageStatus
+typedef _SetMaxPayloadSizeNativeFn = Uint8 Function(UintPtr maxBytes);
+typedef _SetMaxPayloadSizeFn = int Function(int maxBytes);
+
+// fn transcribe_words(
+//   ctx: *mut ffi::c_void,
+//   ctx_len: usize,
+//   wav_path: *mut ffi::c_void,
+//   wav_path_len: usize,
+//   overrides: *mut ffi::c_void,
+//   overrides_len: usize,
+//   words_len_out: *mut usize,
+// ) -> *mut ffi::c_void
+typedef _TranscribeWordsNativeFn =
+    Pointer<Void> Function(
+      Pointer<Void> ctx,
+      UintPtr ctxLen,
+      Pointer<Void> wavPath,
+      UintPtr wavPathLen,
+      Pointer<Void> overrides,
+      UintPtr overridesLen,
+      Pointer<UintPtr> wordsLenOut,
+    );
+typedef _TranscribeWordsFn =
+    Pointer<Void> Function(
+      Pointer<Void> ctx,
+      int ctxLen,
+      Pointer<Void> wavPath,
+      int wavPathLen,
+      Pointer<Void> overrides,
+      int overridesLen,
+      Pointer<UintPtr> wordsLenOut,
+    );
+
+// fn unload_model(path: *mut ffi::c_void, path_len: usize) -> MessageStatus
+typedef _UnloadModelNativeFn =
+    Uint8 Function(Pointer<Void> path, UintPtr pathLen);
+typedef _UnloadModelFn = int Function(Pointer<Void> path, int pathLen);
+
+// fn transcribe_file(
+//   ctx: *mut ffi::c_void,
+//   ctx_len: usize,
+//   wav_path: *mut ffi::c_void,
+//   wav_path_len: usize,
+//   overrides: *mut ffi::c_void,
+//   overrides_len: usize,
+//   transcript_len_out: *mut usize,
+// ) -> *mut ffi::c_void
+typedef _TranscribeFileNativeFn =
+    Pointer<Void> Function(
+      Pointer<Void> ctx,
+      UintPtr ctxLen,
+      Pointer<Void> wavPath,
+      UintPtr wavPathLen,
+      Pointer<Void> overrides,
+      UintPtr overridesLen,
+      Pointer<UintPtr> transcriptLenOut,
+    );
+typedef _TranscribeFileFn =
+    Pointer<Void> Function(
+      Pointer<Void> ctx,
+      int ctxLen,
+      Pointer<Void> wavPath,
+      int wavPathLen,
+      Pointer<Void> overrides,
+      int overridesLen,
+      Pointer<UintPtr> transcriptLenOut,
+    );
+
+// fn ping_dart(nonce: i64) -> MessageStatus
+typedef _PingDartNativeFn = Uint8 Function(Int64 nonce);
+typedef _PingDartFn = int Function(int nonce);
 
 // ==================================================================
 // Function Bindings
@@ -163,7 +1604,8 @@ final freeRustPtr = nativeLib
 /// @param wakeWordsLen The length of the wake words (in bytes).
 /// @param ctxLenOut The length of the returned context (in bytes).
 ///
-/// @returns A pointer to the initalized `Context` object.
+/// @returns A pointer to the initalized `Context` object, or a null pointer if the model path
+/// doesn't exist or isn't readable.
 ///
 /// # Note
 /// The returned pointer must be deallocated using the [freeRustPtr] function.
@@ -187,12 +1629,323 @@ final initDartPort = nativeLib
 /// @param ctx The current context (must be initalized with [initContext]).
 /// @param ctxLen The length of the context (in bytes).
 /// @param listenDurationMs The number of milliseconds to listen to the microphone.
+/// @param overrides The encoded `ContextOverrides` for this call (may be a null pointer).
+/// @param overridesLen The length of the overrides (in bytes).
+///
+/// @returns The index of the resulting [MessageStatus] ([MessageStatus.alreadyListening] or
+/// [MessageStatus.queued] if a session is already running, depending on [Context.overlapPolicy]).
 final transcribeSpeech = nativeLib
     .lookupFunction<_TranscribeSpeechNativeFn, _TranscribeSpeechFn>(
       'transcribe_speech',
     );
 
-/// Stops the microphone.
+/// Stops the microphone, waiting for the native processing thread to exit.
+///
+/// @returns The index of the resulting [MessageStatus] ([MessageStatus.error] if the thread
+/// didn't exit in time).
 final stopMic = nativeLib.lookupFunction<_StopMicNativeFn, _StopMicFn>(
   'stop_mic',
 );
+
+/// Computes the duration of the given number of audio samples.
+///
+/// @param numSamples The number of audio samples.
+/// @param sampleRate The sample rate the samples were captured at (in Hz).
+///
+/// @returns The duration of the samples (in milliseconds).
+final getAudioDurationMs = nativeLib
+    .lookupFunction<_GetAudioDurationMsNativeFn, _GetAudioDurationMsFn>(
+      'get_audio_duration_ms',
+    );
+
+/// Returns the estimated memory usage of the loaded model.
+///
+/// @param estimateLenOut The length of the returned estimate (in bytes).
+///
+/// @returns A pointer to the encoded `ModelMemoryEstimate`, or a null pointer if no model has
+/// been loaded.
+///
+/// # Note
+/// The returned pointer must be deallocated using the [freeRustPtr] function.
+final modelMemoryEstimate = nativeLib
+    .lookupFunction<_ModelMemoryEstimateNativeFn, _ModelMemoryEstimateFn>(
+      'model_memory_estimate',
+    );
+
+/// Returns diagnostic information about the native library (for bug reports).
+///
+/// @param diagnosticsLenOut The length of the returned diagnostics (in bytes).
+///
+/// @returns A pointer to the encoded `Diagnostics`.
+///
+/// # Note
+/// The returned pointer must be deallocated using the [freeRustPtr] function.
+final dumpDiagnostics = nativeLib
+    .lookupFunction<_DumpDiagnosticsNativeFn, _DumpDiagnosticsFn>(
+      'dump_diagnostics',
+    );
+
+/// Sends the nonce back through the Dart port, to verify that the port works.
+///
+/// @param nonce The value to send back.
+///
+/// @returns The index of the resulting [MessageStatus].
+final pingDart = nativeLib.lookupFunction<_PingDartNativeFn, _PingDartFn>(
+  'ping_dart',
+);
+
+/// Returns the error message of the most recent failed call (clearing it).
+///
+/// @param msgLenOut The length of the returned message (in bytes).
+///
+/// @returns A pointer to the encoded message, or a null pointer if the most recent call succeeded.
+///
+/// # Note
+/// The returned pointer must be deallocated using the [freeRustPtr] function.
+final lastErrorMessage = nativeLib
+    .lookupFunction<_LastErrorMessageNativeFn, _LastErrorMessageFn>(
+      'last_error_message',
+    );
+
+/// Feeds a WAV file through the transcription pipeline, as if it were microphone input.
+///
+/// @param ctx The current context (must be initalized with [initContext]).
+/// @param ctxLen The length of the context (in bytes).
+/// @param wavPath The encoded path of the WAV file.
+/// @param wavPathLen The length of the WAV path (in bytes).
+/// @param overrides The encoded `ContextOverrides` for this call (may be a null pointer).
+/// @param overridesLen The length of the overrides (in bytes).
+///
+/// @returns The index of the resulting [MessageStatus] ([MessageStatus.alreadyListening] or
+/// [MessageStatus.queued] if a session is already running, depending on [Context.overlapPolicy]).
+final replayFile = nativeLib.lookupFunction<_ReplayFileNativeFn, _ReplayFileFn>(
+  'replay_file',
+);
+
+/// Updates the wake words of the running session.
+///
+/// An empty list stops the session from waiting for wake words (as if it ignored them) until wake
+/// words are set again.
+///
+/// @param wakeWords The encoded [WakeWords].
+/// @param wakeWordsLen The length of the wake words (in bytes).
+///
+/// @returns The index of the resulting [MessageStatus].
+final setWakeWords = nativeLib
+    .lookupFunction<_SetWakeWordsNativeFn, _SetWakeWordsFn>('set_wake_words');
+
+/// Pauses the microphone, keeping the model and accumulated audio around.
+///
+/// @returns The index of the resulting [MessageStatus].
+final pauseListening = nativeLib
+    .lookupFunction<_PauseListeningNativeFn, _PauseListeningFn>(
+      'pause_listening',
+    );
+
+/// Resumes a microphone paused with [pauseListening].
+///
+/// @returns The index of the resulting [MessageStatus].
+final resumeListening = nativeLib
+    .lookupFunction<_ResumeListeningNativeFn, _ResumeListeningFn>(
+      'resume_listening',
+    );
+
+/// Returns a slice of the transcript history (in characters).
+///
+/// @param start The index of the first character.
+/// @param len The maximum number of characters.
+/// @param sliceLenOut The length of the returned slice (in bytes).
+///
+/// @returns A pointer to the encoded slice (empty if [start] is out of range).
+///
+/// # Note
+/// The returned pointer must be deallocated using the [freeRustPtr] function.
+final getTranscriptSlice = nativeLib
+    .lookupFunction<_GetTranscriptSliceNativeFn, _GetTranscriptSliceFn>(
+      'get_transcript_slice',
+    );
+
+/// Loads the model from the context's model path ahead of time.
+///
+/// @param ctx The current context (must be initalized with [initContext]).
+/// @param ctxLen The length of the context (in bytes).
+/// @param forceReload Determines if the model is reloaded even if it was loaded from the same path.
+///
+/// @returns The index of the resulting [MessageStatus] ([MessageStatus.alreadyLoaded] if loading
+/// was skipped).
+final loadModel = nativeLib.lookupFunction<_LoadModelNativeFn, _LoadModelFn>(
+  'load_model',
+);
+
+/// Transcribes several WAV files concurrently, sending each transcript (in order) once they're
+/// all done (or a single transcript of the mixed files, see [Context.sourceMode]).
+///
+/// @param ctx The current context (must be initalized with [initContext]).
+/// @param ctxLen The length of the context (in bytes).
+/// @param wavPaths The encoded [WavPaths].
+/// @param wavPathsLen The length of the WAV paths (in bytes).
+/// @param overrides The encoded `ContextOverrides` for this call (may be a null pointer).
+/// @param overridesLen The length of the overrides (in bytes).
+///
+/// @returns The index of the resulting [MessageStatus] ([MessageStatus.error] if there are more
+/// than [Context.maxAudioSources] paths).
+final transcribeFiles = nativeLib
+    .lookupFunction<_TranscribeFilesNativeFn, _TranscribeFilesFn>(
+      'transcribe_files',
+    );
+
+/// Returns the codes of the languages the loaded model supports (only `en` for English-only
+/// models).
+///
+/// @param languagesLenOut The length of the returned languages (in bytes).
+///
+/// @returns A pointer to the encoded [SupportedLanguages], or a null pointer if no model has been
+/// loaded.
+///
+/// # Note
+/// The returned pointer must be deallocated using the [freeRustPtr] function.
+final supportedLanguages = nativeLib
+    .lookupFunction<_SupportedLanguagesNativeFn, _SupportedLanguagesFn>(
+      'supported_languages',
+    );
+
+/// Applies a new [ListenConfig] to the running session, without restarting the microphone or
+/// reloading the model.
+///
+/// @param cfg The encoded [ListenConfig].
+/// @param cfgLen The length of the config (in bytes).
+///
+/// @returns The index of the resulting [MessageStatus] ([MessageStatus.error] if not listening or
+/// the config is invalid).
+final updateListenConfig = nativeLib
+    .lookupFunction<_UpdateListenConfigNativeFn, _UpdateListenConfigFn>(
+      'update_listen_config',
+    );
+
+/// Adds (or replaces) the wake word profile with the given name.
+///
+/// @param name The encoded [ProfileName].
+/// @param nameLen The length of the name (in bytes).
+/// @param wakeWords The encoded [WakeWords] of the profile.
+/// @param wakeWordsLen The length of the wake words (in bytes).
+///
+/// @returns The index of the resulting [MessageStatus].
+final addWakeWordProfile = nativeLib
+    .lookupFunction<_AddWakeWordProfileNativeFn, _AddWakeWordProfileFn>(
+      'add_wake_word_profile',
+    );
+
+/// Activates the wake word profile with the given name, whose wake words are used instead of the
+/// context's (including by the running session).
+///
+/// @param name The encoded [ProfileName].
+/// @param nameLen The length of the name (in bytes).
+///
+/// @returns The index of the resulting [MessageStatus] ([MessageStatus.error] if no profile with
+/// the name was added).
+final activateProfile = nativeLib
+    .lookupFunction<_ActivateProfileNativeFn, _ActivateProfileFn>(
+      'activate_profile',
+    );
+
+/// Returns the most recent session events (oldest first), with when they happened.
+///
+/// @param logLenOut The length of the returned log (in bytes).
+///
+/// @returns A pointer to the encoded [EventLog].
+///
+/// # Note
+/// The returned pointer must be deallocated using the [freeRustPtr] function.
+final getEventLog = nativeLib
+    .lookupFunction<_GetEventLogNativeFn, _GetEventLogFn>('get_event_log');
+
+/// Returns the current state of the listening pipeline.
+///
+/// @param stateLenOut The length of the returned state (in bytes).
+///
+/// @returns A pointer to the encoded [ListeningStateMessage].
+///
+/// # Note
+/// The returned pointer must be deallocated using the [freeRustPtr] function.
+final listeningState = nativeLib
+    .lookupFunction<_ListeningStateNativeFn, _ListeningStateFn>(
+      'listening_state',
+    );
+
+/// Clears the error count, the transcript history and the event log at once, so a fresh session
+/// can be measured.
+///
+/// @returns The index of the resulting [MessageStatus].
+final resetSessionState = nativeLib
+    .lookupFunction<_ResetSessionStateNativeFn, _ResetSessionStateFn>(
+      'reset_session_state',
+    );
+
+/// Sets the maximum size (in bytes) of the buffers returned by the [nativeLib]; larger buffers are
+/// refused (64 MiB by default).
+///
+/// @param maxBytes The maximum size (must be greater than `0`).
+///
+/// @returns The index of the resulting [MessageStatus].
+final setMaxPayloadSize = nativeLib
+    .lookupFunction<_SetMaxPayloadSizeNativeFn, _SetMaxPayloadSizeFn>(
+      'set_max_payload_size',
+    );
+
+/// Transcribes a WAV file into words, with the time each one is spoken (e.g. for karaoke-style
+/// highlighting).
+///
+/// The loaded model is used (see [loadModel]), so this can't be called while listening.
+///
+/// @param ctx The current context (must be initalized with [initContext]).
+/// @param ctxLen The length of the context (in bytes).
+/// @param wavPath The encoded path of the WAV file.
+/// @param wavPathLen The length of the WAV path (in bytes).
+/// @param overrides The encoded `ContextOverrides` for this call (may be a null pointer).
+/// @param overridesLen The length of the overrides (in bytes).
+/// @param wordsLenOut The length of the returned words (in bytes).
+///
+/// @returns A pointer to the encoded [WordTimings], or a null pointer if the file couldn't be
+/// transcribed (see [lastErrorMessage]).
+///
+/// # Note
+/// The returned pointer must be deallocated using the [freeRustPtr] function.
+final transcribeWords = nativeLib
+    .lookupFunction<_TranscribeWordsNativeFn, _TranscribeWordsFn>(
+      'transcribe_words',
+    );
+
+/// Unloads the model loaded from the path, freeing its memory (on the GPU or CPU).
+///
+/// Unloading a model that isn't loaded does nothing. If a session is using the model, its memory
+/// is freed once the session stops.
+///
+/// @param path The encoded path the model was loaded from (resolved against [Context.assetDir]).
+/// @param pathLen The length of the path (in bytes).
+///
+/// @returns The index of the resulting [MessageStatus].
+final unloadModel = nativeLib
+    .lookupFunction<_UnloadModelNativeFn, _UnloadModelFn>('unload_model');
+
+/// Transcribes a WAV file, returning its transcript instead of sending it through the port (e.g.
+/// for tests or batch jobs).
+///
+/// The loaded model is used (see [loadModel]), so this can't be called while listening.
+///
+/// @param ctx The current context (must be initalized with [initContext]).
+/// @param ctxLen The length of the context (in bytes).
+/// @param wavPath The encoded path of the WAV file.
+/// @param wavPathLen The length of the WAV path (in bytes).
+/// @param overrides The encoded `ContextOverrides` for this call (may be a null pointer).
+/// @param overridesLen The length of the overrides (in bytes).
+/// @param transcriptLenOut The length of the returned transcript (in bytes).
+///
+/// @returns A pointer to the encoded [Transcript], or a null pointer if the file couldn't be
+/// transcribed (see [lastErrorMessage]).
+///
+/// # Note
+/// The returned pointer must be deallocated using the [freeRustPtr] function.
+final transcribeFile = nativeLib
+    .lookupFunction<_TranscribeFileNativeFn, _TranscribeFileFn>(
+      'transcribe_file',
+    );
@@ -3,7 +3,6 @@ library;
 
 import 'dart:ffi';
 
-import 'package:d_bincode/d_bincode.dart';
 import 'package:ffi/ffi.dart';
 import 'package:virgil/main.dart';
 import 'package:virgil/native.dart';
@@ -13,13 +12,15 @@ import 'package:virgil/native.dart';
 final _logger = logger;
 
 /// Initalizes the Rust context.
+///
+/// Throws if the model at [modelPath] doesn't exist or can't be read.
 Future<Context> initalizeContext({
   required String modelPath,
   required List<String> wakeWords,
 }) async {
   // Encode arguments
-  final modelPathEncoded = BincodeWriter.encode(ModelPath(path: modelPath));
-  final wakeWordsEncoded = BincodeWriter.encode(
+  final modelPathEncoded = encodeMessage(ModelPath(path: modelPath));
+  final wakeWordsEncoded = encodeMessage(
     WakeWords(wakeWords: wakeWords),
   );
 
@@ -43,12 +44,16 @@ Future<Context> initalizeContext({
     wakeWordsBytes.length,
     ctxLenOutPtr,
   );
+  if (ctxPtr == nullptr) {
+    _freeAllocs(dartAllocs: dartAllocs, nativeAllocs: {});
+    throw getLastErrorMessage() ?? 'Invalid model path: $modelPath';
+  }
   final nativeAllocs = {(ctxPtr, ctxLenOutPtr.value)};
 
   // Decode and return response
   final ctxBytesPtr = ctxPtr.cast<Uint8>();
   final ctxBytes = ctxBytesPtr.asTypedList(ctxLenOutPtr.value);
-  final ctx = BincodeReader.decode(ctxBytes, Context.empty());
+  final ctx = decodeMessage(ctxBytes, Context.empty());
 
   // Free allocations
   _freeAllocs(dartAllocs: dartAllocs, nativeAllocs: nativeAllocs);
@@ -66,16 +71,27 @@ Future<void> initFFI(int port) async {
   initDartPort(port);
 }
 
-Future<void> transcribeMicInput(Context ctx, int listenDurationMs) async {
-  _transcribeMicInput([ctx, listenDurationMs]);
+/// Transcribes the microphone input.
+///
+/// The [overrides] only apply to this call, leaving [ctx] untouched.
+///
+/// If the microphone is already being transcribed, returns [MessageStatus.alreadyListening] or
+/// [MessageStatus.queued] (see [Context.overlapPolicy]).
+Future<MessageStatus> transcribeMicInput(
+  Context ctx,
+  int listenDurationMs, {
+  ContextOverrides? overrides,
+}) async {
+  return _transcribeMicInput([ctx, listenDurationMs, overrides]);
 }
 
-void _transcribeMicInput(List<dynamic> args) {
+MessageStatus _transcribeMicInput(List<dynamic> args) {
   final ctx = args[0];
   final listenDurationMs = args[1];
+  final ContextOverrides? overrides = args[2];
 
   // Encode arguments
-  final ctxEncoded = BincodeWriter.encode(ctx);
+  final ctxEncoded = encodeMessage(ctx);
 
   // Allocate memory to send to Rust
   final ctxPtr = calloc.allocate<Uint8>(ctxEncoded.length);
@@ -85,11 +101,52 @@ void _transcribeMicInput(List<dynamic> args) {
   var ctxBytes = ctxPtr.asTypedList(ctxEncoded.length);
   ctxBytes.setAll(0, ctxEncoded);
 
+  // Encode overrides (if any)
+  Pointer<Uint8> overridesPtr = nullptr;
+  var overridesLen = 0;
+  if (overrides != null) {
+    final overridesEncoded = encodeMessage(overrides);
+    overridesLen = overridesEncoded.length;
+    overridesPtr = calloc.allocate<Uint8>(overridesLen);
+    overridesPtr.asTypedList(overridesLen).setAll(0, overridesEncoded);
+    dartAllocs.add(overridesPtr);
+  }
+
   // Call Rust function
-  transcribeSpeech(ctxPtr.cast(), ctxEncoded.length, listenDurationMs);
+  final status = transcribeSpeech(
+    ctxPtr.cast(),
+    ctxEncoded.length,
+    listenDurationMs,
+    overridesPtr.cast(),
+    overridesLen,
+  );
 
   // Free allocations
   _freeAllocs(dartAllocs: dartAllocs, nativeAllocs: {});
+
+  return MessageStatus.values[status];
+}
+
+/// Returns the error message of the most recent failed call to the [nativeLib].
+///
+/// Returns `null` if the most recent call succeeded.
+String? getLastErrorMessage() {
+  final msgLenOutPtr = calloc.allocate<UintPtr>(sizeOf<UintPtr>());
+  final msgPtr = lastErrorMessage(msgLenOutPtr);
+  if (msgPtr == nullptr) {
+    _freeAllocs(dartAllocs: [msgLenOutPtr], nativeAllocs: {});
+    return null;
+  }
+  final nativeAllocs = {(msgPtr, msgLenOutPtr.value)};
+
+  // Decode response
+  final msgBytes = msgPtr.cast<Uint8>().asTypedList(msgLenOutPtr.value);
+  final msg = decodeMessage(msgBytes, ErrorMessage.empty());
+
+  // Free allocations
+  _freeAllocs(dartAllocs: [msgLenOutPtr], nativeAllocs: nativeAllocs);
+
+  return msg.message;
 }
 
 /// Frees the defined allocations.
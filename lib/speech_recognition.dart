@@ -1,8 +1,11 @@
 import 'dart:async';
 import 'dart:collection';
+import 'dart:convert';
 import 'dart:ffi';
 import 'dart:isolate';
+import 'dart:typed_data';
 
+import 'package:d_bincode/d_bincode.dart';
 import 'package:flutter/material.dart';
 import 'package:virgil/main.dart';
 import 'package:virgil/model_manager.dart';
@@ -33,6 +36,12 @@ class SpeechRecognition {
   /// The port used for FFI communications.
   final _receivePort = ReceivePort();
 
+  /// The most recently detected wake word.
+  String? wakeWord;
+
+  /// The most recent window of audio sent by the native library (if raw audio is requested).
+  Float32List? lastAudioWindow;
+
   /// Determines if the mic is listening.
   bool isListening = false;
 
@@ -60,20 +69,129 @@ class SpeechRecognition {
         _logger.e('Invalid message');
         return;
       }
+      if (message is int) {
+        _logger.d('Received ping: $message');
+        return;
+      }
+      if (message is Float32List) {
+        _logger.t('Received ${message.length} samples of raw audio');
+        lastAudioWindow = message;
+        return;
+      }
+      if (message is Uint8List) {
+        _handleEvent(message);
+        return;
+      }
+      if (message is String) {
+        final event = _decodeJsonEvent(message);
+        if (event != null) {
+          _handleJsonEvent(event);
+          return;
+        }
+      }
       _transcript.add(message);
     });
   }
 
+  /// Handles an encoded event sent from the native library.
+  void _handleEvent(Uint8List bytes) {
+    switch (EventKind.of(bytes)) {
+      case EventKind.wakeWordDetected:
+        final event = BincodeReader.decode(bytes, WakeWordDetected.empty());
+        _logger.i('Wake word detected: ${event.word} (${event.confidence})');
+        wakeWord = event.word;
+      case EventKind.transcript:
+        final event = BincodeReader.decode(bytes, Transcript.empty());
+        _logger.d('Raw transcript: ${event.raw}');
+        if (event.language != null) {
+          final percent = (event.languageProbability * 100).round();
+          _logger.d('Detected language: ${event.language} ($percent%)');
+        }
+        _transcript.add(event.clean);
+      case EventKind.noSpeech:
+        _logger.i("Didn't catch that: no speech recognized");
+      case EventKind.lowConfidence:
+        final event = BincodeReader.decode(bytes, LowConfidence.empty());
+        _logger.i('Not sure about that: ${event.text} (${event.confidence})');
+      case EventKind.firstWord:
+        final event = BincodeReader.decode(bytes, FirstWord.empty());
+        _logger.d('First word: ${event.word}');
+      case EventKind.utterance:
+        final event = BincodeReader.decode(bytes, Utterance.empty());
+        _logger.d('Utterance (${event.startMs}-${event.endMs} ms)');
+        _transcript.add(event.text);
+      case EventKind.sessionError:
+        final event = BincodeReader.decode(bytes, SessionError.empty());
+        _logger.e('Session failed (${event.status.name}): ${event.detail}');
+      case EventKind.partial:
+        final event = BincodeReader.decode(bytes, Partial.empty());
+        _logger.d('Partial: ${event.text}');
+    }
+  }
+
+  /// Decodes the message as a JSON event, or returns `null` if it's a plain transcript.
+  ///
+  /// Transcripts can start with `{` too, so only JSON objects tagged with a `type` are events.
+  Map<String, dynamic>? _decodeJsonEvent(String message) {
+    if (!message.startsWith('{')) {
+      return null;
+    }
+    try {
+      final event = jsonDecode(message);
+      if (event is Map<String, dynamic> && event['type'] is String) {
+        return event;
+      }
+    } on FormatException {
+      // Not JSON, so it's a transcript
+    }
+    return null;
+  }
+
+  /// Handles an event sent from the native library as JSON (see [JsonEvents]).
+  void _handleJsonEvent(Map<String, dynamic> event) {
+    switch (event['type']) {
+      case 'wakeWordDetected':
+        _logger.i('Wake word detected: ${event['word']} (${event['confidence']})');
+        wakeWord = event['word'];
+      case 'transcript':
+        _logger.d('Raw transcript: ${event['raw']}');
+        _transcript.add(event['clean']);
+      case 'noSpeech':
+        _logger.i("Didn't catch that: no speech recognized");
+      case 'lowConfidence':
+        _logger.i('Not sure about that: ${event['text']} (${event['confidence']})');
+      case 'firstWord':
+        _logger.d('First word: ${event['word']}');
+      case 'utterance':
+        _logger.d('Utterance (${event['startMs']}-${event['endMs']} ms)');
+        _transcript.add(event['text']);
+      case 'sessionError':
+        _logger.e('Session failed (${event['status']}): ${event['detail']}');
+      case 'partial':
+        _logger.d('Partial: ${event['text']}');
+      default:
+        _logger.w('Unknown event: $event');
+    }
+  }
+
   /// Starts listening to the mic and running speech recognition.
   Future<void> startListening() async {
+    final status = await transcribeMicInput(_ctx, _listenDurationMs);
+    if (status == MessageStatus.alreadyListening) {
+      _logger.w('Already listening');
+    } else if (status == MessageStatus.queued) {
+      _logger.i('Already listening, request queued');
+    }
     isListening = true;
-    await transcribeMicInput(_ctx, _listenDurationMs);
   }
 
   /// Stops the microphone.
   Future<void> stopListening() async {
     isListening = false;
-    stopMic();
+    final status = MessageStatus.values[stopMic()];
+    if (status != MessageStatus.success) {
+      _logger.e('Unable to stop listening: ${getLastErrorMessage()}');
+    }
   }
 
   /// Cleans up resources.
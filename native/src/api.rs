@@ -1,28 +1,62 @@
+//! The FFI functions called from Dart.
+//!
+//! # Threading
+//! Every function can be called from any thread (e.g. from several isolates at once):
+//! - Global state is only accessed through its mutex, and calls that initialize or replace it
+//!   ([init_context], [load_model]) are serialized by [INIT_LOCK].
+//! - Functions block on those mutexes, so they must not be called from inside an async runtime;
+//!   none of them call each other or back into Dart, so they're reentrancy-safe.
+//! - [LAST_ERROR] is shared by all threads, so it describes the most recent call from any thread.
+//!   Callers that need a call's exact error must not make calls concurrently with it.
+
 use std::{
+    collections::HashMap,
     ffi,
-    ptr::slice_from_raw_parts_mut,
-    sync::LazyLock,
-    thread,
+    panic::{self, AssertUnwindSafe},
+    ptr,
+    sync::{
+        Arc, LazyLock,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread::{self, JoinHandle},
     time::{Duration, Instant},
 };
 
-use cpal::traits::StreamTrait;
+use futures::{executor::block_on, future::join_all};
+use thiserror::Error;
 use tokio::{
-    runtime::Runtime,
+    runtime::{self, Runtime},
     sync::{
         Mutex,
-        mpsc::{self},
+        mpsc::{self, error::TryRecvError},
     },
+    time::timeout,
+};
+use tracing::{Instrument, Level, Span, Subscriber, debug, error, info, span, warn};
+use tracing_subscriber::{
+    Layer, filter,
+    layer::{self, SubscriberExt},
+    util::SubscriberInitExt,
 };
-use tracing::{Level, Span, debug, error, info, span};
-use tracing_subscriber::{filter, layer::SubscriberExt, util::SubscriberInitExt};
-use whisper_rs::{FullParams, SamplingStrategy, WhisperState, install_logging_hooks};
+use whisper_rs::{WHISPER_CPP_VERSION, install_logging_hooks};
 
 use crate::{
-    port::{DartPort, send_text_to_dart, set_dart_port},
+    audio_math::{ms_to_samples, samples_to_bytes, samples_to_ms},
+    messages::{Event, LifecycleEvent, ListeningState, MessageStatus},
+    port::{
+        DartPort, ThrottledKind, ThrottledSender, send_audio_to_dart, send_event_to_dart,
+        send_json_event_to_dart, send_nonce_to_dart, send_status_to_dart, send_text_to_dart,
+        set_dart_port,
+    },
     utils::{
-        Context, EXPECTED_SAMPLE_RATE, SendStream, deserialize, detect_wake_words, init_microphone,
-        init_model, serialize, transcribe,
+        AccumulationMode, Agc, Context, ContextOverrides, DETECTED_SAMPLE_RATE, Diagnostics,
+        EVENT_LOG_CAPACITY, Endpointer, EventLog, FfiBuffer, InputDeviceInfo, ListenConfig,
+        MAX_PAYLOAD_BYTES, MIC_PAUSED, Microphone, ModelHandle, ModelInfo, OverlapPolicy, PreRoll,
+        SourceMode, SpeechState, Transcriber, TranscriberPool, Transcript, VirgilResult,
+        WakeWordMatcher, WhisperTranscriber, WordTiming, append_transcript, check_model_path,
+        chunk_audio, deserialize, detect_wake_words, evict_model_context, group_words, init_model,
+        is_speech, merge_chunk_segments, mix_sources, model_languages, rate_drifted, read_wav,
+        resample_to_16k, serialize, transcribe, transcribe_chunks, transcript_slice,
     },
 };
 
@@ -30,8 +64,143 @@ pub static RUN: LazyLock<Mutex<bool>> = LazyLock::new(|| Mutex::new(false));
 
 pub static LOGS_SET: LazyLock<Mutex<bool>> = LazyLock::new(|| Mutex::new(false));
 
+/// The most recently loaded model, while it isn't being used by a session.
+pub static MODEL: LazyLock<Mutex<Option<ModelHandle>>> = LazyLock::new(|| Mutex::new(None));
+
+/// Information about the most recently loaded model.
+pub static LOADED_MODEL: LazyLock<Mutex<Option<ModelInfo>>> = LazyLock::new(|| Mutex::new(None));
+
+/// Information about the most recently initialized input device.
+pub static INPUT_DEVICE: LazyLock<Mutex<Option<InputDeviceInfo>>> =
+    LazyLock::new(|| Mutex::new(None));
+
+/// The number of errors logged since logging was set up.
+pub static ERROR_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// The error message of the most recent failed call, cleared by the next successful call.
+pub static LAST_ERROR: LazyLock<Mutex<Option<String>>> = LazyLock::new(|| Mutex::new(None));
+
+/// Every transcript produced since the library was loaded.
+pub static TRANSCRIPT_HISTORY: LazyLock<Mutex<String>> =
+    LazyLock::new(|| Mutex::new(String::new()));
+
+/// The microphone stream of the current session.
+pub static MIC: LazyLock<Mutex<Option<Microphone>>> = LazyLock::new(|| Mutex::new(None));
+
+/// The wake words of the current session, which can be updated while it's running.
+pub static WAKE_WORDS: LazyLock<Mutex<Vec<String>>> = LazyLock::new(|| Mutex::new(Vec::new()));
+
+/// Named sets of wake words (e.g. "home", "car"), which can be switched between with
+/// [activate_profile].
+pub static WAKE_WORD_PROFILES: LazyLock<Mutex<HashMap<String, Vec<String>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// The name of the active wake word profile, whose wake words are used instead of the context's.
+pub static ACTIVE_PROFILE: LazyLock<Mutex<Option<String>>> = LazyLock::new(|| Mutex::new(None));
+
+/// The most recent session events, queried with [get_event_log].
+pub static EVENT_LOG: LazyLock<Mutex<EventLog>> =
+    LazyLock::new(|| Mutex::new(EventLog::new(EVENT_LOG_CAPACITY)));
+
+/// The thread processing the audio of the current session.
+pub static PROCESS_THREAD: LazyLock<Mutex<Option<JoinHandle<()>>>> =
+    LazyLock::new(|| Mutex::new(None));
+
+/// A new listen config for the running session, applied before its next window.
+pub static LISTEN_CONFIG_UPDATE: LazyLock<Mutex<Option<ListenConfig>>> =
+    LazyLock::new(|| Mutex::new(None));
+
+/// Held while global state is initialized or replaced, so concurrent calls don't interleave.
+pub static INIT_LOCK: LazyLock<Mutex<()>> = LazyLock::new(|| Mutex::new(()));
+
+/// The current state of the listening pipeline, queried with [listening_state].
+pub static LISTENING_STATE: LazyLock<Mutex<ListeningState>> =
+    LazyLock::new(|| Mutex::new(ListeningState::Idle));
+
+/// The number of listening sessions currently running.
+pub static ACTIVE_SESSIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// The maximum number of listening sessions (and their runtimes/threads) that can run at once.
+const MAX_ACTIVE_SESSIONS: usize = 1;
+
 const ACTIVE_LISTEN_DURATION_SECS: u64 = 5;
 
+/// The number of audio chunks (not samples) that can be queued between the microphone and the
+/// processing thread.
+///
+/// At the typical ~10ms per callback, this holds roughly 10s of audio while inference runs.
+const AUDIO_CHANNEL_CAPACITY: usize = 1024;
+
+/// The extra samples added to each window, so the end of the listen duration isn't cut off.
+const WINDOW_PADDING_SAMPLES: usize = 200;
+
+/// How much longer (in milliseconds) windows get once a wake word is detected.
+const WAKE_WINDOW_EXTENSION_MS: usize = 1000;
+
+/// How long [stop_mic] waits for the processing thread to exit.
+///
+/// The thread only checks if it should stop between windows, so this covers a slow inference.
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The maximum number of times processing is restarted after panicking, per session.
+const MAX_PROCESS_RESTARTS: usize = 3;
+
+/// How long processing waits for the next audio buffer before checking if it should stop.
+const AUDIO_RECV_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// How often [stop_mic] checks if the processing thread has exited.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The length (in milliseconds) of the chunks a recording is replayed in.
+const REPLAY_CHUNK_MS: usize = 100;
+
+/// The listen duration (in milliseconds) used when replaying a recording.
+const REPLAY_LISTEN_DURATION_MS: u64 = 1000;
+
+/// Logs the error and records it as the [LAST_ERROR].
+fn set_last_error(msg: String) {
+    error!("{msg}");
+    *LAST_ERROR.blocking_lock() = Some(msg);
+}
+
+/// Clears the [LAST_ERROR] after a successful call.
+fn clear_last_error() {
+    *LAST_ERROR.blocking_lock() = None;
+}
+
+/// Marks a listening session as active until dropped.
+struct SessionGuard;
+
+impl SessionGuard {
+    /// Starts a new session, or returns `None` if the maximum number of sessions are active.
+    fn acquire() -> Option<Self> {
+        ACTIVE_SESSIONS
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |active| {
+                (active < MAX_ACTIVE_SESSIONS).then_some(active + 1)
+            })
+            .ok()
+            .map(|_| Self)
+    }
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        ACTIVE_SESSIONS.fetch_sub(1, Ordering::SeqCst);
+        debug!("Listening session ended");
+    }
+}
+
+/// A logging layer that counts the number of errors logged.
+struct ErrorCounter;
+
+impl<S: Subscriber> Layer<S> for ErrorCounter {
+    fn on_event(&self, event: &tracing::Event<'_>, _ctx: layer::Context<'_, S>) {
+        if *event.metadata().level() == Level::ERROR {
+            ERROR_COUNT.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
 /// Sets up logging for the library.
 #[unsafe(no_mangle)]
 pub fn setup_logs(level: usize) {
@@ -45,40 +214,49 @@ pub fn setup_logs(level: usize) {
     };
 
     let mut logs_set = LOGS_SET.blocking_lock();
-    if *logs_set == false {
+    if !*logs_set {
         // Suppress logs from `whisper.cpp`.
         install_logging_hooks();
 
-        // Filter specific crates by log levels
-        let filter = filter::Targets::new()
-            .with_target("native", log_level)
-            .with_target("whisper-rs", Level::ERROR);
-        tracing_subscriber::registry()
-            .with(filter)
-            .with(
-                tracing_subscriber::fmt::layer()
-                    .with_line_number(true)
-                    .with_target(true),
-            )
-            .init();
+        // NOTE: The global subscriber can only be set once per process, so one that's already
+        // set (e.g. by the host application) is kept rather than panicking.
+        if let Err(e) = logs_subscriber(log_level).try_init() {
+            warn!("Keeping the existing log subscriber: {e}");
+        }
 
         *logs_set = true;
     }
 }
 
+/// Creates the subscriber used for the library's logs.
+///
+/// [setup_logs] installs it globally; it can also be installed for a single scope (e.g. one
+/// test) with [tracing::subscriber::set_default], which doesn't affect the global subscriber.
+fn logs_subscriber(log_level: Level) -> impl Subscriber + Send + Sync {
+    // Filter specific crates by log levels
+    let filter = filter::Targets::new()
+        .with_target("native", log_level)
+        .with_target("whisper-rs", Level::ERROR);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_line_number(true)
+                .with_target(true),
+        )
+        .with(ErrorCounter)
+}
+
 /// Frees the memory allocated by Rust.
 #[unsafe(no_mangle)]
 pub fn free_rust_ptr(ptr: *mut ffi::c_void, len: usize) {
-    if ptr.is_null() {
-        return;
-    }
-    unsafe {
-        let ptr: *mut u8 = ptr.cast();
-        let _ = Box::from_raw(slice_from_raw_parts_mut(ptr, len));
-    }
+    FfiBuffer::from_raw_parts(ptr, len).free();
 }
 
 /// Initalizes the application context.
+///
+/// A null pointer is returned if the arguments can't be decoded, or the model path doesn't exist
+/// or isn't readable.
 #[unsafe(no_mangle)]
 pub fn init_context(
     model_path: *mut ffi::c_void,
@@ -89,28 +267,48 @@ pub fn init_context(
 ) -> *mut ffi::c_void {
     let span = span!(Level::TRACE, "init_context");
     let _enter = span.enter();
+    let _init = INIT_LOCK.blocking_lock();
 
     // Decode model path and wake words
-    let model_path: String = deserialize(model_path, model_path_len)
-        .map_err(|e| error!("{e}"))
-        .unwrap();
+    let model_path: String = match deserialize(model_path, model_path_len) {
+        Ok(model_path) => model_path,
+        Err(e) => {
+            set_last_error(e.to_string());
+            return ptr::null_mut();
+        }
+    };
     debug!("Model path decoded: {model_path}");
 
-    let wake_words: Vec<String> = deserialize(wake_words, wake_words_len)
-        .map_err(|e| error!("{e}"))
-        .unwrap();
+    let wake_words: Vec<String> = match deserialize(wake_words, wake_words_len) {
+        Ok(wake_words) => wake_words,
+        Err(e) => {
+            set_last_error(e.to_string());
+            return ptr::null_mut();
+        }
+    };
     debug!("Wake words decoded: {wake_words:?}");
 
+    // Fail early if the model can't be loaded later
+    //
+    // NOTE: `Context::asset_dir` isn't set yet, so relative paths are resolved against the working
+    // directory.
+    let ctx = Context::new(model_path, wake_words);
+    if let Err(e) = check_model_path(&ctx.resolved_model_path()) {
+        set_last_error(e.to_string());
+        return ptr::null_mut();
+    }
+
     // Encode context
-    let ctx = Context {
-        model_path,
-        wake_words,
+    let encoded_ctx = match serialize(ctx, ctx_len_out) {
+        Ok(encoded_ctx) => encoded_ctx,
+        Err(e) => {
+            set_last_error(e.to_string());
+            return ptr::null_mut();
+        }
     };
-    let encoded_ctx = serialize(ctx, ctx_len_out)
-        .map_err(|e| error!("{e}"))
-        .unwrap();
     debug!("Context encoded");
 
+    clear_last_error();
     encoded_ctx
 }
 
@@ -126,172 +324,3480 @@ pub fn init_dart_port(port: DartPort) {
     set_dart_port(port);
 }
 
-/// Turns microphone input into text.
+/// Sends the given nonce back to Dart through the port.
+///
+/// This lets Dart verify that the port works before relying on it for transcripts.
 #[unsafe(no_mangle)]
-pub fn transcribe_speech(ctx: *mut ffi::c_void, ctx_len: usize, listen_duration_ms: usize) {
-    let span = span!(Level::TRACE, "transcribe_speech");
+pub fn ping_dart(nonce: i64) -> MessageStatus {
+    let span = span!(Level::TRACE, "ping_dart");
     let _enter = span.enter();
 
-    let listen_duration_ms = listen_duration_ms as u64;
+    match send_nonce_to_dart(nonce) {
+        Ok(_) => {
+            debug!("Pinged Dart with nonce: {nonce}");
+            clear_last_error();
+            MessageStatus::Success
+        }
+        Err(e) => {
+            set_last_error(format!("Unable to ping Dart: {e}"));
+            MessageStatus::Error
+        }
+    }
+}
 
-    // Init tokio runtime
-    let rt = Runtime::new().unwrap();
+/// Pauses the microphone without tearing down the model or the accumulated audio.
+#[unsafe(no_mangle)]
+pub fn pause_listening() -> MessageStatus {
+    let span = span!(Level::TRACE, "pause_listening");
+    let _enter = span.enter();
 
-    // Setup channels for communication
-    let (input_audio_tx, input_audio_rx) = mpsc::channel::<Vec<f32>>(EXPECTED_SAMPLE_RATE);
+    let Some(mic) = &*MIC.blocking_lock() else {
+        set_last_error("Unable to pause: not listening".into());
+        return MessageStatus::Error;
+    };
+    MIC_PAUSED.store(true, Ordering::SeqCst);
+    if let Err(e) = mic.pause() {
+        set_last_error(format!("Unable to pause microphone: {e}"));
+        return MessageStatus::Error;
+    }
+    *LISTENING_STATE.blocking_lock() = ListeningState::Paused;
+    info!("Listening paused");
 
-    // Decode context
-    let ctx: Context = deserialize(ctx, ctx_len)
-        .map_err(|e| error!("{e}"))
-        .unwrap();
-    debug!("Context decoded");
+    clear_last_error();
+    MessageStatus::Success
+}
 
-    // Init `Whisper` model
-    let model = init_model(&ctx.model_path)
-        .map_err(|e| error!("{e}"))
-        .unwrap();
+/// Resumes a microphone paused with [pause_listening].
+#[unsafe(no_mangle)]
+pub fn resume_listening() -> MessageStatus {
+    let span = span!(Level::TRACE, "resume_listening");
+    let _enter = span.enter();
 
-    // Initalize microphone
-    let mic = SendStream(
-        init_microphone(input_audio_tx.clone())
-            .map_err(|e| error!("{e}"))
-            .unwrap(),
-    );
-    *RUN.blocking_lock() = true;
+    let Some(mic) = &*MIC.blocking_lock() else {
+        set_last_error("Unable to resume: not listening".into());
+        return MessageStatus::Error;
+    };
+    if let Err(e) = mic.play() {
+        set_last_error(format!("Unable to resume microphone: {e}"));
+        return MessageStatus::Error;
+    }
+    MIC_PAUSED.store(false, Ordering::SeqCst);
+    // NOTE: The session switches back to `Armed` with its next window if it was armed.
+    *LISTENING_STATE.blocking_lock() = ListeningState::Listening;
+    info!("Listening resumed");
 
-    // Listen to the microphone for the specified amount of time
-    rt.spawn(async move {
-        let span = span!(Level::TRACE, "listener");
-        let _enter = span.enter();
+    clear_last_error();
+    MessageStatus::Success
+}
 
-        mic.0
-            .play()
-            .map_err(|e| error!("Failed to start listening to mic: {e}"))
-            .unwrap();
-        info!("Listening to microphone...");
+/// Loads the model from the context's model path ahead of time.
+///
+/// Loading is skipped (returning [MessageStatus::AlreadyLoaded]) if the model was already loaded
+/// from the same path, unless `force_reload` is set.
+#[unsafe(no_mangle)]
+pub fn load_model(ctx: *mut ffi::c_void, ctx_len: usize, force_reload: bool) -> MessageStatus {
+    let span = span!(Level::TRACE, "load_model");
+    let _enter = span.enter();
+    let _init = INIT_LOCK.blocking_lock();
 
-        loop {
-            tokio::time::sleep(Duration::from_millis(listen_duration_ms)).await;
+    if ACTIVE_SESSIONS.load(Ordering::SeqCst) > 0 {
+        set_last_error("Unable to load model: already listening".into());
+        return MessageStatus::AlreadyListening;
+    }
+
+    let ctx: Context = match deserialize(ctx, ctx_len) {
+        Ok(ctx) => ctx,
+        Err(e) => {
+            set_last_error(e.to_string());
+            return MessageStatus::Error;
         }
-    });
+    };
 
-    info!("Processing microphone input...");
-    let parent_span = span.clone();
-    thread::spawn(move || {
-        rt.block_on(async move {
-            tokio::select! {
-                _ = tokio::spawn(process(ctx, model, input_audio_rx, listen_duration_ms, parent_span)) => {},
-                _ = futures::future::pending::<()>() => {
-                    if *RUN.lock().await == false {
-                        return;
-                    }
-                },
-            }
-        });
+    let (model, loaded) = match take_or_load_model(&ctx, force_reload) {
+        Ok(model) => model,
+        Err(e) => {
+            set_last_error(format!("Unable to load model: {e}"));
+            return MessageStatus::Error;
+        }
+    };
+    *MODEL.blocking_lock() = Some(model);
+    clear_last_error();
+
+    if loaded {
+        info!("Model loaded: {}", ctx.resolved_model_path());
+        MessageStatus::Success
+    } else {
+        info!("Model already loaded: {}", ctx.resolved_model_path());
+        MessageStatus::AlreadyLoaded
+    }
+}
+
+/// Unloads the model loaded from the path, freeing its memory (on the GPU or CPU).
+///
+/// The path must be the one the model was loaded from (resolved against [Context::asset_dir]).
+/// Unloading a model that isn't loaded does nothing. If a session is using the model, its memory
+/// is freed once the session stops.
+#[unsafe(no_mangle)]
+pub fn unload_model(path: *mut ffi::c_void, path_len: usize) -> MessageStatus {
+    let span = span!(Level::TRACE, "unload_model");
+    let _enter = span.enter();
+    let _init = INIT_LOCK.blocking_lock();
+
+    let path: String = match deserialize(path, path_len) {
+        Ok(path) => path,
+        Err(e) => {
+            set_last_error(e.to_string());
+            return MessageStatus::Error;
+        }
+    };
+
+    let mut unloaded = evict_model_context(&path);
+    let mut loaded_model = LOADED_MODEL.blocking_lock();
+    if loaded_model.as_ref().is_some_and(|info| info.path == path) {
+        *loaded_model = None;
+        *MODEL.blocking_lock() = None;
+        unloaded = true;
+    }
+
+    if unloaded {
+        info!("Model unloaded: {path}");
+    } else {
+        debug!("Model not loaded, nothing to unload: {path}");
+    }
+    clear_last_error();
+    MessageStatus::Success
+}
+
+/// Updates the wake words of the running session.
+///
+/// The new wake words are used starting with the next detection; the context passed to Dart is
+/// left untouched. An empty list stops the session from waiting for wake words (as if it ignored
+/// them) until wake words are set again.
+#[unsafe(no_mangle)]
+pub fn set_wake_words(wake_words: *mut ffi::c_void, wake_words_len: usize) -> MessageStatus {
+    let span = span!(Level::TRACE, "set_wake_words");
+    let _enter = span.enter();
+
+    let wake_words: Vec<String> = match deserialize(wake_words, wake_words_len) {
+        Ok(wake_words) => wake_words,
+        Err(e) => {
+            set_last_error(e.to_string());
+            return MessageStatus::Error;
+        }
+    };
+    debug!("Wake words updated: {wake_words:?}");
+    *WAKE_WORDS.blocking_lock() = wake_words;
+
+    clear_last_error();
+    MessageStatus::Success
+}
+
+/// Adds (or replaces) the wake word profile with the given name.
+///
+/// If the profile is active, its new wake words are used starting with the next detection.
+#[unsafe(no_mangle)]
+pub fn add_wake_word_profile(
+    name: *mut ffi::c_void,
+    name_len: usize,
+    wake_words: *mut ffi::c_void,
+    wake_words_len: usize,
+) -> MessageStatus {
+    let span = span!(Level::TRACE, "add_wake_word_profile");
+    let _enter = span.enter();
+
+    let decoded = deserialize::<String>(name, name_len).and_then(|name| {
+        deserialize::<Vec<String>>(wake_words, wake_words_len).map(|words| (name, words))
     });
+    let (name, wake_words) = match decoded {
+        Ok(decoded) => decoded,
+        Err(e) => {
+            set_last_error(e.to_string());
+            return MessageStatus::Error;
+        }
+    };
+    debug!("Wake word profile added: {name} ({wake_words:?})");
+
+    if ACTIVE_PROFILE.blocking_lock().as_ref() == Some(&name) {
+        *WAKE_WORDS.blocking_lock() = wake_words.clone();
+    }
+    WAKE_WORD_PROFILES.blocking_lock().insert(name, wake_words);
+
+    clear_last_error();
+    MessageStatus::Success
 }
 
-/// Stops the microphone.
+/// Activates the wake word profile with the given name.
+///
+/// The profile's wake words are used starting with the next detection, and by every following
+/// session (instead of the context's).
 #[unsafe(no_mangle)]
-pub fn stop_mic() {
-    let span = span!(Level::TRACE, "stop_mic");
+pub fn activate_profile(name: *mut ffi::c_void, name_len: usize) -> MessageStatus {
+    let span = span!(Level::TRACE, "activate_profile");
     let _enter = span.enter();
-    info!("Mic stopped!");
-    *RUN.blocking_lock() = false;
+
+    let name: String = match deserialize(name, name_len) {
+        Ok(name) => name,
+        Err(e) => {
+            set_last_error(e.to_string());
+            return MessageStatus::Error;
+        }
+    };
+    let Some(wake_words) = WAKE_WORD_PROFILES.blocking_lock().get(&name).cloned() else {
+        set_last_error(format!("Unknown wake word profile: {name}"));
+        return MessageStatus::Error;
+    };
+    info!("Wake word profile activated: {name}");
+    *WAKE_WORDS.blocking_lock() = wake_words;
+    *ACTIVE_PROFILE.blocking_lock() = Some(name);
+
+    clear_last_error();
+    MessageStatus::Success
 }
 
-/// Processes the audio data (in a loop) by transcibing audio data if wake words are detected.
-async fn process(
-    ctx: Context,
-    mut model: WhisperState,
-    mut input_audio_rx: mpsc::Receiver<Vec<f32>>,
-    listen_duration_ms: u64,
-    parent_span: Span,
-) {
-    let span = span!(parent: &parent_span, Level::TRACE, "process");
+/// Applies a new `ListenConfig` (VAD, gain, windowing, etc.) to the running session, without
+/// restarting the microphone or reloading the model.
+///
+/// The session picks up the new settings before its next window. Invalid configs are rejected.
+#[unsafe(no_mangle)]
+pub fn update_listen_config(cfg: *mut ffi::c_void, cfg_len: usize) -> MessageStatus {
+    let span = span!(Level::TRACE, "update_listen_config");
     let _enter = span.enter();
-    info!("Processing audio data...");
 
-    let mut detected_time = None;
-    let mut wake_word_detected = false;
-    let mut desired_num_samples = (listen_duration_ms as usize / 1000) * EXPECTED_SAMPLE_RATE + 200;
-    let original_desired_num_samples =
-        (listen_duration_ms as usize / 1000) * EXPECTED_SAMPLE_RATE + 200;
-    let mut accumulated_audio = Vec::with_capacity(desired_num_samples);
-    while *RUN.lock().await == true {
-        while let Ok(audio_data) = input_audio_rx.try_recv() {
-            let accumulated_samples = accumulated_audio.len();
-            let samples_to_add = audio_data.len();
-            let num_samples = accumulated_samples + samples_to_add;
+    if ACTIVE_SESSIONS.load(Ordering::SeqCst) == 0 {
+        set_last_error("Unable to update listen config: not listening".into());
+        return MessageStatus::Error;
+    }
 
-            // Accumulate audio data until desired length is reached
-            if num_samples < desired_num_samples {
-                accumulated_audio.extend_from_slice(&audio_data);
-                continue;
-            }
+    let cfg: ListenConfig = match deserialize(cfg, cfg_len) {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            set_last_error(e.to_string());
+            return MessageStatus::Error;
+        }
+    };
+    if let Err(e) = cfg.validate() {
+        set_last_error(e.to_string());
+        return MessageStatus::Error;
+    }
+    debug!("Listen config update queued: {cfg:?}");
+    *LISTEN_CONFIG_UPDATE.blocking_lock() = Some(cfg);
 
-            // If more than desired samples, send exact amount then restart accumulation
-            if num_samples >= desired_num_samples {
-                let extra = num_samples - desired_num_samples;
-                let end_idx = samples_to_add.abs_diff(extra).min(audio_data.len());
-                // let end_idx = samples_to_add - extra;
+    clear_last_error();
+    MessageStatus::Success
+}
 
-                // Send desired number of samples
-                accumulated_audio.extend_from_slice(&audio_data[0..end_idx]);
-                debug!("Accumulated {} samples", accumulated_audio.len());
+/// Sets the maximum size (in bytes) of the buffers returned to Dart; larger buffers are refused
+/// (see [serialize]).
+///
+/// The default is [crate::utils::DEFAULT_MAX_PAYLOAD_BYTES]. A maximum of `0` is rejected.
+#[unsafe(no_mangle)]
+pub fn set_max_payload_size(max_bytes: usize) -> MessageStatus {
+    let span = span!(Level::TRACE, "set_max_payload_size");
+    let _enter = span.enter();
 
-                // FIXME: Handle multiple channels
+    if max_bytes == 0 {
+        set_last_error("Maximum payload size must be greater than 0".into());
+        return MessageStatus::Error;
+    }
+    MAX_PAYLOAD_BYTES.store(max_bytes, Ordering::Relaxed);
+    debug!("Maximum payload size set to {max_bytes} bytes");
 
-                // Transcribe data
-                let params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
-                if !wake_word_detected {
-                    wake_word_detected = detect_wake_words(
-                        &mut model,
-                        params.clone(),
-                        &accumulated_audio,
-                        &ctx.wake_words,
-                    )
-                    .map_err(|e| error!("Unable to detected wake words: {e}"))
-                    .unwrap_or_else(|_| false);
-
-                    if wake_word_detected {
-                        info!("Wake word detected");
-                        detected_time = Some(Instant::now());
-                        desired_num_samples += EXPECTED_SAMPLE_RATE;
-                        continue;
-                    }
-                }
+    clear_last_error();
+    MessageStatus::Success
+}
 
-                if wake_word_detected {
-                    if let Some(recorded_time) = detected_time {
-                        let elasped = Instant::now() - recorded_time;
+/// Computes the duration (in milliseconds) of the given number of audio samples.
+#[unsafe(no_mangle)]
+pub fn get_audio_duration_ms(num_samples: usize, sample_rate: usize) -> u64 {
+    samples_to_ms(num_samples, sample_rate)
+}
 
-                        // FIXME: Make duration dynamic!
-                        if elasped >= Duration::from_secs(ACTIVE_LISTEN_DURATION_SECS) {
-                            wake_word_detected = false;
-                            detected_time = None;
-                            desired_num_samples = original_desired_num_samples;
-                            continue;
-                        }
-                    }
+/// Returns the estimated memory usage of the loaded model.
+///
+/// # Note
+/// The caller must free the returned pointer with [free_rust_ptr]. A null pointer is returned if
+/// no model has been loaded.
+#[unsafe(no_mangle)]
+pub fn model_memory_estimate(estimate_len_out: *mut usize) -> *mut ffi::c_void {
+    let span = span!(Level::TRACE, "model_memory_estimate");
+    let _enter = span.enter();
 
-                    // Send transcript to Dart
-                    let text = transcribe(&mut model, params, &accumulated_audio).unwrap();
-                    send_text_to_dart(text)
-                        .map_err(|e| error!("Unable to send text to Dart: {e}"))
-                        .unwrap();
-                    debug!("Transcript sent");
-                }
+    let Some(model_info) = &*LOADED_MODEL.blocking_lock() else {
+        set_last_error("Unable to estimate memory usage: no model loaded".into());
+        return ptr::null_mut();
+    };
+    debug!(
+        "Estimated model memory usage: {} bytes",
+        model_info.memory_estimate.total_bytes()
+    );
 
-                // Reset accumulated data and fill with remaining/overflowing samples
-                debug!("Accumulated data reset");
-                accumulated_audio.clear();
-                accumulated_audio.extend_from_slice(&audio_data[end_idx..]);
+    match serialize(model_info.memory_estimate, estimate_len_out) {
+        Ok(encoded) => {
+            clear_last_error();
+            encoded
+        }
+        Err(e) => {
+            set_last_error(e.to_string());
+            ptr::null_mut()
+        }
+    }
+}
 
-                continue;
-            }
+/// Returns the codes of the languages the loaded model supports.
+///
+/// English-only models only support `"en"`.
+///
+/// # Note
+/// The caller must free the returned pointer with [free_rust_ptr]. A null pointer is returned if
+/// no model has been loaded.
+#[unsafe(no_mangle)]
+pub fn supported_languages(languages_len_out: *mut usize) -> *mut ffi::c_void {
+    let span = span!(Level::TRACE, "supported_languages");
+    let _enter = span.enter();
+
+    let Some(model_info) = &*LOADED_MODEL.blocking_lock() else {
+        set_last_error("Unable to list supported languages: no model loaded".into());
+        return ptr::null_mut();
+    };
+    let languages = model_languages(model_info.multilingual);
+    debug!("Supported languages: {languages:?}");
+
+    match serialize(languages, languages_len_out) {
+        Ok(encoded) => {
+            clear_last_error();
+            encoded
+        }
+        Err(e) => {
+            set_last_error(e.to_string());
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Returns diagnostic information about the library, to be attached to bug reports.
+///
+/// # Note
+/// The caller must free the returned pointer with [free_rust_ptr].
+#[unsafe(no_mangle)]
+pub fn dump_diagnostics(diagnostics_len_out: *mut usize) -> *mut ffi::c_void {
+    let span = span!(Level::TRACE, "dump_diagnostics");
+    let _enter = span.enter();
+
+    let mut diagnostics = Diagnostics {
+        crate_version: env!("CARGO_PKG_VERSION").into(),
+        whisper_cpp_version: WHISPER_CPP_VERSION.into(),
+        error_count: ERROR_COUNT.load(Ordering::Relaxed),
+        ..Default::default()
+    };
+    if let Some(model_info) = &*LOADED_MODEL.blocking_lock() {
+        diagnostics.model_path = model_info.path.clone();
+        diagnostics.gpu_enabled = model_info.use_gpu;
+    }
+    if let Some(device_info) = &*INPUT_DEVICE.blocking_lock() {
+        diagnostics.input_device = device_info.name.clone();
+        diagnostics.sample_rate = device_info.sample_rate;
+        diagnostics.detected_rate = DETECTED_SAMPLE_RATE.load(Ordering::SeqCst);
+    }
+    debug!("Diagnostics collected: {diagnostics:?}");
+
+    match serialize(diagnostics, diagnostics_len_out) {
+        Ok(encoded) => {
+            clear_last_error();
+            encoded
+        }
+        Err(e) => {
+            set_last_error(e.to_string());
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Clears the metrics (the error count), the transcript history and the event log, so a fresh
+/// session can be measured.
+///
+/// The history and event log are locked together, so no caller sees one cleared without the
+/// other.
+#[unsafe(no_mangle)]
+pub fn reset_session_state() -> MessageStatus {
+    let span = span!(Level::TRACE, "reset_session_state");
+    let _enter = span.enter();
+
+    // NOTE: Nothing else holds both locks, so taking them in this order can't deadlock.
+    let mut history = TRANSCRIPT_HISTORY.blocking_lock();
+    let mut event_log = EVENT_LOG.blocking_lock();
+    history.clear();
+    event_log.clear();
+    ERROR_COUNT.store(0, Ordering::Relaxed);
+    info!("Session state reset");
+
+    clear_last_error();
+    MessageStatus::Success
+}
+
+/// Returns the most recent session events (oldest first), with when they happened.
+///
+/// Only the last [EVENT_LOG_CAPACITY] events are kept.
+///
+/// # Note
+/// The caller must free the returned pointer with [free_rust_ptr].
+#[unsafe(no_mangle)]
+pub fn get_event_log(log_len_out: *mut usize) -> *mut ffi::c_void {
+    let span = span!(Level::TRACE, "get_event_log");
+    let _enter = span.enter();
+
+    let entries = EVENT_LOG.blocking_lock().entries();
+    debug!("{} event log entries collected", entries.len());
+
+    match serialize(entries, log_len_out) {
+        Ok(encoded) => {
+            clear_last_error();
+            encoded
+        }
+        Err(e) => {
+            set_last_error(e.to_string());
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Returns the current state of the listening pipeline.
+///
+/// # Note
+/// The caller must free the returned pointer with [free_rust_ptr].
+#[unsafe(no_mangle)]
+pub fn listening_state(state_len_out: *mut usize) -> *mut ffi::c_void {
+    let span = span!(Level::TRACE, "listening_state");
+    let _enter = span.enter();
+
+    let state = *LISTENING_STATE.blocking_lock();
+    match serialize(state, state_len_out) {
+        Ok(encoded) => {
+            clear_last_error();
+            encoded
+        }
+        Err(e) => {
+            set_last_error(e.to_string());
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Returns the error message of the most recent failed call.
+///
+/// Call this after a function signals a failure (through a status or null pointer) for details.
+/// Like every other call, returning the message successfully clears it.
+///
+/// # Note
+/// The caller must free the returned pointer with [free_rust_ptr]. A null pointer is returned if
+/// the most recent call succeeded.
+#[unsafe(no_mangle)]
+pub fn last_error_message(msg_len_out: *mut usize) -> *mut ffi::c_void {
+    let span = span!(Level::TRACE, "last_error_message");
+    let _enter = span.enter();
+
+    let Some(msg) = LAST_ERROR.blocking_lock().clone() else {
+        return ptr::null_mut();
+    };
+
+    match serialize(msg, msg_len_out) {
+        Ok(encoded) => {
+            clear_last_error();
+            encoded
+        }
+        Err(e) => {
+            set_last_error(e.to_string());
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Returns up to `len` characters of the transcript history, starting at the `start`th character.
+///
+/// This lets long transcripts be paged through without transferring the whole thing. The slice
+/// is clamped to the end of the history, and is empty if `start` is out of range.
+///
+/// # Note
+/// The caller must free the returned pointer with [free_rust_ptr].
+#[unsafe(no_mangle)]
+pub fn get_transcript_slice(
+    start: usize,
+    len: usize,
+    slice_len_out: *mut usize,
+) -> *mut ffi::c_void {
+    let span = span!(Level::TRACE, "get_transcript_slice");
+    let _enter = span.enter();
+
+    let slice = transcript_slice(&TRANSCRIPT_HISTORY.blocking_lock(), start, len);
+    debug!("Transcript slice [{start}, {start} + {len}): {slice}");
+
+    match serialize(slice, slice_len_out) {
+        Ok(encoded) => {
+            clear_last_error();
+            encoded
         }
+        Err(e) => {
+            set_last_error(e.to_string());
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Decodes the context, applying the per-request overrides (a serialized `ContextOverrides`,
+/// which may be null if the context should be used as is).
+///
+/// The overrides are applied to the decoded copy, so they only apply to a single call.
+fn decode_context(
+    ctx: *mut ffi::c_void,
+    ctx_len: usize,
+    overrides: *mut ffi::c_void,
+    overrides_len: usize,
+) -> VirgilResult<Context> {
+    let ctx: Context = deserialize(ctx, ctx_len)?;
+    debug!("Context decoded");
+    if overrides.is_null() {
+        return Ok(ctx);
+    }
+
+    let overrides: ContextOverrides = deserialize(overrides, overrides_len)?;
+    debug!("Context overrides decoded: {overrides:?}");
+    Ok(ctx.with_overrides(&overrides))
+}
+
+/// Turns microphone input into text.
+///
+/// The `overrides` (a serialized `ContextOverrides`) only apply to this call, and may be null if
+/// the context should be used as is.
+///
+/// If a session is already running, the request is handled according to the context's
+/// [OverlapPolicy].
+#[unsafe(no_mangle)]
+pub fn transcribe_speech(
+    ctx: *mut ffi::c_void,
+    ctx_len: usize,
+    listen_duration_ms: usize,
+    overrides: *mut ffi::c_void,
+    overrides_len: usize,
+) -> MessageStatus {
+    let span = span!(Level::TRACE, "transcribe_speech");
+    let _enter = span.enter();
+
+    let listen_duration_ms = listen_duration_ms as u64;
+
+    // Decode context
+    let ctx = match decode_context(ctx, ctx_len, overrides, overrides_len) {
+        Ok(ctx) => ctx,
+        Err(e) => {
+            set_last_error(e.to_string());
+            return MessageStatus::Error;
+        }
+    };
+
+    start_session(ctx, "listening", &span, move |ctx, session, span| {
+        start_listening(ctx, listen_duration_ms, session, span)
+    })
+}
+
+/// Starts a session with `start`, handling a session that's already running according to the
+/// context's [OverlapPolicy].
+///
+/// The `action` names the session in errors (e.g. `"listening"`).
+fn start_session(
+    ctx: Context,
+    action: &'static str,
+    span: &Span,
+    start: impl FnOnce(Context, SessionGuard, &Span) -> MessageStatus + Send + 'static,
+) -> MessageStatus {
+    // Only one session's runtime/threads may exist at a time
+    if let Some(session) = SessionGuard::acquire() {
+        return start(ctx, session, span);
+    }
+    match ctx.overlap_policy {
+        OverlapPolicy::Reject => {
+            set_last_error(format!("Unable to start {action}: already listening"));
+            MessageStatus::AlreadyListening
+        }
+        OverlapPolicy::Queue => {
+            let queued_span = span.clone();
+            let spawned = thread::Builder::new()
+                .name("virgil-queued".into())
+                .spawn(move || {
+                    let session = loop {
+                        if let Some(session) = SessionGuard::acquire() {
+                            break session;
+                        }
+                        thread::sleep(STOP_POLL_INTERVAL);
+                    };
+                    debug!("Starting queued session");
+                    // NOTE: Nothing is waiting for the queued call's status, so failures are
+                    // reported as events.
+                    let status = start(ctx.clone(), session, &queued_span);
+                    if status != MessageStatus::Success {
+                        let detail = LAST_ERROR.blocking_lock().clone().unwrap_or_default();
+                        report_status(&ctx, status, detail);
+                    }
+                });
+            if let Err(e) = spawned {
+                set_last_error(format!("Unable to spawn queued session thread: {e}"));
+                return MessageStatus::Error;
+            }
+            info!("Already listening, session queued");
+
+            clear_last_error();
+            MessageStatus::Queued
+        }
+        OverlapPolicy::ReplaceLatest => {
+            info!("Already listening, replacing the current session");
+            if let Err(e) = stop_session() {
+                set_last_error(format!("Unable to replace the current session: {e}"));
+                return MessageStatus::Error;
+            }
+            let Some(session) = SessionGuard::acquire() else {
+                set_last_error(format!("Unable to start {action}: already listening"));
+                return MessageStatus::AlreadyListening;
+            };
+            start(ctx, session, span)
+        }
+    }
+}
+
+/// Starts a listening session: loads the model, starts the microphone, and spawns the processing
+/// thread.
+fn start_listening(
+    ctx: Context,
+    listen_duration_ms: u64,
+    session: SessionGuard,
+    span: &Span,
+) -> MessageStatus {
+    // Init tokio runtime
+    let rt = match runtime::Builder::new_multi_thread()
+        .enable_all()
+        .thread_name("virgil-runtime")
+        .build()
+    {
+        Ok(rt) => rt,
+        Err(e) => {
+            set_last_error(format!("Unable to start runtime: {e}"));
+            return MessageStatus::Error;
+        }
+    };
+
+    // Setup channels for communication
+    let (input_audio_tx, input_audio_rx) = mpsc::channel::<Vec<f32>>(AUDIO_CHANNEL_CAPACITY);
+
+    // Init `Whisper` model
+    let (model, _) = match take_or_load_model(&ctx, false) {
+        Ok(model) => model,
+        Err(e) => {
+            set_last_error(format!("Unable to load model: {e}"));
+            return MessageStatus::Error;
+        }
+    };
+
+    // Initalize microphone
+    let (mic, device_info) = match Microphone::spawn(
+        input_audio_tx.clone(),
+        ctx.input_channel,
+        ctx.sample_formats.clone(),
+    ) {
+        Ok(mic) => mic,
+        Err(e) => {
+            // NOTE: The model is kept loaded for the next session.
+            *MODEL.blocking_lock() = Some(model);
+            set_last_error(format!("Unable to initialize microphone: {e}"));
+            return MessageStatus::Error;
+        }
+    };
+    let sample_rate = device_info.sample_rate as usize;
+    *INPUT_DEVICE.blocking_lock() = Some(device_info);
+    *RUN.blocking_lock() = true;
+
+    // Start listening to the microphone
+    //
+    // NOTE: The stream is stored globally so it can be paused/resumed, and is dropped once
+    // processing stops.
+    let listener_span = span!(parent: span, Level::TRACE, "listener");
+    let listener_ctx = ctx.clone();
+    rt.spawn(
+        async move {
+            MIC_PAUSED.store(false, Ordering::SeqCst);
+            if let Err(e) = mic.play() {
+                // NOTE: Processing stops once the microphone (and its sender) is dropped.
+                report_error(
+                    &listener_ctx,
+                    format!("Failed to start listening to mic: {e}"),
+                );
+                *LISTENING_STATE.lock().await = ListeningState::Error;
+                return;
+            }
+            info!("Listening to microphone...");
+            *MIC.lock().await = Some(mic);
+        }
+        .instrument(listener_span),
+    );
+
+    info!("Processing microphone input...");
+    *WAKE_WORDS.blocking_lock() = session_wake_words(&ctx);
+    if let Err(e) = spawn_process_thread(
+        ctx,
+        model,
+        rt,
+        input_audio_rx,
+        sample_rate,
+        listen_duration_ms,
+        session,
+        span,
+    ) {
+        set_last_error(format!("Unable to spawn processing thread: {e}"));
+        return MessageStatus::Error;
+    }
+
+    clear_last_error();
+    MessageStatus::Success
+}
+
+/// Feeds a WAV file through the live transcription pipeline, as if it were microphone input.
+///
+/// The file is read in real-time chunks, so the `Context` settings (windowing, VAD, etc.) behave
+/// the same as they do live. This is meant for reproducing live bugs from a recording.
+///
+/// The `overrides` (a serialized `ContextOverrides`) only apply to this call, and may be null if
+/// the context should be used as is.
+///
+/// If a session is already running, the request is handled according to the context's
+/// [OverlapPolicy].
+#[unsafe(no_mangle)]
+pub fn replay_file(
+    ctx: *mut ffi::c_void,
+    ctx_len: usize,
+    wav_path: *mut ffi::c_void,
+    wav_path_len: usize,
+    overrides: *mut ffi::c_void,
+    overrides_len: usize,
+) -> MessageStatus {
+    let span = span!(Level::TRACE, "replay_file");
+    let _enter = span.enter();
+
+    // Decode arguments
+    let ctx = match decode_context(ctx, ctx_len, overrides, overrides_len) {
+        Ok(ctx) => ctx,
+        Err(e) => {
+            set_last_error(e.to_string());
+            return MessageStatus::Error;
+        }
+    };
+    let wav_path: String = match deserialize(wav_path, wav_path_len) {
+        Ok(wav_path) => wav_path,
+        Err(e) => {
+            set_last_error(e.to_string());
+            return MessageStatus::Error;
+        }
+    };
+    debug!("Replay arguments decoded: {wav_path}");
+
+    start_session(ctx, "replay", &span, move |ctx, session, span| {
+        start_replay(ctx, &wav_path, session, span)
+    })
+}
+
+/// Starts a replay session: reads the recording, loads the model, and spawns the tasks that feed
+/// the recording to the processing thread.
+fn start_replay(ctx: Context, wav_path: &str, session: SessionGuard, span: &Span) -> MessageStatus {
+    // Read recording
+    let (audio_data, sample_rate) = match read_wav(wav_path) {
+        Ok(wav) => wav,
+        Err(e) => {
+            set_last_error(e.to_string());
+            return MessageStatus::Error;
+        }
+    };
+    info!(
+        "Replaying {} ms of audio from `{wav_path}`",
+        samples_to_ms(audio_data.len(), sample_rate)
+    );
+
+    // Init tokio runtime
+    let rt = match runtime::Builder::new_multi_thread()
+        .enable_all()
+        .thread_name("virgil-runtime")
+        .build()
+    {
+        Ok(rt) => rt,
+        Err(e) => {
+            set_last_error(format!("Unable to start runtime: {e}"));
+            return MessageStatus::Error;
+        }
+    };
+    let (input_audio_tx, input_audio_rx) = mpsc::channel::<Vec<f32>>(AUDIO_CHANNEL_CAPACITY);
+
+    // Init `Whisper` model
+    let (model, _) = match take_or_load_model(&ctx, false) {
+        Ok(model) => model,
+        Err(e) => {
+            set_last_error(format!("Unable to load model: {e}"));
+            return MessageStatus::Error;
+        }
+    };
+    *RUN.blocking_lock() = true;
+
+    // Feed the recording in real-time chunks, like the microphone would
+    let replay_span = span!(parent: span, Level::TRACE, "replay");
+    rt.spawn(
+        async move {
+            feed_replay(&audio_data, sample_rate, &input_audio_tx).await;
+
+            // NOTE: The sender is kept alive so the end of the file isn't reported as a
+            // disconnected microphone.
+            std::future::pending::<()>().await;
+        }
+        .instrument(replay_span),
+    );
+
+    // NOTE: The file's rate is exact, so a rate detected from the microphone doesn't apply.
+    DETECTED_SAMPLE_RATE.store(0, Ordering::SeqCst);
+    *WAKE_WORDS.blocking_lock() = session_wake_words(&ctx);
+    if let Err(e) = spawn_process_thread(
+        ctx,
+        model,
+        rt,
+        input_audio_rx,
+        sample_rate,
+        REPLAY_LISTEN_DURATION_MS,
+        session,
+        span,
+    ) {
+        set_last_error(format!("Unable to spawn processing thread: {e}"));
+        return MessageStatus::Error;
+    }
+
+    clear_last_error();
+    MessageStatus::Success
+}
+
+/// Feeds the recording to the processing loop in real-time chunks, like the microphone would,
+/// then stops the session once every chunk has been processed.
+async fn feed_replay(
+    audio_data: &[f32],
+    sample_rate: usize,
+    input_audio_tx: &mpsc::Sender<Vec<f32>>,
+) {
+    let chunk_len = ms_to_samples(REPLAY_CHUNK_MS, sample_rate).max(1);
+    for chunk in audio_data.chunks(chunk_len) {
+        if !*RUN.lock().await {
+            break;
+        }
+        if input_audio_tx.send(chunk.to_vec()).await.is_err() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(REPLAY_CHUNK_MS as u64)).await;
+    }
+    info!("Replay finished");
+
+    // Wait for the remaining chunks to be processed
+    while input_audio_tx.capacity() < input_audio_tx.max_capacity() {
+        tokio::time::sleep(Duration::from_millis(REPLAY_CHUNK_MS as u64)).await;
+    }
+    *RUN.lock().await = false;
+}
+
+/// Transcribes several WAV files concurrently, on a [TranscriberPool] of up to
+/// [Context::max_pool_workers] workers.
+///
+/// Long recordings are split into overlapping 30s chunks (see [chunk_audio]), which are
+/// transcribed concurrently as well.
+///
+/// The pool loads its own copy of the model, so this can run alongside a listening session. Each
+/// file's transcript is sent to Dart (in the order the paths were given) once every file has been
+/// transcribed; if [Context::source_mode] is [SourceMode::Mix], the files are mixed and a single
+/// transcript is sent instead.
+///
+/// At most [Context::max_audio_sources] files can be transcribed at once.
+///
+/// The `overrides` (a serialized `ContextOverrides`) only apply to this call, and may be null if
+/// the context should be used as is.
+#[unsafe(no_mangle)]
+pub fn transcribe_files(
+    ctx: *mut ffi::c_void,
+    ctx_len: usize,
+    wav_paths: *mut ffi::c_void,
+    wav_paths_len: usize,
+    overrides: *mut ffi::c_void,
+    overrides_len: usize,
+) -> MessageStatus {
+    let span = span!(Level::TRACE, "transcribe_files");
+    let _enter = span.enter();
+
+    // Decode arguments
+    let ctx = match decode_context(ctx, ctx_len, overrides, overrides_len) {
+        Ok(ctx) => ctx,
+        Err(e) => {
+            set_last_error(e.to_string());
+            return MessageStatus::Error;
+        }
+    };
+    let wav_paths: Vec<String> = match deserialize(wav_paths, wav_paths_len) {
+        Ok(wav_paths) => wav_paths,
+        Err(e) => {
+            set_last_error(e.to_string());
+            return MessageStatus::Error;
+        }
+    };
+    debug!("WAV paths decoded: {wav_paths:?}");
+    if ctx.max_audio_sources > 0 && wav_paths.len() > ctx.max_audio_sources {
+        set_last_error(format!(
+            "Too many audio sources: {} (at most {} are allowed)",
+            wav_paths.len(),
+            ctx.max_audio_sources
+        ));
+        return MessageStatus::Error;
+    }
+
+    // Read recordings
+    let mut sources = Vec::with_capacity(wav_paths.len());
+    for wav_path in &wav_paths {
+        match read_wav(wav_path) {
+            Ok((audio_data, sample_rate)) => {
+                sources.push(resample_to_16k(&audio_data, sample_rate));
+            }
+            Err(e) => {
+                set_last_error(format!("Unable to read `{wav_path}`: {e}"));
+                return MessageStatus::Error;
+            }
+        }
+    }
+    let (labels, recordings) = route_sources(ctx.source_mode, wav_paths, &sources);
+
+    let num_chunks = recordings.iter().map(Vec::len).sum::<usize>();
+    let pool = match TranscriberPool::new(&ctx, num_chunks.min(ctx.max_pool_workers)) {
+        Ok(pool) => pool,
+        Err(e) => {
+            set_last_error(format!("Unable to start transcriber pool: {e}"));
+            return MessageStatus::Error;
+        }
+    };
+
+    let batch_span = span!(parent: &span, Level::TRACE, "batch");
+    let spawned = thread::Builder::new()
+        .name("virgil-batch".into())
+        .spawn(move || {
+            let _enter = batch_span.enter();
+            let pool = &pool;
+            let results = block_on(join_all(recordings.into_iter().map(|chunks| async move {
+                let chunks = join_all(chunks.into_iter().map(
+                    |(offset_ms, audio_data)| async move {
+                        pool.submit(audio_data)
+                            .await
+                            .map(|segments| (offset_ms, segments))
+                    },
+                ))
+                .await;
+                chunks
+                    .into_iter()
+                    .collect::<VirgilResult<Vec<_>>>()
+                    .map(merge_chunk_segments)
+            })));
+            for (wav_path, segments) in labels.iter().zip(results) {
+                let segments = match segments {
+                    Ok(segments) => segments,
+                    Err(e) => {
+                        error!("Unable to transcribe `{wav_path}`: {e}");
+                        continue;
+                    }
+                };
+                let transcript = Transcript::new(segments, None, &ctx);
+                if ctx.no_speech_events && transcript.clean.is_empty() {
+                    send_event(&ctx, Event::NoSpeech);
+                    debug!("No speech recognized in `{wav_path}`");
+                    continue;
+                }
+                if transcript.is_low_confidence(&ctx) {
+                    info!(
+                        "Suppressing low-confidence transcript of `{wav_path}` ({:.2})",
+                        transcript.confidence
+                    );
+                    send_event(
+                        &ctx,
+                        Event::LowConfidence {
+                            text: transcript.clean,
+                            confidence: transcript.confidence,
+                        },
+                    );
+                    continue;
+                }
+                append_transcript(&mut TRANSCRIPT_HISTORY.blocking_lock(), &transcript.clean);
+                if let Err(e) = send_text_to_dart(transcript.clean) {
+                    error!("Unable to send text to Dart: {e}");
+                }
+                debug!("Transcript of `{wav_path}` sent");
+            }
+        });
+    if let Err(e) = spawned {
+        set_last_error(format!("Unable to spawn batch thread: {e}"));
+        return MessageStatus::Error;
+    }
+
+    clear_last_error();
+    MessageStatus::Success
+}
+
+/// A recording split into chunks (see [chunk_audio]), with their offsets.
+type ChunkedRecording = Vec<(u64, Vec<f32>)>;
+
+/// Splits the (16kHz) sources into the chunked recordings to transcribe, according to the source
+/// mode, returning each recording with the WAV path(s) it came from.
+fn route_sources(
+    source_mode: SourceMode,
+    wav_paths: Vec<String>,
+    sources: &[Vec<f32>],
+) -> (Vec<String>, Vec<ChunkedRecording>) {
+    match source_mode {
+        SourceMode::PerSource => wav_paths
+            .into_iter()
+            .zip(sources.iter().map(|source| chunk_audio(source)))
+            .unzip(),
+        SourceMode::Mix => {
+            debug!("Mixing {} sources", sources.len());
+            let label = wav_paths.join(" + ");
+            (vec![label], vec![chunk_audio(&mix_sources(sources))])
+        }
+    }
+}
+
+/// Transcribes a WAV file, returning its transcript (as an encoded [Event::Transcript]) instead of
+/// sending it to Dart, so files can be transcribed without a port (e.g. in tests or batch jobs).
+///
+/// The loaded model is used (see [load_model]), so this can't run while listening. Long
+/// recordings are split into overlapping 30s chunks (see [chunk_audio]), which are transcribed in
+/// order. A null pointer is returned if the file can't be transcribed.
+///
+/// The `overrides` (a serialized `ContextOverrides`) only apply to this call, and may be null if
+/// the context should be used as is.
+///
+/// # Note
+/// The caller must free the returned pointer with [free_rust_ptr].
+#[unsafe(no_mangle)]
+pub fn transcribe_file(
+    ctx: *mut ffi::c_void,
+    ctx_len: usize,
+    wav_path: *mut ffi::c_void,
+    wav_path_len: usize,
+    overrides: *mut ffi::c_void,
+    overrides_len: usize,
+    transcript_len_out: *mut usize,
+) -> *mut ffi::c_void {
+    let span = span!(Level::TRACE, "transcribe_file");
+    let _enter = span.enter();
+
+    let Some(_session) = SessionGuard::acquire() else {
+        set_last_error("Unable to transcribe file: already listening".into());
+        return ptr::null_mut();
+    };
+
+    // Decode arguments
+    let ctx = match decode_context(ctx, ctx_len, overrides, overrides_len) {
+        Ok(ctx) => ctx,
+        Err(e) => {
+            set_last_error(e.to_string());
+            return ptr::null_mut();
+        }
+    };
+    let wav_path: String = match deserialize(wav_path, wav_path_len) {
+        Ok(wav_path) => wav_path,
+        Err(e) => {
+            set_last_error(e.to_string());
+            return ptr::null_mut();
+        }
+    };
+    debug!("WAV path decoded: {wav_path}");
+
+    let transcript = match file_transcript(&ctx, &wav_path) {
+        Ok(transcript) => transcript,
+        Err(e) => {
+            set_last_error(format!("Unable to transcribe `{wav_path}`: {e}"));
+            return ptr::null_mut();
+        }
+    };
+    debug!("Transcript of `{wav_path}`: {}", transcript.clean);
+
+    match serialize(Event::Transcript(transcript), transcript_len_out) {
+        Ok(encoded) => {
+            clear_last_error();
+            encoded
+        }
+        Err(e) => {
+            set_last_error(e.to_string());
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Transcribes the WAV file chunk by chunk, putting the model back once it's done.
+fn file_transcript(ctx: &Context, wav_path: &str) -> VirgilResult<Transcript> {
+    let (audio_data, sample_rate) = read_wav(wav_path)?;
+    let audio_data = resample_to_16k(&audio_data, sample_rate);
+
+    let (model, _) = take_or_load_model(ctx, false)?;
+    let mut transcriber = WhisperTranscriber::new(model, ctx);
+    let transcript = transcribe_chunks(&mut transcriber, &audio_data, ctx);
+    *MODEL.blocking_lock() = Some(transcriber.into_model());
+    transcript
+}
+
+/// Transcribes a WAV file into words, with the time each one is spoken (e.g. for karaoke-style
+/// highlighting).
+///
+/// The loaded model is used (see [load_model]), so this can't run while listening. A null
+/// pointer is returned if the file can't be transcribed.
+///
+/// The `overrides` (a serialized `ContextOverrides`) only apply to this call, and may be null if
+/// the context should be used as is.
+///
+/// # Note
+/// The caller must free the returned pointer with [free_rust_ptr].
+#[unsafe(no_mangle)]
+pub fn transcribe_words(
+    ctx: *mut ffi::c_void,
+    ctx_len: usize,
+    wav_path: *mut ffi::c_void,
+    wav_path_len: usize,
+    overrides: *mut ffi::c_void,
+    overrides_len: usize,
+    words_len_out: *mut usize,
+) -> *mut ffi::c_void {
+    let span = span!(Level::TRACE, "transcribe_words");
+    let _enter = span.enter();
+
+    let Some(_session) = SessionGuard::acquire() else {
+        set_last_error("Unable to transcribe words: already listening".into());
+        return ptr::null_mut();
+    };
+
+    // Decode arguments
+    let ctx = match decode_context(ctx, ctx_len, overrides, overrides_len) {
+        Ok(ctx) => ctx,
+        Err(e) => {
+            set_last_error(e.to_string());
+            return ptr::null_mut();
+        }
+    };
+    let wav_path: String = match deserialize(wav_path, wav_path_len) {
+        Ok(wav_path) => wav_path,
+        Err(e) => {
+            set_last_error(e.to_string());
+            return ptr::null_mut();
+        }
+    };
+    debug!("WAV path decoded: {wav_path}");
+
+    let words = match file_words(&ctx, &wav_path) {
+        Ok(words) => words,
+        Err(e) => {
+            set_last_error(format!("Unable to transcribe `{wav_path}`: {e}"));
+            return ptr::null_mut();
+        }
+    };
+    debug!("{} words transcribed", words.len());
+
+    match serialize(words, words_len_out) {
+        Ok(encoded) => {
+            clear_last_error();
+            encoded
+        }
+        Err(e) => {
+            set_last_error(e.to_string());
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Transcribes the WAV file into timed words, putting the model back once it's done.
+fn file_words(ctx: &Context, wav_path: &str) -> VirgilResult<Vec<WordTiming>> {
+    let (audio_data, sample_rate) = read_wav(wav_path)?;
+    let audio_data = resample_to_16k(&audio_data, sample_rate);
+
+    let (model, _) = take_or_load_model(ctx, false)?;
+    let mut transcriber = WhisperTranscriber::new(model, ctx);
+    let tokens = transcriber.transcribe_tokens(&audio_data);
+    *MODEL.blocking_lock() = Some(transcriber.into_model());
+    Ok(group_words(&tokens?))
+}
+
+/// Takes the cached model if it was loaded from the context's model path, loading it otherwise.
+///
+/// Returns the model and whether it was (re)loaded. The model is always reloaded if
+/// `force_reload` is set.
+///
+/// The model path is resolved against [Context::asset_dir], and must exist.
+fn take_or_load_model(ctx: &Context, force_reload: bool) -> VirgilResult<(ModelHandle, bool)> {
+    let model_path = ctx.resolved_model_path();
+    let mut loaded_model = LOADED_MODEL.blocking_lock();
+    let same_model = loaded_model
+        .as_ref()
+        .is_some_and(|info| info.path == model_path && info.use_gpu == ctx.use_gpu);
+    if same_model
+        && !force_reload
+        && let Some(model) = MODEL.blocking_lock().take()
+    {
+        debug!("Reusing loaded model: {model_path}");
+        return Ok((model, false));
+    }
+
+    check_model_path(&model_path)?;
+    if force_reload {
+        evict_model_context(&model_path);
+    }
+    let (model, model_info) = init_model(
+        &model_path,
+        ctx.warmup_model.then_some(ctx.transcription),
+        ctx.use_gpu,
+        ctx.mmap_model,
+    )?;
+    *loaded_model = Some(model_info);
+    Ok((model, true))
+}
+
+/// Spawns the thread that processes the audio received from `input_audio_rx`.
+///
+/// The runtime and session are kept alive until processing stops (see [run_process]).
+///
+/// If the thread can't be spawned, the session is stopped and the error is returned.
+#[allow(clippy::too_many_arguments)]
+fn spawn_process_thread(
+    ctx: Context,
+    model: ModelHandle,
+    rt: Runtime,
+    mut input_audio_rx: mpsc::Receiver<Vec<f32>>,
+    sample_rate: usize,
+    listen_duration_ms: u64,
+    session: SessionGuard,
+    parent_span: &Span,
+) -> std::io::Result<()> {
+    let process_span = span!(parent: parent_span, Level::TRACE, "process");
+    let spawned = thread::Builder::new()
+        .name("virgil-process".into())
+        .spawn(move || {
+            let _session = session;
+            let mut model = WhisperTranscriber::new(model, &ctx);
+            if ctx.streaming {
+                let sink_ctx = ctx.clone();
+                model.set_segment_sink(Arc::new(move |text| {
+                    send_event(&sink_ctx, Event::Partial { text });
+                }));
+            }
+            run_process(
+                &ctx,
+                &mut model,
+                &rt,
+                &mut input_audio_rx,
+                sample_rate,
+                listen_duration_ms,
+                &process_span,
+            );
+            MIC.blocking_lock().take();
+
+            // Keep the model around for the next session
+            *MODEL.blocking_lock() = Some(model.into_model());
+        });
+    let process_thread = match spawned {
+        Ok(process_thread) => process_thread,
+        Err(e) => {
+            // NOTE: The runtime (and the microphone listener on it) was dropped along with the
+            // thread's closure, so only the microphone it may have stored is left to clean up.
+            *RUN.blocking_lock() = false;
+            MIC.blocking_lock().take();
+            *LISTENING_STATE.blocking_lock() = ListeningState::Error;
+            return Err(e);
+        }
+    };
+    *PROCESS_THREAD.blocking_lock() = Some(process_thread);
+    Ok(())
+}
+
+/// Runs [process] (in the given span) until the session ends.
+///
+/// If processing panics, the panic is logged and processing is restarted (up to
+/// [MAX_PROCESS_RESTARTS] times) with the same model and microphone, unless the session was
+/// stopped. The session is only logged as started and stopped once, however often it's restarted.
+fn run_process(
+    ctx: &Context,
+    model: &mut dyn Transcriber,
+    rt: &Runtime,
+    input_audio_rx: &mut mpsc::Receiver<Vec<f32>>,
+    sample_rate: usize,
+    listen_duration_ms: u64,
+    process_span: &Span,
+) {
+    EVENT_LOG
+        .blocking_lock()
+        .push(LifecycleEvent::SessionStarted);
+    let mut restarts = 0;
+    loop {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            rt.block_on(
+                process(
+                    ctx.clone(),
+                    model,
+                    input_audio_rx,
+                    sample_rate,
+                    listen_duration_ms,
+                )
+                .instrument(process_span.clone()),
+            )
+        }));
+        let Err(payload) = result else {
+            break;
+        };
+
+        let msg = payload
+            .downcast_ref::<&str>()
+            .map(|msg| msg.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        error!("Processing panicked: {msg}");
+        *LISTENING_STATE.blocking_lock() = ListeningState::Error;
+        if !*RUN.blocking_lock() || restarts == MAX_PROCESS_RESTARTS {
+            *RUN.blocking_lock() = false;
+            break;
+        }
+        restarts += 1;
+        warn!("Restarting processing ({restarts}/{MAX_PROCESS_RESTARTS})");
+    }
+    EVENT_LOG
+        .blocking_lock()
+        .push(LifecycleEvent::SessionStopped);
+}
+
+/// Stops the microphone, waiting (up to [STOP_TIMEOUT]) for the processing thread to exit.
+///
+/// Once this returns [MessageStatus::Success], a new session can safely be started.
+#[unsafe(no_mangle)]
+pub fn stop_mic() -> MessageStatus {
+    let span = span!(Level::TRACE, "stop_mic");
+    let _enter = span.enter();
+
+    if let Err(e) = stop_session() {
+        set_last_error(e.to_string());
+        return MessageStatus::Error;
+    }
+
+    clear_last_error();
+    MessageStatus::Success
+}
+
+#[derive(Debug, Error)]
+#[error("StopSessionError: {0}")]
+struct StopSessionError(String);
+
+/// Stops the current session, waiting (up to [STOP_TIMEOUT]) for its processing thread to exit.
+fn stop_session() -> VirgilResult<()> {
+    *RUN.blocking_lock() = false;
+    info!("Mic stopped!");
+
+    let Some(process_thread) = PROCESS_THREAD.blocking_lock().take() else {
+        debug!("No processing thread to wait for");
+        return Ok(());
+    };
+    let deadline = Instant::now() + STOP_TIMEOUT;
+    while !process_thread.is_finished() {
+        if Instant::now() >= deadline {
+            // NOTE: The handle is kept so a later call can wait for the thread again.
+            *PROCESS_THREAD.blocking_lock() = Some(process_thread);
+            return Err(StopSessionError(format!(
+                "Processing thread didn't exit within {}s",
+                STOP_TIMEOUT.as_secs()
+            ))
+            .into());
+        }
+        thread::sleep(STOP_POLL_INTERVAL);
+    }
+    if process_thread.join().is_err() {
+        return Err(StopSessionError("Processing thread panicked".into()).into());
+    }
+    debug!("Processing thread exited");
+    Ok(())
+}
+
+/// Processes the audio data (in a loop) by transcibing audio data if wake words are detected.
+///
+/// The `model` can be any [Transcriber]; the live pipeline uses a [WhisperTranscriber].
+async fn process(
+    mut ctx: Context,
+    model: &mut dyn Transcriber,
+    input_audio_rx: &mut mpsc::Receiver<Vec<f32>>,
+    sample_rate: usize,
+    listen_duration_ms: u64,
+) {
+    info!("Processing audio data...");
+
+    // NOTE: Updates meant for a previous session are dropped.
+    LISTEN_CONFIG_UPDATE.lock().await.take();
+
+    let mut detected_time = None;
+    // NOTE: Ignoring wake words (or starting without any) behaves as if one was detected, without
+    // the active listen timeout. Dictation always ignores them.
+    let dictation = ctx.accumulation_mode == AccumulationMode::Dictation;
+    let ignore_wake_words = dictation || ctx.ignore_wake_words;
+    let wake_word_matcher = WakeWordMatcher::new(ctx.wake_word_max_distance);
+    let always_on = ignore_wake_words || WAKE_WORDS.lock().await.is_empty();
+    let mut wake_word_detected = always_on;
+    if always_on {
+        debug!("Ignoring wake words");
+    }
+    set_listening_state(if always_on {
+        ListeningState::Armed
+    } else {
+        ListeningState::Listening
+    })
+    .await;
+    let mut max_window_samples = ctx.listen.max_window_samples(sample_rate);
+    let mut overlap_samples = ctx.listen.overlap_samples(sample_rate);
+    let listen_duration_samples =
+        ms_to_samples(listen_duration_ms as usize, sample_rate) + WINDOW_PADDING_SAMPLES;
+    // NOTE: Dictation windows only end when the utterance does (or it's too long).
+    let mut original_desired_num_samples = if dictation {
+        ctx.listen.max_utterance_samples(sample_rate)
+    } else {
+        listen_duration_samples.min(max_window_samples)
+    };
+    let mut desired_num_samples = original_desired_num_samples;
+    let mut accumulated_audio = Vec::with_capacity(desired_num_samples);
+    let mut endpointer = Endpointer::new(&ctx.listen, sample_rate);
+    let mut agc = ctx.listen.agc_enabled.then(|| Agc::new(&ctx.listen));
+    let mut preroll = PreRoll::new(ctx.listen.preroll_samples(sample_rate));
+    let mut first_word_sent = false;
+    let mut idle_windows = 0;
+    let mut session_samples = 0;
+    let mut throttle = ThrottledSender::new([
+        (
+            ThrottledKind::RawAudio,
+            Duration::from_millis(ctx.raw_audio_interval_ms as u64),
+        ),
+        (
+            ThrottledKind::NoSpeech,
+            Duration::from_millis(ctx.no_speech_interval_ms as u64),
+        ),
+    ]);
+    'listen: while *RUN.lock().await {
+        // Apply listen config updates between windows
+        if let Some(listen) = LISTEN_CONFIG_UPDATE.lock().await.take() {
+            ctx.listen = listen;
+            max_window_samples = ctx.listen.max_window_samples(sample_rate);
+            overlap_samples = ctx.listen.overlap_samples(sample_rate);
+            original_desired_num_samples = if dictation {
+                ctx.listen.max_utterance_samples(sample_rate)
+            } else {
+                listen_duration_samples.min(max_window_samples)
+            };
+            desired_num_samples = if wake_word_detected && !dictation {
+                desired_num_samples.min(max_window_samples)
+            } else {
+                original_desired_num_samples
+            };
+            endpointer = Endpointer::new(&ctx.listen, sample_rate);
+            agc = ctx.listen.agc_enabled.then(|| Agc::new(&ctx.listen));
+            preroll = PreRoll::new(ctx.listen.preroll_samples(sample_rate));
+            info!("Listen config updated: {:?}", ctx.listen);
+        }
+
+        // Wait for the next buffer, then drain any others that are queued
+        //
+        // NOTE: The wait is bounded so a stop (or config update) is noticed even if no audio
+        // arrives.
+        let mut next_audio = Some(
+            match timeout(AUDIO_RECV_TIMEOUT, input_audio_rx.recv()).await {
+                Ok(Some(audio_data)) => Ok(audio_data),
+                Ok(None) => Err(TryRecvError::Disconnected),
+                Err(_) => continue,
+            },
+        );
+        loop {
+            // NOTE: A stop is also checked for between queued buffers, so a backlog isn't
+            // processed (and transcribed) after the session was stopped.
+            if !*RUN.lock().await {
+                break 'listen;
+            }
+            let received = next_audio
+                .take()
+                .unwrap_or_else(|| input_audio_rx.try_recv());
+            let mut audio_data = match received {
+                Ok(audio_data) => audio_data,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    // NOTE: The sender is owned by the microphone stream, so the mic has died.
+                    error!("Microphone disconnected, stopping audio processing");
+                    *RUN.lock().await = false;
+                    set_listening_state(ListeningState::Error).await;
+                    break 'listen;
+                }
+            };
+            session_samples += audio_data.len();
+
+            // Once the user stops speaking, the command can be transcribed right away
+            let speech_state = wake_word_detected.then(|| endpointer.push(&audio_data));
+            let endpoint_reached = speech_state == Some(SpeechState::Endpoint);
+            if endpoint_reached {
+                debug!("End of speech detected");
+                endpointer.reset();
+            }
+
+            // NOTE: Gain is applied after endpointing so the VAD sees the raw energy.
+            if let Some(agc) = &mut agc {
+                agc.process(&mut audio_data);
+            }
+
+            // NOTE: Silence before an utterance is only kept as pre-roll in dictation mode, so
+            // the utterance starts right before the speaker does.
+            if dictation {
+                if speech_state == Some(SpeechState::Silence) {
+                    preroll.push(&audio_data);
+                    continue;
+                }
+                accumulated_audio.extend(preroll.take());
+            }
+
+            let accumulated_samples = accumulated_audio.len();
+            let samples_to_add = audio_data.len();
+            let num_samples = accumulated_samples + samples_to_add;
+
+            // Accumulate audio data until desired length is reached
+            if num_samples < desired_num_samples && !endpoint_reached {
+                accumulated_audio.extend_from_slice(&audio_data);
+                continue;
+            }
+
+            // If more than desired samples, send exact amount then restart accumulation
+            let extra = num_samples.saturating_sub(desired_num_samples);
+            let end_idx = samples_to_add.abs_diff(extra).min(audio_data.len());
+
+            // Send desired number of samples
+            accumulated_audio.extend_from_slice(&audio_data[0..end_idx]);
+            debug!(
+                "Accumulated {} samples ({} ms, {} bytes)",
+                accumulated_audio.len(),
+                samples_to_ms(accumulated_audio.len(), sample_rate),
+                samples_to_bytes(accumulated_audio.len())
+            );
+
+            // Resample the whole window at once (the accumulated audio is at the device's rate)
+            let detected_rate = DETECTED_SAMPLE_RATE.load(Ordering::SeqCst);
+            let window_rate =
+                if ctx.correct_rate_drift && rate_drifted(sample_rate as u32, detected_rate) {
+                    detected_rate as usize
+                } else {
+                    sample_rate
+                };
+            let window = resample_to_16k(&accumulated_audio, window_rate);
+            if ctx.send_raw_audio
+                && throttle
+                    .send(ThrottledKind::RawAudio, || send_audio_to_dart(&window))
+                    .map_err(|e| error!("Unable to send audio to Dart: {e}"))
+                    .unwrap_or_default()
+            {
+                debug!("Raw audio window sent ({} samples)", window.len());
+            }
+
+            // NOTE: The wake words can be updated mid-session (see `set_wake_words`). Clearing
+            // them stops gating on wake words, and setting them starts it again (once any active
+            // listen period ends).
+            if !ignore_wake_words {
+                let has_wake_words = !WAKE_WORDS.lock().await.is_empty();
+                if !has_wake_words && !wake_word_detected {
+                    debug!("Wake words cleared, ignoring wake words");
+                    wake_word_detected = true;
+                    detected_time = None;
+                } else if has_wake_words && wake_word_detected && detected_time.is_none() {
+                    debug!("Wake words set, waiting for a wake word");
+                    wake_word_detected = false;
+                }
+            }
+
+            // Transcribe data
+            if !MIC_PAUSED.load(Ordering::SeqCst) {
+                set_listening_state(if wake_word_detected {
+                    ListeningState::Armed
+                } else {
+                    ListeningState::Listening
+                })
+                .await;
+            }
+            if !wake_word_detected {
+                let detect = idle_windows % ctx.detect_every_n_windows.max(1) == 0;
+                idle_windows += 1;
+
+                // NOTE: The wake words are cloned so the lock isn't held during inference.
+                let wake_word = if detect && !is_speech(&window, ctx.listen.vad_threshold) {
+                    debug!("Silent window, skipping wake word detection");
+                    None
+                } else if detect {
+                    let wake_words = WAKE_WORDS.lock().await.clone();
+                    detect_wake_words(model, &window, &wake_words, wake_word_matcher)
+                        .unwrap_or_else(|e| {
+                            report_error(&ctx, format!("Unable to detect wake words: {e}"));
+                            None
+                        })
+                } else {
+                    debug!("Skipping wake word detection");
+                    None
+                };
+
+                if let Some(wake_word) = wake_word {
+                    info!(
+                        "Wake word detected (words {}..{})",
+                        wake_word.span.start_idx, wake_word.span.end_idx
+                    );
+                    wake_word_detected = true;
+                    idle_windows = 0;
+                    set_listening_state(ListeningState::Armed).await;
+                    EVENT_LOG
+                        .lock()
+                        .await
+                        .push(LifecycleEvent::WakeWordDetected {
+                            word: wake_word.word.clone(),
+                            confidence: wake_word.confidence,
+                        });
+                    if ctx.wake_word_events {
+                        let event = Event::WakeWordDetected {
+                            word: wake_word.word,
+                            confidence: wake_word.confidence,
+                        };
+                        send_event(&ctx, event);
+                    }
+                    detected_time = Some(Instant::now());
+
+                    // Include the audio from right before the window, in case the wake word (or
+                    // command) started there
+                    let preroll_audio = preroll.take();
+                    debug!("Prepending {} samples of pre-roll", preroll_audio.len());
+                    desired_num_samples = (desired_num_samples
+                        + preroll_audio.len()
+                        + ms_to_samples(WAKE_WINDOW_EXTENSION_MS, sample_rate))
+                    .min(max_window_samples);
+                    accumulated_audio.splice(0..0, preroll_audio);
+                    continue;
+                }
+            }
+
+            if wake_word_detected {
+                // NOTE: The window is still transcribed when the active listen period times
+                // out, so the tail of the command isn't dropped.
+                // FIXME: Make duration dynamic!
+                let timed_out = detected_time.is_some_and(|recorded_time| {
+                    Instant::now() - recorded_time
+                        >= Duration::from_secs(ACTIVE_LISTEN_DURATION_SECS)
+                });
+
+                // Send transcript to Dart
+                // NOTE: Silent windows are treated as empty transcripts without running inference.
+                let transcript = if !is_speech(&window, ctx.listen.vad_threshold) {
+                    debug!("Silent window, skipping transcription");
+                    Transcript::new(Vec::new(), None, &ctx)
+                } else {
+                    set_listening_state(ListeningState::Transcribing).await;
+                    // NOTE: A window that fails to transcribe is reported, then treated as if no
+                    // speech was recognized, rather than stopping the session.
+                    let transcript = transcribe(model, &window, &ctx).unwrap_or_else(|e| {
+                        report_error(&ctx, format!("Unable to transcribe window: {e}"));
+                        Transcript::new(Vec::new(), None, &ctx)
+                    });
+                    set_listening_state(ListeningState::Armed).await;
+                    transcript
+                };
+                if ctx.no_speech_events && transcript.clean.is_empty() {
+                    let sent = throttle
+                        .send(ThrottledKind::NoSpeech, || {
+                            dispatch_event(&ctx, Event::NoSpeech)
+                        })
+                        .map_err(|e| error!("Unable to send event to Dart: {e}"))
+                        .unwrap_or_default();
+                    // NOTE: Throttled events are left out so silence doesn't flood the log.
+                    if sent {
+                        EVENT_LOG.lock().await.push(LifecycleEvent::NoSpeech);
+                    }
+                    debug!("No speech recognized");
+                } else if transcript.is_low_confidence(&ctx) {
+                    info!(
+                        "Suppressing low-confidence transcript ({:.2})",
+                        transcript.confidence
+                    );
+                    EVENT_LOG.lock().await.push(LifecycleEvent::LowConfidence {
+                        text: transcript.clean.clone(),
+                        confidence: transcript.confidence,
+                    });
+                    send_event(
+                        &ctx,
+                        Event::LowConfidence {
+                            text: transcript.clean,
+                            confidence: transcript.confidence,
+                        },
+                    );
+                } else {
+                    if ctx.first_word_events
+                        && !first_word_sent
+                        && let Some(word) = transcript.clean.split_whitespace().next()
+                    {
+                        let event = Event::FirstWord {
+                            word: word.to_string(),
+                        };
+                        send_event(&ctx, event);
+                        first_word_sent = true;
+                    }
+                    if !transcript.clean.is_empty() {
+                        EVENT_LOG.lock().await.push(LifecycleEvent::Transcribed {
+                            text: transcript.clean.clone(),
+                        });
+                    }
+                    append_transcript(&mut *TRANSCRIPT_HISTORY.lock().await, &transcript.clean);
+                    if dictation {
+                        if !transcript.clean.is_empty() {
+                            let end_ms = samples_to_ms(
+                                session_samples - (audio_data.len() - end_idx),
+                                sample_rate,
+                            );
+                            let start_ms = end_ms.saturating_sub(samples_to_ms(
+                                accumulated_audio.len(),
+                                sample_rate,
+                            ));
+                            let event = Event::Utterance {
+                                text: transcript.clean,
+                                start_ms,
+                                end_ms,
+                            };
+                            send_event(&ctx, event);
+                            debug!("Utterance sent");
+                        }
+                    } else if ctx.include_raw_transcript || ctx.report_language || ctx.n_best > 1 {
+                        send_event(&ctx, Event::Transcript(transcript));
+                        debug!("Transcript event sent");
+                    } else if !transcript.clean.is_empty() {
+                        if let Err(e) = send_text_to_dart(transcript.clean) {
+                            error!("Unable to send text to Dart: {e}");
+                        }
+                        debug!("Transcript sent");
+                    }
+                }
+
+                // NOTE: The next utterance starts after the pause.
+                if endpoint_reached {
+                    first_word_sent = false;
+                }
+
+                if timed_out {
+                    debug!("Active listen period timed out");
+                    wake_word_detected = false;
+                    first_word_sent = false;
+                    detected_time = None;
+                    set_listening_state(ListeningState::Listening).await;
+                    endpointer.reset();
+                    desired_num_samples = original_desired_num_samples;
+                }
+            }
+
+            // Reset accumulated data and fill with remaining/overflowing samples
+            //
+            // NOTE: Windows that hit the max window size (or dictated utterances that hit the max
+            // utterance size) were cut off mid-speech, so the tail of the window is kept to
+            // stitch it to the next one.
+            let cut_off = if dictation {
+                !endpoint_reached
+            } else {
+                desired_num_samples >= max_window_samples
+            };
+            if cut_off {
+                let keep_from = accumulated_audio.len().saturating_sub(overlap_samples);
+                accumulated_audio.drain(..keep_from);
+                debug!(
+                    "Max window reached, carrying over {} samples",
+                    accumulated_audio.len()
+                );
+            } else {
+                // NOTE: The end of a window without a wake word is kept as pre-roll for the next
+                // one.
+                if !wake_word_detected {
+                    preroll.push(&accumulated_audio);
+                }
+                accumulated_audio.clear();
+                debug!("Accumulated data reset");
+            }
+            accumulated_audio.extend_from_slice(&audio_data[end_idx..]);
+        }
+    }
+
+    // NOTE: Errors are kept so the UI can tell why the session stopped.
+    let mut state = LISTENING_STATE.lock().await;
+    if *state != ListeningState::Error {
+        *state = ListeningState::Idle;
+    }
+}
+
+/// Records the new state of the listening pipeline.
+async fn set_listening_state(state: ListeningState) {
+    let mut current = LISTENING_STATE.lock().await;
+    if *current != state {
+        debug!("Listening state changed: {:?} -> {state:?}", *current);
+        *current = state;
+    }
+}
+
+/// Returns the wake words a new session starts with: the active profile's, or the context's if no
+/// profile is active.
+fn session_wake_words(ctx: &Context) -> Vec<String> {
+    let active_profile = ACTIVE_PROFILE.blocking_lock();
+    active_profile
+        .as_ref()
+        .and_then(|name| WAKE_WORD_PROFILES.blocking_lock().get(name).cloned())
+        .unwrap_or_else(|| ctx.wake_words.clone())
+}
+
+/// Sends the event to Dart, as JSON if it's included in [Context::json_events], logging any error.
+fn send_event(ctx: &Context, event: Event) {
+    if let Err(e) = dispatch_event(ctx, event) {
+        error!("Unable to send event to Dart: {e}");
+    }
+}
+
+/// Logs the error, and reports it to Dart as an [Event::SessionError].
+fn report_error(ctx: &Context, detail: String) {
+    error!("{detail}");
+    report_status(ctx, MessageStatus::Error, detail);
+}
+
+/// Reports the status of a failed session to Dart (see [send_status_to_dart]), as JSON if it's
+/// included in [Context::json_events], logging any error.
+fn report_status(ctx: &Context, status: MessageStatus, detail: String) {
+    let result = if ctx.json_events.session_error {
+        send_json_event_to_dart(Event::SessionError { status, detail })
+    } else {
+        send_status_to_dart(status, detail)
+    };
+    if let Err(e) = result {
+        error!("Unable to send status to Dart: {e}");
+    }
+}
+
+/// Sends the event to Dart in the format the context asks for, returning any error.
+fn dispatch_event(ctx: &Context, event: Event) -> VirgilResult<()> {
+    if ctx.json_events.includes(&event) {
+        send_json_event_to_dart(event)
+    } else {
+        send_event_to_dart(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        f32::consts::TAU,
+        sync::{Mutex as StdMutex, MutexGuard, PoisonError},
+    };
+
+    use bincode::{Decode, Encode};
+    use tracing_subscriber::registry::LookupSpan;
+
+    use super::*;
+    use crate::{
+        messages::Message,
+        port::capture::{Posted, capture_posts},
+        utils::{
+            DEFAULT_MAX_PAYLOAD_BYTES, InputDeviceInfo, ModelInfo, ModelMemoryEstimate, Segment,
+        },
+    };
+
+    /// The sample rate test audio is "captured" at, so windows aren't resampled.
+    const SAMPLE_RATE: usize = 16_000;
+
+    /// Serializes the tests that run a session, since sessions share global state.
+    static SESSION_LOCK: StdMutex<()> = StdMutex::new(());
+
+    /// Locks the session state, and resets it for a new session.
+    fn lock_session() -> MutexGuard<'static, ()> {
+        let guard = SESSION_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        *RUN.blocking_lock() = true;
+        WAKE_WORDS.blocking_lock().clear();
+        WAKE_WORD_PROFILES.blocking_lock().clear();
+        *ACTIVE_PROFILE.blocking_lock() = None;
+        TRANSCRIPT_HISTORY.blocking_lock().clear();
+        EVENT_LOG.blocking_lock().clear();
+        guard
+    }
+
+    /// A transcriber that hears the same text in every window, recording the length of each
+    /// window it's given.
+    struct MockTranscriber {
+        text: String,
+        windows: Vec<usize>,
+
+        /// The listening state while each window was transcribed.
+        states: Vec<ListeningState>,
+
+        /// The confidence of every segment.
+        confidence: f32,
+
+        /// The number of windows whose transcription panics (as if inference crashed) before it
+        /// succeeds.
+        panics: usize,
+
+        /// The number of windows after which the session is stopped (as if by [stop_mic]).
+        stop_after: Option<usize>,
+
+        /// The number of windows after which the wake words are replaced (as if by
+        /// [set_wake_words]), and the new wake words.
+        wake_words_after: Option<(usize, Vec<String>)>,
+
+        /// The number of windows after which the wake words are replaced from another thread
+        /// (through [set_wake_words]) while the window is still being transcribed, as if the
+        /// model was slow, and the new wake words.
+        concurrent_wake_words: Option<(usize, Vec<String>)>,
+
+        /// The number of windows after which the listen config is updated (as if by
+        /// [update_listen_config]), and the new config.
+        listen_config_after: Option<(usize, ListenConfig)>,
+    }
+
+    impl MockTranscriber {
+        fn new(text: &str) -> Self {
+            Self {
+                text: text.into(),
+                windows: Vec::new(),
+                states: Vec::new(),
+                confidence: 1.0,
+                panics: 0,
+                stop_after: None,
+                wake_words_after: None,
+                concurrent_wake_words: None,
+                listen_config_after: None,
+            }
+        }
+
+        fn with_confidence(self, confidence: f32) -> Self {
+            Self { confidence, ..self }
+        }
+
+        fn panicking(self, panics: usize) -> Self {
+            Self { panics, ..self }
+        }
+
+        fn updating_listen_config_after(self, windows: usize, listen: ListenConfig) -> Self {
+            Self {
+                listen_config_after: Some((windows, listen)),
+                ..self
+            }
+        }
+
+        fn setting_wake_words_concurrently(self, windows: usize, wake_words: &[&str]) -> Self {
+            let wake_words = wake_words.iter().map(|word| word.to_string()).collect();
+            Self {
+                concurrent_wake_words: Some((windows, wake_words)),
+                ..self
+            }
+        }
+
+        fn setting_wake_words_after(self, windows: usize, wake_words: &[&str]) -> Self {
+            let wake_words = wake_words.iter().map(|word| word.to_string()).collect();
+            Self {
+                wake_words_after: Some((windows, wake_words)),
+                ..self
+            }
+        }
+
+        fn stopping_after(self, windows: usize) -> Self {
+            Self {
+                stop_after: Some(windows),
+                ..self
+            }
+        }
+    }
+
+    impl Transcriber for MockTranscriber {
+        fn transcribe(&mut self, audio_data: &[f32]) -> VirgilResult<Vec<Segment>> {
+            self.windows.push(audio_data.len());
+            self.states.push(*LISTENING_STATE.try_lock().unwrap());
+            if self.windows.len() <= self.panics {
+                panic!("Inference crashed");
+            }
+            if self.stop_after == Some(self.windows.len()) {
+                // NOTE: This runs inside the session's runtime, so it can't block on the lock.
+                *RUN.try_lock().unwrap() = false;
+            }
+            if let Some((windows, wake_words)) = &self.wake_words_after
+                && *windows == self.windows.len()
+            {
+                *WAKE_WORDS.try_lock().unwrap() = wake_words.clone();
+            }
+            if let Some((windows, listen)) = &self.listen_config_after
+                && *windows == self.windows.len()
+            {
+                *LISTEN_CONFIG_UPDATE.try_lock().unwrap() = Some(listen.clone());
+            }
+            if let Some((windows, wake_words)) = &self.concurrent_wake_words
+                && *windows == self.windows.len()
+            {
+                let wake_words = wake_words.clone();
+                let (status_tx, status_rx) = std::sync::mpsc::channel();
+                thread::spawn(move || {
+                    let mut wake_words_len = 0;
+                    let encoded = serialize(wake_words, &mut wake_words_len).unwrap();
+                    let status = set_wake_words(encoded, wake_words_len);
+                    free_rust_ptr(encoded, wake_words_len);
+                    status_tx.send(status).unwrap();
+                });
+
+                // NOTE: This window is still being transcribed, so the update would time out if
+                // the wake words were locked during inference.
+                let status = status_rx.recv_timeout(Duration::from_secs(5));
+                assert_eq!(status, Ok(MessageStatus::Success));
+            }
+            Ok(vec![Segment {
+                text: self.text.clone(),
+                start_ms: 0,
+                end_ms: samples_to_ms(audio_data.len(), SAMPLE_RATE),
+                confidence: self.confidence,
+            }])
+        }
+    }
+
+    /// A tone loud enough to be considered speech, `ms` long.
+    fn speech(ms: usize) -> Vec<f32> {
+        (0..ms_to_samples(ms, SAMPLE_RATE))
+            .map(|i| 0.5 * (TAU * 440.0 * i as f32 / SAMPLE_RATE as f32).sin())
+            .collect()
+    }
+
+    /// A receiver holding the buffers, whose sender is dropped (as if the microphone was
+    /// disconnected once they were captured).
+    fn audio_receiver(buffers: Vec<Vec<f32>>) -> mpsc::Receiver<Vec<f32>> {
+        let (input_audio_tx, input_audio_rx) = mpsc::channel(buffers.len().max(1));
+        for buffer in buffers {
+            input_audio_tx.try_send(buffer).unwrap();
+        }
+        input_audio_rx
+    }
+
+    /// Runs a session over the buffers, which ends once they run out (as if the microphone was
+    /// disconnected).
+    fn run_session(
+        ctx: Context,
+        model: &mut MockTranscriber,
+        buffers: Vec<Vec<f32>>,
+        listen_duration_ms: u64,
+    ) {
+        let mut input_audio_rx = audio_receiver(buffers);
+        let rt = runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        rt.block_on(process(
+            ctx,
+            model,
+            &mut input_audio_rx,
+            SAMPLE_RATE,
+            listen_duration_ms,
+        ));
+    }
+
+    /// A context for a session without wake words.
+    fn test_context() -> Context {
+        Context::new(String::new(), Vec::new())
+    }
+
+    /// Logs an error with the library's subscriber installed for this test only, returning whether
+    /// the subscriber saw it.
+    fn log_error_with_scoped_subscriber() -> bool {
+        let _logs = tracing::subscriber::set_default(logs_subscriber(Level::DEBUG));
+        let before = ERROR_COUNT.load(Ordering::Relaxed);
+        error!("Scoped log subscriber test");
+        ERROR_COUNT.load(Ordering::Relaxed) > before
+    }
+
+    #[test]
+    fn scoped_logs_can_be_set_up() {
+        // NOTE: Resetting the session state clears the error count.
+        let _session = lock_session();
+        assert!(log_error_with_scoped_subscriber());
+    }
+
+    #[test]
+    fn reset_session_state_clears_history_events_and_errors() {
+        let _session = lock_session();
+        append_transcript(
+            &mut TRANSCRIPT_HISTORY.blocking_lock(),
+            "Turn on the lights.",
+        );
+        EVENT_LOG
+            .blocking_lock()
+            .push(LifecycleEvent::SessionStarted);
+        ERROR_COUNT.store(3, Ordering::Relaxed);
+
+        assert_eq!(reset_session_state(), MessageStatus::Success);
+        assert!(TRANSCRIPT_HISTORY.blocking_lock().is_empty());
+        assert!(EVENT_LOG.blocking_lock().entries().is_empty());
+        assert_eq!(ERROR_COUNT.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn scoped_logs_can_be_set_up_again() {
+        let _session = lock_session();
+        assert!(log_error_with_scoped_subscriber());
+        assert!(log_error_with_scoped_subscriber());
+    }
+
+    #[test]
+    fn get_audio_duration_ms_rounds_to_nearest() {
+        assert_eq!(get_audio_duration_ms(48_000, 48_000), 1_000);
+        assert_eq!(get_audio_duration_ms(23, 48_000), 0);
+        assert_eq!(get_audio_duration_ms(24, 48_000), 1);
+        assert_eq!(get_audio_duration_ms(48_000, 0), 0);
+    }
+
+    #[test]
+    fn mock_transcriber_drives_pipeline() {
+        let _session = lock_session();
+        let mut model = MockTranscriber::new(" Turn on the lights.");
+        run_session(test_context(), &mut model, vec![speech(1000); 4], 1000);
+
+        // NOTE: Each window is the listen duration (plus padding), with the overflow carried
+        // into the next one.
+        let window_samples = ms_to_samples(1000, SAMPLE_RATE) + WINDOW_PADDING_SAMPLES;
+        assert_eq!(model.windows, vec![window_samples; 3]);
+        assert_eq!(
+            *TRANSCRIPT_HISTORY.blocking_lock(),
+            "Turn on the lights. Turn on the lights. Turn on the lights."
+        );
+        assert!(!*RUN.blocking_lock());
+        assert_eq!(*LISTENING_STATE.blocking_lock(), ListeningState::Error);
+    }
+
+    #[test]
+    fn mock_transcriber_detects_wake_word_before_transcribing() {
+        let _session = lock_session();
+        *WAKE_WORDS.blocking_lock() = vec!["hey virgil".into()];
+        let mut model = MockTranscriber::new(" Hey Virgil, turn on the lights.");
+        let ctx = Context::new(String::new(), vec!["hey virgil".into()]);
+        run_session(ctx, &mut model, vec![speech(1000); 4], 1000);
+
+        let events = EVENT_LOG.blocking_lock().entries();
+        let detected_idx = events
+            .iter()
+            .position(|entry| {
+                matches!(
+                    &entry.event,
+                    LifecycleEvent::WakeWordDetected { word, .. } if word == "hey virgil"
+                )
+            })
+            .expect("Wake word wasn't detected");
+        let transcribed_idx = events
+            .iter()
+            .position(|entry| matches!(entry.event, LifecycleEvent::Transcribed { .. }))
+            .expect("Command wasn't transcribed");
+        assert!(detected_idx < transcribed_idx);
+    }
+
+    #[test]
+    fn ignored_wake_words_transcribe_every_window() {
+        let _session = lock_session();
+        *WAKE_WORDS.blocking_lock() = vec!["hey virgil".into()];
+        let mut model = MockTranscriber::new(" Turn on the lights.");
+        let ctx = Context {
+            ignore_wake_words: true,
+            ..Context::new(String::new(), vec!["hey virgil".into()])
+        };
+        run_session(ctx, &mut model, vec![speech(1000); 2], 1000);
+
+        assert_eq!(*TRANSCRIPT_HISTORY.blocking_lock(), "Turn on the lights.");
+        assert!(detected_wake_words().is_empty());
+    }
+
+    #[test]
+    fn replayed_recording_goes_through_the_live_pipeline() {
+        let _session = lock_session();
+        let (audio_data, sample_rate) = read_wav("tests/fixtures/speech-16k-mono.wav").unwrap();
+        let mut model = MockTranscriber::new(" Turn on the lights.");
+        let (input_audio_tx, mut input_audio_rx) = mpsc::channel(AUDIO_CHANNEL_CAPACITY);
+        let rt = runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            tokio::join!(
+                feed_replay(&audio_data, sample_rate, &input_audio_tx),
+                process(
+                    test_context(),
+                    &mut model,
+                    &mut input_audio_rx,
+                    sample_rate,
+                    REPLAY_LISTEN_DURATION_MS,
+                ),
+            )
+        });
+
+        assert!(!*RUN.blocking_lock());
+        assert!(!model.windows.is_empty());
+        assert!(model.windows.iter().sum::<usize>() <= audio_data.len());
+        assert!(
+            TRANSCRIPT_HISTORY
+                .blocking_lock()
+                .starts_with("Turn on the lights.")
+        );
+    }
+
+    /// The transcript slice returned by [get_transcript_slice].
+    fn transcript_slice_of_history(start: usize, len: usize) -> String {
+        let mut slice_len = 0;
+        let slice = get_transcript_slice(start, len, &mut slice_len);
+        let decoded = deserialize(slice, slice_len);
+        free_rust_ptr(slice, slice_len);
+        decoded.unwrap()
+    }
+
+    #[test]
+    fn transcript_slices_are_clamped_to_the_history() {
+        let _session = lock_session();
+        *TRANSCRIPT_HISTORY.blocking_lock() = "Café lights on.".into();
+
+        assert_eq!(transcript_slice_of_history(0, 4), "Café");
+        assert_eq!(transcript_slice_of_history(5, 100), "lights on.");
+        assert_eq!(transcript_slice_of_history(15, 1), "");
+        assert_eq!(transcript_slice_of_history(100, 5), "");
+    }
+
+    #[test]
+    fn mock_transcriber_skips_silence() {
+        let _session = lock_session();
+        let mut model = MockTranscriber::new(" Turn on the lights.");
+        run_session(
+            test_context(),
+            &mut model,
+            vec![vec![0.0; SAMPLE_RATE]; 4],
+            1000,
+        );
+
+        assert!(model.windows.is_empty());
+        assert!(TRANSCRIPT_HISTORY.blocking_lock().is_empty());
+    }
+
+    #[test]
+    fn continuous_speech_is_flushed_at_max_window() {
+        let _session = lock_session();
+        let mut ctx = test_context();
+        ctx.listen.max_window_ms = 2_000;
+        ctx.listen.overlap_ms = 500;
+        let mut model = MockTranscriber::new(" Turn on the lights.");
+        run_session(ctx, &mut model, vec![speech(1000); 7], 10_000);
+
+        // NOTE: Each flushed window carries its last 500 ms into the next one, so every window
+        // after the first adds 1.5s of new audio.
+        let max_window_samples = ms_to_samples(2_000, SAMPLE_RATE);
+        assert_eq!(model.windows, vec![max_window_samples; 4]);
+    }
+
+    /// Splits the audio into 100ms buffers, as the microphone would deliver it.
+    fn buffers(audio_data: &[f32]) -> Vec<Vec<f32>> {
+        audio_data
+            .chunks(ms_to_samples(100, SAMPLE_RATE))
+            .map(<[f32]>::to_vec)
+            .collect()
+    }
+
+    #[test]
+    fn window_size_can_be_changed_mid_session() {
+        let _session = lock_session();
+        let listen = ListenConfig {
+            max_window_ms: 1_000,
+            overlap_ms: 0,
+            ..Default::default()
+        };
+        listen.validate().unwrap();
+        let mut model =
+            MockTranscriber::new(" Turn on the lights.").updating_listen_config_after(1, listen);
+
+        // NOTE: Updates are picked up between buffers, so the buffers are sent one at a time (like
+        // a microphone would) rather than all queued up front.
+        let (input_audio_tx, mut input_audio_rx) = mpsc::channel(1);
+        let rt = runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let send_buffers = async move {
+                for buffer in vec![speech(1000); 6] {
+                    input_audio_tx.send(buffer).await.unwrap();
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            };
+            tokio::join!(
+                send_buffers,
+                process(
+                    test_context(),
+                    &mut model,
+                    &mut input_audio_rx,
+                    SAMPLE_RATE,
+                    2_000
+                ),
+            )
+        });
+
+        // NOTE: The first window was accumulated before the update.
+        let (first, rest) = model.windows.split_first().unwrap();
+        assert!(*first > ms_to_samples(2_000, SAMPLE_RATE));
+        assert!(!rest.is_empty());
+        assert!(
+            rest.iter()
+                .all(|&window| window == ms_to_samples(1_000, SAMPLE_RATE)),
+            "{rest:?}"
+        );
+    }
+
+    #[test]
+    fn audio_right_before_the_timeout_is_transcribed() {
+        let _session = lock_session();
+        *WAKE_WORDS.blocking_lock() = vec!["hey virgil".into()];
+        let mut model = MockTranscriber::new(" Hey Virgil, turn on the lights.");
+        let ctx = Context::new(String::new(), vec!["hey virgil".into()]);
+
+        // NOTE: The wake word is detected in the first window (which is padded, so it takes two
+        // buffers), and the rest of the command only arrives once the active listen period is over.
+        let (input_audio_tx, mut input_audio_rx) = mpsc::channel(1);
+        let rt = runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let send_buffers = async move {
+                for buffer in vec![speech(1000); 2] {
+                    input_audio_tx.send(buffer).await.unwrap();
+                }
+                tokio::time::sleep(Duration::from_millis(
+                    ACTIVE_LISTEN_DURATION_SECS * 1000 + 200,
+                ))
+                .await;
+                for buffer in vec![speech(1000); 2] {
+                    input_audio_tx.send(buffer).await.unwrap();
+                }
+            };
+            tokio::join!(
+                send_buffers,
+                process(ctx, &mut model, &mut input_audio_rx, SAMPLE_RATE, 1000),
+            )
+        });
+
+        let entries = EVENT_LOG.blocking_lock().entries();
+        let timestamp_of = |matches: fn(&LifecycleEvent) -> bool| {
+            let entries: Vec<_> = entries
+                .iter()
+                .filter(|entry| matches(&entry.event))
+                .collect();
+            assert_eq!(entries.len(), 1, "{entries:?}");
+            entries[0].timestamp_ms
+        };
+        let detected_ms =
+            timestamp_of(|event| matches!(event, LifecycleEvent::WakeWordDetected { .. }));
+        let transcribed_ms =
+            timestamp_of(|event| matches!(event, LifecycleEvent::Transcribed { .. }));
+        assert!(transcribed_ms - detected_ms >= ACTIVE_LISTEN_DURATION_SECS * 1000);
+        assert_eq!(
+            *TRANSCRIPT_HISTORY.blocking_lock(),
+            "Hey Virgil, turn on the lights."
+        );
+        // NOTE: The command window includes the audio that arrived after the wake word.
+        assert!(*model.windows.last().unwrap() > ms_to_samples(2_000, SAMPLE_RATE));
+    }
+
+    #[test]
+    fn dictation_splits_utterances_at_pauses() {
+        let _session = lock_session();
+        let mut ctx = test_context();
+        ctx.accumulation_mode = AccumulationMode::Dictation;
+        let silence = vec![0.0; ms_to_samples(2_000, SAMPLE_RATE)];
+        let audio_data = [speech(1_000), silence.clone(), speech(1_000), silence].concat();
+        let mut model = MockTranscriber::new(" Turn on the lights.");
+        run_session(ctx, &mut model, buffers(&audio_data), 1000);
+
+        assert_eq!(model.windows.len(), 2);
+        assert_eq!(
+            *TRANSCRIPT_HISTORY.blocking_lock(),
+            "Turn on the lights. Turn on the lights."
+        );
+    }
+
+    /// The words sent as [Event::FirstWord]s.
+    fn posted_first_words(posted: &[Posted]) -> Vec<String> {
+        posted
+            .iter()
+            .filter_map(|posted| match posted.event() {
+                Some(Event::FirstWord { word }) => Some(word),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn first_word_is_sent_once_per_utterance() {
+        let _session = lock_session();
+        let mut ctx = test_context();
+        ctx.first_word_events = true;
+        let mut model = MockTranscriber::new(" Turn on the lights.");
+        let posted = capture_posts(|| run_session(ctx, &mut model, vec![speech(1000); 4], 1000));
+
+        // NOTE: Continuous speech is a single utterance, however many windows it spans.
+        assert!(model.windows.len() > 1);
+        assert_eq!(posted_first_words(&posted), ["Turn"]);
+
+        *RUN.blocking_lock() = true;
+        let mut ctx = test_context();
+        ctx.first_word_events = true;
+        ctx.accumulation_mode = AccumulationMode::Dictation;
+        let silence = vec![0.0; ms_to_samples(2_000, SAMPLE_RATE)];
+        let audio_data = [speech(1_000), silence.clone(), speech(1_000), silence].concat();
+        let mut model = MockTranscriber::new(" Turn on the lights.");
+        let posted = capture_posts(|| run_session(ctx, &mut model, buffers(&audio_data), 1000));
+
+        assert_eq!(model.windows.len(), 2);
+        assert_eq!(posted_first_words(&posted), ["Turn", "Turn"]);
+    }
+
+    #[test]
+    fn dictation_splits_long_utterances_at_max_utterance() {
+        let _session = lock_session();
+        let mut ctx = test_context();
+        ctx.accumulation_mode = AccumulationMode::Dictation;
+        ctx.listen.max_utterance_ms = 2_000;
+        ctx.listen.overlap_ms = 500;
+        let mut model = MockTranscriber::new(" Turn on the lights.");
+        run_session(ctx, &mut model, buffers(&speech(7_000)), 1000);
+
+        // NOTE: Each forced split carries its last 500 ms into the next utterance.
+        let max_utterance_samples = ms_to_samples(2_000, SAMPLE_RATE);
+        assert_eq!(model.windows, vec![max_utterance_samples; 4]);
+    }
+
+    #[test]
+    fn process_returns_promptly_without_audio() {
+        let _session = lock_session();
+        let mut model = MockTranscriber::new(" Turn on the lights.");
+        // NOTE: The sender is kept alive, so the session only ends once it's stopped.
+        let (_input_audio_tx, mut input_audio_rx) = mpsc::channel::<Vec<f32>>(1);
+        let budget = Duration::from_secs(1);
+        let stopper = thread::spawn(move || {
+            thread::sleep(budget);
+            *RUN.blocking_lock() = false;
+        });
+
+        let start = Instant::now();
+        let rt = runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        rt.block_on(process(
+            test_context(),
+            &mut model,
+            &mut input_audio_rx,
+            SAMPLE_RATE,
+            budget.as_millis() as u64,
+        ));
+        let elapsed = start.elapsed();
+        stopper.join().unwrap();
+
+        assert!(
+            elapsed < Duration::from_millis(1200),
+            "Processing took {elapsed:?} to stop"
+        );
+        assert!(model.windows.is_empty());
+    }
+
+    #[test]
+    fn disconnected_microphone_stops_session_with_error() {
+        let _session = lock_session();
+        let mut model = MockTranscriber::new(" Turn on the lights.");
+        // NOTE: The sender is dropped before any audio is sent, as if the microphone died.
+        run_session(test_context(), &mut model, Vec::new(), 60_000);
+
+        assert!(!*RUN.blocking_lock());
+        assert_eq!(*LISTENING_STATE.blocking_lock(), ListeningState::Error);
+        assert!(model.windows.is_empty());
+        *LISTENING_STATE.blocking_lock() = ListeningState::Idle;
+    }
+
+    #[test]
+    fn process_stops_between_queued_buffers() {
+        let _session = lock_session();
+        let mut model = MockTranscriber::new(" Turn on the lights.").stopping_after(1);
+        run_session(test_context(), &mut model, vec![speech(1000); 10], 1000);
+
+        assert_eq!(model.windows.len(), 1);
+        assert_eq!(*TRANSCRIPT_HISTORY.blocking_lock(), "Turn on the lights.");
+    }
+
+    #[test]
+    fn small_chunks_accumulate_into_full_windows() {
+        let _session = lock_session();
+        let mut model = MockTranscriber::new(" Turn on the lights.");
+        let chunk = speech(1000)[..3_000].to_vec();
+        run_session(test_context(), &mut model, vec![chunk; 11], 1000);
+
+        // NOTE: 11 chunks only fill two windows if the samples past the end of the first one are
+        // carried over into the second.
+        let window_samples = ms_to_samples(1000, SAMPLE_RATE) + WINDOW_PADDING_SAMPLES;
+        assert_eq!(11 * 3_000 / window_samples, 2);
+        assert_eq!(model.windows, vec![window_samples; 2]);
+    }
+
+    /// The wake word events posted to Dart.
+    fn posted_wake_words(posted: &[Posted]) -> Vec<String> {
+        posted
+            .iter()
+            .filter_map(|posted| match posted.event() {
+                Some(Event::WakeWordDetected { word, .. }) => Some(word),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn wake_word_event_is_sent_once_per_detection() {
+        let _session = lock_session();
+        *WAKE_WORDS.blocking_lock() = vec!["hey virgil".into()];
+        let mut model = MockTranscriber::new(" Hey Virgil, turn on the lights.");
+        let ctx = Context {
+            wake_word_events: true,
+            ..Context::new(String::new(), vec!["hey virgil".into()])
+        };
+        let posted = capture_posts(|| run_session(ctx, &mut model, vec![speech(1000); 2], 1000));
+
+        assert_eq!(posted_wake_words(&posted), ["hey virgil"]);
+        assert_eq!(detected_wake_words(), ["hey virgil"]);
+    }
+
+    #[test]
+    fn wake_word_events_are_opt_in() {
+        let _session = lock_session();
+        *WAKE_WORDS.blocking_lock() = vec!["hey virgil".into()];
+        let mut model = MockTranscriber::new(" Hey Virgil, turn on the lights.");
+        let ctx = Context::new(String::new(), vec!["hey virgil".into()]);
+        let posted = capture_posts(|| run_session(ctx, &mut model, vec![speech(1000); 2], 1000));
+
+        assert!(posted_wake_words(&posted).is_empty());
+        assert_eq!(detected_wake_words(), ["hey virgil"]);
+    }
+
+    #[test]
+    fn raw_transcript_shows_what_post_processing_changed() {
+        let _session = lock_session();
+        let mut model = MockTranscriber::new(" [MUSIC] Turn on the lights.");
+        let ctx = Context {
+            include_raw_transcript: true,
+            ..test_context()
+        };
+        let posted = capture_posts(|| run_session(ctx, &mut model, vec![speech(1000); 2], 1000));
+
+        let transcripts: Vec<_> = posted
+            .iter()
+            .filter_map(|posted| match posted.event() {
+                Some(Event::Transcript(transcript)) => Some((transcript.raw, transcript.clean)),
+                _ => None,
+            })
+            .collect();
+        assert!(!transcripts.is_empty());
+        for (raw, clean) in transcripts {
+            assert_eq!(raw, " [MUSIC] Turn on the lights.");
+            assert_eq!(clean, "Turn on the lights.");
+        }
+    }
+
+    #[test]
+    fn raw_transcripts_are_opt_in() {
+        let _session = lock_session();
+        let mut model = MockTranscriber::new(" [MUSIC] Turn on the lights.");
+        let posted =
+            capture_posts(|| run_session(test_context(), &mut model, vec![speech(1000); 2], 1000));
+
+        assert!(
+            posted
+                .iter()
+                .any(|posted| posted.text() == Some("Turn on the lights."))
+        );
+        assert!(
+            !posted
+                .iter()
+                .any(|posted| matches!(posted.event(), Some(Event::Transcript(_))))
+        );
+    }
+
+    #[test]
+    fn raw_audio_buffer_matches_the_window() {
+        let _session = lock_session();
+        let mut model = MockTranscriber::new(" Turn on the lights.");
+        let ctx = Context {
+            send_raw_audio: true,
+            ..test_context()
+        };
+        let posted = capture_posts(|| run_session(ctx, &mut model, vec![speech(1000); 3], 1000));
+
+        let buffers: Vec<usize> = posted
+            .iter()
+            .filter_map(|posted| match posted {
+                Posted::Float32(samples) => Some(samples.len()),
+                _ => None,
+            })
+            .collect();
+        assert!(!buffers.is_empty());
+        assert_eq!(buffers, model.windows);
+    }
+
+    /// The number of no-speech events posted to Dart.
+    fn posted_no_speech(posted: &[Posted]) -> usize {
+        posted
+            .iter()
+            .filter(|posted| matches!(posted.event(), Some(Event::NoSpeech)))
+            .count()
+    }
+
+    #[test]
+    fn no_speech_event_is_sent_for_silence() {
+        let _session = lock_session();
+        let mut model = MockTranscriber::new(" Turn on the lights.");
+        let ctx = Context {
+            no_speech_events: true,
+            ..test_context()
+        };
+        let posted =
+            capture_posts(|| run_session(ctx, &mut model, vec![vec![0.0; SAMPLE_RATE]; 2], 1000));
+
+        // NOTE: Repeated no-speech events are throttled, so the silence is only reported once.
+        assert_eq!(posted_no_speech(&posted), 1);
+        assert!(model.windows.is_empty());
+        assert!(!posted.iter().any(|posted| posted.text().is_some()));
+    }
+
+    #[test]
+    fn no_speech_event_is_not_sent_for_speech() {
+        let _session = lock_session();
+        let mut model = MockTranscriber::new(" Turn on the lights.");
+        let ctx = Context {
+            no_speech_events: true,
+            ..test_context()
+        };
+        let posted = capture_posts(|| run_session(ctx, &mut model, vec![speech(1000); 2], 1000));
+
+        assert_eq!(posted_no_speech(&posted), 0);
+        assert!(
+            posted
+                .iter()
+                .any(|posted| posted.text() == Some("Turn on the lights."))
+        );
+    }
+
+    #[test]
+    fn low_confidence_transcript_is_suppressed() {
+        let _session = lock_session();
+        let mut model = MockTranscriber::new(" Turn on the lights.").with_confidence(0.2);
+        let ctx = Context {
+            min_confidence: 0.5,
+            ..test_context()
+        };
+        let posted = capture_posts(|| run_session(ctx, &mut model, vec![speech(1000); 2], 1000));
+
+        let low_confidence: Vec<_> = posted
+            .iter()
+            .filter_map(|posted| match posted.event() {
+                Some(Event::LowConfidence { text, confidence }) => Some((text, confidence)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(low_confidence, [("Turn on the lights.".to_string(), 0.2)]);
+        assert!(!posted.iter().any(|posted| posted.text().is_some()));
+        assert!(TRANSCRIPT_HISTORY.blocking_lock().is_empty());
+        assert!(
+            EVENT_LOG
+                .blocking_lock()
+                .entries()
+                .iter()
+                .any(|entry| matches!(
+                    &entry.event,
+                    LifecycleEvent::LowConfidence { text, .. } if text == "Turn on the lights."
+                ))
+        );
+    }
+
+    /// The wake words detected in the session, in order.
+    fn detected_wake_words() -> Vec<String> {
+        EVENT_LOG
+            .blocking_lock()
+            .entries()
+            .into_iter()
+            .filter_map(|entry| match entry.event {
+                LifecycleEvent::WakeWordDetected { word, .. } => Some(word),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn updated_wake_words_are_used_mid_session() {
+        let _session = lock_session();
+        *WAKE_WORDS.blocking_lock() = vec!["hey virgil".into()];
+        let mut model = MockTranscriber::new(" Okay computer, turn on the lights.")
+            .setting_wake_words_after(1, &["okay computer"]);
+        let ctx = Context::new(String::new(), vec!["hey virgil".into()]);
+        run_session(ctx, &mut model, vec![speech(1000); 4], 1000);
+
+        assert_eq!(detected_wake_words(), ["okay computer"]);
+        assert!(!TRANSCRIPT_HISTORY.blocking_lock().is_empty());
+    }
+
+    #[test]
+    fn listening_state_follows_the_session() {
+        let _session = lock_session();
+        *LISTENING_STATE.blocking_lock() = ListeningState::Idle;
+        *WAKE_WORDS.blocking_lock() = vec!["hey virgil".into()];
+        let mut model = MockTranscriber::new(" Hey Virgil, turn on the lights.").stopping_after(3);
+        let ctx = Context::new(String::new(), vec!["hey virgil".into()]);
+        run_session(ctx, &mut model, vec![speech(1000); 6], 1000);
+
+        // NOTE: The first window is transcribed to detect the wake word, and the following ones
+        // are commands.
+        let (first, rest) = model.states.split_first().unwrap();
+        assert_eq!(*first, ListeningState::Listening);
+        assert!(!rest.is_empty());
+        assert!(
+            rest.iter()
+                .all(|&state| state == ListeningState::Transcribing),
+            "{rest:?}"
+        );
+        // NOTE: The session was stopped (rather than the microphone disconnected), so it's idle.
+        assert_eq!(*LISTENING_STATE.blocking_lock(), ListeningState::Idle);
+    }
+
+    /// The number of windows transcribed while waiting for a wake word that's never spoken,
+    /// detecting on every `n`th window of a session fed with `buffers` seconds of speech.
+    fn detections_every_n_windows(n: usize, buffers: usize) -> usize {
+        *RUN.blocking_lock() = true;
+        *WAKE_WORDS.blocking_lock() = vec!["okay computer".into()];
+        let mut model = MockTranscriber::new(" Turn on the lights.");
+        let mut ctx = Context::new(String::new(), vec!["okay computer".into()]);
+        ctx.detect_every_n_windows = n;
+        run_session(ctx, &mut model, vec![speech(1000); buffers], 1000);
+        model.windows.len()
+    }
+
+    #[test]
+    fn wake_words_are_detected_every_n_windows() {
+        let _session = lock_session();
+        let every_window = detections_every_n_windows(1, 10);
+        assert!(every_window > 3);
+        assert_eq!(detections_every_n_windows(0, 10), every_window);
+        // NOTE: The first window is always checked, then every third one.
+        assert_eq!(detections_every_n_windows(3, 10), every_window.div_ceil(3));
+        assert!(detected_wake_words().is_empty());
+    }
+
+    #[test]
+    fn wake_words_are_updated_during_inference() {
+        let _session = lock_session();
+        *WAKE_WORDS.blocking_lock() = vec!["hey virgil".into()];
+        let mut model = MockTranscriber::new(" Okay computer, turn on the lights.")
+            .setting_wake_words_concurrently(1, &["okay computer"]);
+        let ctx = Context::new(String::new(), vec!["hey virgil".into()]);
+        run_session(ctx, &mut model, vec![speech(1000); 4], 1000);
+
+        assert_eq!(*WAKE_WORDS.blocking_lock(), ["okay computer"]);
+        assert_eq!(detected_wake_words(), ["okay computer"]);
+    }
+
+    /// Adds the wake word profile through the FFI.
+    fn add_profile(name: &str, wake_words: &[&str]) -> MessageStatus {
+        let (mut name_len, mut wake_words_len) = (0, 0);
+        let name = serialize(name.to_string(), &mut name_len).unwrap();
+        let wake_words: Vec<String> = wake_words.iter().map(|word| word.to_string()).collect();
+        let wake_words = serialize(wake_words, &mut wake_words_len).unwrap();
+        let status = add_wake_word_profile(name, name_len, wake_words, wake_words_len);
+        free_rust_ptr(name, name_len);
+        free_rust_ptr(wake_words, wake_words_len);
+        status
+    }
+
+    /// Activates the wake word profile through the FFI.
+    fn activate(name: &str) -> MessageStatus {
+        let mut name_len = 0;
+        let name = serialize(name.to_string(), &mut name_len).unwrap();
+        let status = activate_profile(name, name_len);
+        free_rust_ptr(name, name_len);
+        status
+    }
+
+    #[test]
+    fn wake_word_profiles_can_be_switched() {
+        let _session = lock_session();
+        let ctx = Context::new(String::new(), vec!["hey virgil".into()]);
+        assert_eq!(add_profile("home", &["okay house"]), MessageStatus::Success);
+        assert_eq!(add_profile("car", &["okay car"]), MessageStatus::Success);
+
+        // NOTE: Adding a profile doesn't activate it.
+        assert_eq!(session_wake_words(&ctx), ["hey virgil"]);
+        assert!(WAKE_WORDS.blocking_lock().is_empty());
+
+        assert_eq!(activate("home"), MessageStatus::Success);
+        assert_eq!(*WAKE_WORDS.blocking_lock(), ["okay house"]);
+        assert_eq!(session_wake_words(&ctx), ["okay house"]);
+
+        // Replacing the active profile updates the wake words in use
+        assert_eq!(
+            add_profile("home", &["hello house"]),
+            MessageStatus::Success
+        );
+        assert_eq!(*WAKE_WORDS.blocking_lock(), ["hello house"]);
+
+        assert_eq!(activate("car"), MessageStatus::Success);
+        assert_eq!(*WAKE_WORDS.blocking_lock(), ["okay car"]);
+        assert_eq!(session_wake_words(&ctx), ["okay car"]);
+
+        // Unknown profiles are rejected, keeping the active one
+        assert_eq!(activate("office"), MessageStatus::Error);
+        assert_eq!(*WAKE_WORDS.blocking_lock(), ["okay car"]);
+        assert_eq!(*ACTIVE_PROFILE.blocking_lock(), Some("car".into()));
+    }
+
+    /// The length of each window transcribed in a session where the wake word is only set (and
+    /// so detected) after the first window, with the given pre-roll.
+    fn windows_with_preroll(preroll_ms: usize) -> Vec<usize> {
+        *RUN.blocking_lock() = true;
+        *WAKE_WORDS.blocking_lock() = vec!["okay computer".into()];
+        let mut model = MockTranscriber::new(" Hey Virgil, turn on the lights.")
+            .setting_wake_words_after(1, &["hey virgil"]);
+        let mut ctx = Context::new(String::new(), vec!["okay computer".into()]);
+        ctx.listen.preroll_ms = preroll_ms;
+        run_session(ctx, &mut model, vec![speech(1000); 4], 1000);
+        model.windows
+    }
+
+    #[test]
+    fn preroll_is_prepended_when_wake_word_triggers() {
+        let _session = lock_session();
+        let with_preroll = windows_with_preroll(300);
+        let without_preroll = windows_with_preroll(0);
+
+        // NOTE: The first two windows are for detection, and the third is the command.
+        assert_eq!(with_preroll[..2], without_preroll[..2]);
+        assert_eq!(
+            with_preroll[2],
+            without_preroll[2] + ms_to_samples(300, SAMPLE_RATE)
+        );
+    }
+
+    #[test]
+    fn clearing_wake_words_mid_session_disables_gating() {
+        let _session = lock_session();
+        *WAKE_WORDS.blocking_lock() = vec!["hey virgil".into()];
+        let mut model =
+            MockTranscriber::new(" Turn on the lights.").setting_wake_words_after(1, &[]);
+        let ctx = Context::new(String::new(), vec!["hey virgil".into()]);
+        run_session(ctx, &mut model, vec![speech(1000); 4], 1000);
+
+        assert!(detected_wake_words().is_empty());
+        assert_eq!(
+            *TRANSCRIPT_HISTORY.blocking_lock(),
+            "Turn on the lights. Turn on the lights."
+        );
+    }
+
+    #[test]
+    fn stop_mic_joins_processing_thread() {
+        let _session = lock_session();
+        let (input_audio_tx, mut input_audio_rx) = mpsc::channel(1);
+        let process_thread = thread::spawn(move || {
+            let mut model = MockTranscriber::new(" Turn on the lights.");
+            let rt = runtime::Builder::new_current_thread()
+                .enable_time()
+                .build()
+                .unwrap();
+            rt.block_on(process(
+                test_context(),
+                &mut model,
+                &mut input_audio_rx,
+                SAMPLE_RATE,
+                1000,
+            ));
+        });
+        *PROCESS_THREAD.blocking_lock() = Some(process_thread);
+
+        // Keep the "microphone" sending audio until the session is stopped
+        let mic_thread = thread::spawn(
+            move || {
+                while input_audio_tx.blocking_send(speech(100)).is_ok() {}
+            },
+        );
+        thread::sleep(Duration::from_millis(300));
+
+        assert_eq!(stop_mic(), MessageStatus::Success);
+        assert!(PROCESS_THREAD.blocking_lock().is_none());
+        assert!(!*RUN.blocking_lock());
+        mic_thread.join().unwrap();
+    }
+
+    #[test]
+    fn sources_are_transcribed_separately_or_mixed() {
+        let wav_paths = vec!["left.wav".to_string(), "right.wav".to_string()];
+        let speaker = speech(1_000);
+        let silence = vec![0.0; ms_to_samples(2_000, SAMPLE_RATE)];
+        let sources = [speaker.clone(), silence.clone()];
+
+        let (labels, recordings) =
+            route_sources(SourceMode::PerSource, wav_paths.clone(), &sources);
+        assert_eq!(labels, wav_paths);
+        assert_eq!(recordings, [vec![(0, speaker.clone())], vec![(0, silence)]]);
+
+        // NOTE: The mix is as long as the longest source, and each source is halved.
+        let (labels, recordings) = route_sources(SourceMode::Mix, wav_paths, &sources);
+        assert_eq!(labels, ["left.wav + right.wav"]);
+        let [chunks] = &recordings[..] else {
+            panic!("Expected a single mixed recording");
+        };
+        let [(0, mixed)] = &chunks[..] else {
+            panic!("Expected a single chunk");
+        };
+        assert_eq!(mixed.len(), ms_to_samples(2_000, SAMPLE_RATE));
+        assert!(
+            mixed
+                .iter()
+                .zip(&speaker)
+                .all(|(mixed, speaker)| *mixed == speaker / 2.0)
+        );
+        assert!(mixed[speaker.len()..].iter().all(|&sample| sample == 0.0));
+    }
+
+    #[test]
+    fn transcribe_file_returns_transcript() {
+        let _session = lock_session();
+        let ctx = Context::new(
+            concat!(env!("CARGO_MANIFEST_DIR"), "/test_assets/ggml-tiny.en.bin").into(),
+            Vec::new(),
+        );
+        let wav_path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/speech-16k-mono.wav"
+        )
+        .to_string();
+        let (mut ctx_len, mut wav_path_len, mut transcript_len) = (0, 0, 0);
+        let ctx = serialize(ctx, &mut ctx_len).unwrap();
+        let wav_path = serialize(wav_path, &mut wav_path_len).unwrap();
+
+        let transcript = transcribe_file(
+            ctx,
+            ctx_len,
+            wav_path,
+            wav_path_len,
+            ptr::null_mut(),
+            0,
+            &mut transcript_len,
+        );
+        free_rust_ptr(ctx, ctx_len);
+        free_rust_ptr(wav_path, wav_path_len);
+        assert!(
+            !transcript.is_null(),
+            "{:?}",
+            LAST_ERROR.blocking_lock().as_deref()
+        );
+
+        let event: VirgilResult<Event> = deserialize(transcript, transcript_len);
+        free_rust_ptr(transcript, transcript_len);
+        let Event::Transcript(transcript) = event.unwrap() else {
+            panic!("Expected a transcript event");
+        };
+        assert!(!transcript.clean.is_empty());
+    }
+
+    #[test]
+    fn same_model_path_is_loaded_once() {
+        let _session = lock_session();
+        *MODEL.blocking_lock() = None;
+        *LOADED_MODEL.blocking_lock() = None;
+        let ctx = Context::new(
+            concat!(env!("CARGO_MANIFEST_DIR"), "/test_assets/ggml-tiny.en.bin").into(),
+            Vec::new(),
+        );
+        let mut ctx_len = 0;
+        let ctx = serialize(ctx, &mut ctx_len).unwrap();
+
+        let statuses = [
+            load_model(ctx, ctx_len, false),
+            load_model(ctx, ctx_len, false),
+            load_model(ctx, ctx_len, true),
+        ];
+        free_rust_ptr(ctx, ctx_len);
+        *MODEL.blocking_lock() = None;
+        *LOADED_MODEL.blocking_lock() = None;
+        assert_eq!(
+            statuses,
+            [
+                MessageStatus::Success,
+                MessageStatus::AlreadyLoaded,
+                MessageStatus::Success,
+            ]
+        );
+    }
+
+    #[test]
+    fn model_memory_estimate_requires_loaded_model() {
+        let _session = lock_session();
+        let model_path = concat!(env!("CARGO_MANIFEST_DIR"), "/test_assets/ggml-tiny.en.bin");
+        let mut estimate_len = 0;
+        *LOADED_MODEL.blocking_lock() = None;
+        assert!(model_memory_estimate(&mut estimate_len).is_null());
+        assert!(LAST_ERROR.blocking_lock().is_some());
+
+        let (_, model_info) = init_model(model_path, None, false, false).unwrap();
+        *LOADED_MODEL.blocking_lock() = Some(model_info);
+        let estimate = model_memory_estimate(&mut estimate_len);
+        *LOADED_MODEL.blocking_lock() = None;
+        assert!(!estimate.is_null());
+
+        let decoded: VirgilResult<ModelMemoryEstimate> = deserialize(estimate, estimate_len);
+        free_rust_ptr(estimate, estimate_len);
+        let estimate = decoded.unwrap();
+        let model_bytes = std::fs::metadata(model_path).unwrap().len();
+        assert_eq!(estimate.model_bytes, model_bytes);
+        assert!(estimate.working_bytes > 0);
+        assert_eq!(estimate.total_bytes(), model_bytes + estimate.working_bytes);
+    }
+
+    /// The languages returned by [supported_languages] for a loaded model with the given
+    /// multilingual flag, or `None` if no model is loaded.
+    fn supported_languages_of(multilingual: Option<bool>) -> Option<Vec<String>> {
+        *LOADED_MODEL.blocking_lock() = multilingual.map(|multilingual| ModelInfo {
+            path: "models/ggml-tiny.bin".into(),
+            use_gpu: false,
+            memory_estimate: ModelMemoryEstimate {
+                model_bytes: 0,
+                working_bytes: 0,
+            },
+            multilingual,
+        });
+        let mut languages_len = 0;
+        let languages = supported_languages(&mut languages_len);
+        *LOADED_MODEL.blocking_lock() = None;
+        if languages.is_null() {
+            return None;
+        }
+        let decoded = deserialize(languages, languages_len);
+        free_rust_ptr(languages, languages_len);
+        Some(decoded.unwrap())
+    }
+
+    #[test]
+    fn supported_languages_match_the_loaded_model() {
+        let _session = lock_session();
+        assert_eq!(supported_languages_of(None), None);
+        assert!(LAST_ERROR.blocking_lock().is_some());
+
+        assert_eq!(supported_languages_of(Some(false)).unwrap(), ["en"]);
+
+        let languages = supported_languages_of(Some(true)).unwrap();
+        assert_eq!(languages[0], "en");
+        assert!(languages.len() > 90, "{languages:?}");
+        for language in ["es", "fr", "de", "ja", "zh"] {
+            assert!(languages.iter().any(|l| l == language), "{languages:?}");
+        }
+        let mut unique = languages.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), languages.len());
+    }
+
+    #[test]
+    fn context_overrides_apply_to_one_call() {
+        let ctx = Context::new("ggml-tiny.en.bin".into(), Vec::new());
+        let overrides = ContextOverrides {
+            language: Some("es".into()),
+            temperature: Some(0.4),
+            n_threads: Some(1),
+        };
+        let (mut ctx_len, mut overrides_len) = (0, 0);
+        let encoded_ctx = serialize(ctx.clone(), &mut ctx_len).unwrap();
+        let encoded_overrides = serialize(overrides, &mut overrides_len).unwrap();
+
+        let overridden =
+            decode_context(encoded_ctx, ctx_len, encoded_overrides, overrides_len).unwrap();
+        let next = decode_context(encoded_ctx, ctx_len, ptr::null_mut(), 0).unwrap();
+        free_rust_ptr(encoded_ctx, ctx_len);
+        free_rust_ptr(encoded_overrides, overrides_len);
+
+        assert_eq!(overridden.language.as_deref(), Some("es"));
+        assert_eq!(overridden.transcription.temperature, 0.4);
+        assert_eq!(overridden.transcription.n_threads, 1);
+        assert_eq!(overridden.detect_n_threads, 1);
+
+        assert_eq!(next.language, ctx.language);
+        assert_eq!(
+            next.transcription.temperature,
+            ctx.transcription.temperature
+        );
+        assert_eq!(next.transcription.n_threads, ctx.transcription.n_threads);
+        assert_eq!(next.detect_n_threads, ctx.detect_n_threads);
+    }
+
+    #[test]
+    fn dump_diagnostics_reports_model_and_device() {
+        let _session = lock_session();
+        *LOADED_MODEL.blocking_lock() = Some(ModelInfo {
+            path: "models/ggml-tiny.en.bin".into(),
+            use_gpu: true,
+            memory_estimate: ModelMemoryEstimate {
+                model_bytes: 0,
+                working_bytes: 0,
+            },
+            multilingual: false,
+        });
+        *INPUT_DEVICE.blocking_lock() = Some(InputDeviceInfo {
+            name: "Test Microphone".into(),
+            sample_rate: 48_000,
+        });
+        DETECTED_SAMPLE_RATE.store(44_100, Ordering::SeqCst);
+
+        let mut diagnostics_len = 0;
+        let diagnostics = dump_diagnostics(&mut diagnostics_len);
+        *LOADED_MODEL.blocking_lock() = None;
+        *INPUT_DEVICE.blocking_lock() = None;
+        DETECTED_SAMPLE_RATE.store(0, Ordering::SeqCst);
+        let decoded: VirgilResult<Diagnostics> = deserialize(diagnostics, diagnostics_len);
+        free_rust_ptr(diagnostics, diagnostics_len);
+
+        let diagnostics = decoded.unwrap();
+        assert_eq!(diagnostics.crate_version, env!("CARGO_PKG_VERSION"));
+        assert!(!diagnostics.whisper_cpp_version.is_empty());
+        assert_eq!(diagnostics.model_path, "models/ggml-tiny.en.bin");
+        assert!(diagnostics.gpu_enabled);
+        assert_eq!(diagnostics.input_device, "Test Microphone");
+        assert_eq!(diagnostics.sample_rate, 48_000);
+        assert_eq!(diagnostics.detected_rate, 44_100);
+    }
+
+    /// Records the names of the spans each event is logged in (outermost first).
+    #[derive(Clone, Default)]
+    struct SpanRecorder(Arc<StdMutex<Vec<Vec<&'static str>>>>);
+
+    impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for SpanRecorder {
+        fn on_event(&self, event: &tracing::Event<'_>, ctx: layer::Context<'_, S>) {
+            let spans = ctx
+                .event_scope(event)
+                .map(|scope| scope.from_root().map(|span| span.name()).collect())
+                .unwrap_or_default();
+            self.0.lock().unwrap().push(spans);
+        }
+    }
+
+    #[test]
+    fn process_logs_carry_the_parent_span() {
+        let _session = lock_session();
+        let recorder = SpanRecorder::default();
+        let subscriber = tracing_subscriber::registry().with(recorder.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            let parent_span = span!(Level::TRACE, "transcribe_speech");
+            let process_span = span!(parent: &parent_span, Level::TRACE, "process");
+            let rt = runtime::Builder::new_current_thread()
+                .enable_time()
+                .build()
+                .unwrap();
+            let mut model = MockTranscriber::new("Turn on the lights.");
+            let mut input_audio_rx = audio_receiver(buffers(&speech(500)));
+            run_process(
+                &test_context(),
+                &mut model,
+                &rt,
+                &mut input_audio_rx,
+                SAMPLE_RATE,
+                10_000,
+                &process_span,
+            );
+        });
+
+        let events = recorder.0.lock().unwrap();
+        assert!(!events.is_empty());
+        assert!(
+            events
+                .iter()
+                .all(|spans| spans.starts_with(&["transcribe_speech", "process"])),
+            "{events:?}"
+        );
+    }
+
+    /// Runs [run_process] with the model until the session ends, returning the lifecycle events
+    /// logged for the session.
+    fn run_process_with(
+        model: &mut MockTranscriber,
+        buffers: Vec<Vec<f32>>,
+    ) -> Vec<LifecycleEvent> {
+        let rt = runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let mut input_audio_rx = audio_receiver(buffers);
+        run_process(
+            &test_context(),
+            model,
+            &rt,
+            &mut input_audio_rx,
+            SAMPLE_RATE,
+            1000,
+            &Span::none(),
+        );
+        EVENT_LOG
+            .blocking_lock()
+            .entries()
+            .into_iter()
+            .map(|entry| entry.event)
+            .collect()
+    }
+
+    /// The number of times the session was logged as started and stopped.
+    fn sessions_logged(events: &[LifecycleEvent]) -> (usize, usize) {
+        let count = |matches: fn(&LifecycleEvent) -> bool| {
+            events.iter().filter(|event| matches(event)).count()
+        };
+        (
+            count(|event| matches!(event, LifecycleEvent::SessionStarted)),
+            count(|event| matches!(event, LifecycleEvent::SessionStopped)),
+        )
+    }
+
+    #[test]
+    fn panicked_processing_is_restarted() {
+        let _session = lock_session();
+        let mut model = MockTranscriber::new(" Turn on the lights.").panicking(1);
+        let events = run_process_with(&mut model, vec![speech(1000); 6]);
+
+        // NOTE: The first window panicked, and processing carried on with the next ones.
+        assert!(model.windows.len() > 1);
+        assert!(!TRANSCRIPT_HISTORY.blocking_lock().is_empty());
+        assert_eq!(sessions_logged(&events), (1, 1));
+    }
+
+    #[test]
+    fn processing_stops_after_max_restarts() {
+        let _session = lock_session();
+        let mut model = MockTranscriber::new(" Turn on the lights.").panicking(usize::MAX);
+        let events = run_process_with(&mut model, vec![speech(1000); 12]);
+
+        assert_eq!(model.windows.len(), MAX_PROCESS_RESTARTS + 1);
+        assert!(!*RUN.blocking_lock());
+        assert_eq!(*LISTENING_STATE.blocking_lock(), ListeningState::Error);
+        assert_eq!(sessions_logged(&events), (1, 1));
+        *LISTENING_STATE.blocking_lock() = ListeningState::Idle;
+    }
+
+    #[test]
+    fn second_session_is_rejected_until_first_stops() {
+        let _session = lock_session();
+        let ctx = Context {
+            overlap_policy: OverlapPolicy::Reject,
+            ..test_context()
+        };
+        let (session_tx, session_rx) = std::sync::mpsc::channel();
+        let status = start_session(ctx.clone(), "listening", &Span::none(), {
+            let session_tx = session_tx.clone();
+            move |_, session, _| {
+                session_tx.send(session).unwrap();
+                MessageStatus::Success
+            }
+        });
+        assert_eq!(status, MessageStatus::Success);
+        let first = session_rx.try_recv().unwrap();
+
+        let status = start_session(ctx.clone(), "listening", &Span::none(), |_, _, _| {
+            panic!("A second session was started")
+        });
+        assert_eq!(status, MessageStatus::AlreadyListening);
+        assert!(LAST_ERROR.blocking_lock().is_some());
+
+        drop(first);
+        let status = start_session(ctx, "listening", &Span::none(), move |_, session, _| {
+            session_tx.send(session).unwrap();
+            MessageStatus::Success
+        });
+        assert_eq!(status, MessageStatus::Success);
+        drop(session_rx.try_recv().unwrap());
+        assert_eq!(ACTIVE_SESSIONS.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn overlapping_session_is_queued_until_first_stops() {
+        let _session = lock_session();
+        let ctx = Context {
+            overlap_policy: OverlapPolicy::Queue,
+            ..test_context()
+        };
+        let (session_tx, session_rx) = std::sync::mpsc::channel();
+        let start = |session_tx: std::sync::mpsc::Sender<SessionGuard>| {
+            move |_, session, _: &Span| {
+                session_tx.send(session).unwrap();
+                MessageStatus::Success
+            }
+        };
+        let status = start_session(
+            ctx.clone(),
+            "listening",
+            &Span::none(),
+            start(session_tx.clone()),
+        );
+        assert_eq!(status, MessageStatus::Success);
+        let first = session_rx.try_recv().unwrap();
+
+        let status = start_session(ctx, "listening", &Span::none(), start(session_tx));
+        assert_eq!(status, MessageStatus::Queued);
+        assert!(session_rx.recv_timeout(STOP_POLL_INTERVAL * 10).is_err());
+
+        drop(first);
+        let queued = session_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(ACTIVE_SESSIONS.load(Ordering::SeqCst), 1);
+        drop(queued);
+        assert_eq!(ACTIVE_SESSIONS.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn overlapping_session_replaces_the_running_one() {
+        let _session = lock_session();
+        let ctx = Context {
+            overlap_policy: OverlapPolicy::ReplaceLatest,
+            ..test_context()
+        };
+
+        // NOTE: The first session's processing thread holds its session until it's stopped.
+        let status = start_session(ctx.clone(), "listening", &Span::none(), |_, session, _| {
+            let process_thread = thread::spawn(move || {
+                while *RUN.blocking_lock() {
+                    thread::sleep(STOP_POLL_INTERVAL);
+                }
+                drop(session);
+            });
+            *PROCESS_THREAD.blocking_lock() = Some(process_thread);
+            MessageStatus::Success
+        });
+        assert_eq!(status, MessageStatus::Success);
+
+        let (session_tx, session_rx) = std::sync::mpsc::channel();
+        let status = start_session(ctx, "listening", &Span::none(), move |_, session, _| {
+            session_tx.send(session).unwrap();
+            MessageStatus::Success
+        });
+        assert_eq!(status, MessageStatus::Success);
+        assert!(!*RUN.blocking_lock());
+        assert!(PROCESS_THREAD.blocking_lock().is_none());
+
+        let replacement = session_rx.try_recv().unwrap();
+        assert_eq!(ACTIVE_SESSIONS.load(Ordering::SeqCst), 1);
+        drop(replacement);
+        assert_eq!(ACTIVE_SESSIONS.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn init_context_rejects_missing_model() {
+        let _session = lock_session();
+        let model_path = concat!(env!("CARGO_MANIFEST_DIR"), "/test_assets/missing.bin");
+        let (mut model_path_len, mut wake_words_len, mut ctx_len) = (0, 0, 0);
+        let encoded_model_path = serialize(model_path.to_string(), &mut model_path_len).unwrap();
+        let encoded_wake_words =
+            serialize(vec!["hey virgil".to_string()], &mut wake_words_len).unwrap();
+
+        let ctx = init_context(
+            encoded_model_path,
+            model_path_len,
+            encoded_wake_words,
+            wake_words_len,
+            &mut ctx_len,
+        );
+        free_rust_ptr(encoded_model_path, model_path_len);
+        free_rust_ptr(encoded_wake_words, wake_words_len);
+        assert!(ctx.is_null());
+        let error = LAST_ERROR.blocking_lock().clone().unwrap();
+        assert!(error.contains("missing.bin"), "{error}");
+    }
+
+    #[test]
+    fn init_context_is_consistent_across_threads() {
+        let _session = lock_session();
+        let model_path = concat!(env!("CARGO_MANIFEST_DIR"), "/test_assets/ggml-tiny.en.bin");
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                thread::spawn(move || {
+                    let wake_words = vec![format!("hey virgil {i}")];
+                    let (mut model_path_len, mut wake_words_len, mut ctx_len) = (0, 0, 0);
+                    let encoded_model_path =
+                        serialize(model_path.to_string(), &mut model_path_len).unwrap();
+                    let encoded_wake_words =
+                        serialize(wake_words.clone(), &mut wake_words_len).unwrap();
+
+                    let ctx = init_context(
+                        encoded_model_path,
+                        model_path_len,
+                        encoded_wake_words,
+                        wake_words_len,
+                        &mut ctx_len,
+                    );
+                    free_rust_ptr(encoded_model_path, model_path_len);
+                    free_rust_ptr(encoded_wake_words, wake_words_len);
+                    assert!(!ctx.is_null());
+                    let decoded: VirgilResult<Context> = deserialize(ctx, ctx_len);
+                    free_rust_ptr(ctx, ctx_len);
+                    (wake_words, decoded.unwrap())
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            let (wake_words, ctx) = thread.join().unwrap();
+            assert_eq!(ctx.model_path, model_path);
+            assert_eq!(ctx.wake_words, wake_words);
+        }
+        assert!(LAST_ERROR.blocking_lock().is_none());
+    }
+
+    /// A message whose byte length is absurdly large.
+    #[derive(Encode, Decode)]
+    struct Absurd;
+
+    impl Message for Absurd {
+        fn byte_len(&self) -> usize {
+            usize::MAX
+        }
+    }
+
+    #[test]
+    fn absurd_payload_sizes_are_clean_errors() {
+        let _session = lock_session();
+        assert_eq!(set_max_payload_size(0), MessageStatus::Error);
+        assert!(LAST_ERROR.blocking_lock().is_some());
+        assert_eq!(
+            MAX_PAYLOAD_BYTES.load(Ordering::Relaxed),
+            DEFAULT_MAX_PAYLOAD_BYTES
+        );
+
+        let mut len = 0;
+        let err = serialize(Absurd, &mut len).unwrap_err();
+        assert!(err.to_string().contains("exceeds the maximum"), "{err}");
+
+        // NOTE: Without a limit, the allocation itself fails (rather than aborting).
+        assert_eq!(set_max_payload_size(usize::MAX), MessageStatus::Success);
+        let result = serialize(Absurd, &mut len);
+        set_max_payload_size(DEFAULT_MAX_PAYLOAD_BYTES);
+        let err = result.unwrap_err();
+        assert!(err.to_string().contains("Unable to allocate"), "{err}");
+    }
+
+    #[test]
+    fn last_error_is_set_read_and_cleared() {
+        let _session = lock_session();
+        let mut len = 0;
+        set_last_error("Unable to start listening: already listening".into());
+        let msg = last_error_message(&mut len);
+        assert!(!msg.is_null());
+        let decoded: VirgilResult<String> = deserialize(msg, len);
+        free_rust_ptr(msg, len);
+        assert_eq!(
+            decoded.unwrap(),
+            "Unable to start listening: already listening"
+        );
+        assert!(last_error_message(&mut len).is_null());
 
-        std::thread::sleep(Duration::from_millis(listen_duration_ms));
+        // NOTE: Any successful call clears the error.
+        set_last_error("Unable to load model".into());
+        let state = listening_state(&mut len);
+        free_rust_ptr(state, len);
+        assert!(LAST_ERROR.blocking_lock().is_none());
     }
 }
@@ -0,0 +1,139 @@
+use cpal::{FromSample, Sample};
+
+/// Converts a duration (in milliseconds) to the number of samples captured at `sample_rate`.
+///
+/// The result is rounded to the nearest sample.
+pub fn ms_to_samples(ms: usize, sample_rate: usize) -> usize {
+    let samples = (ms as u64 * sample_rate as u64 + 500) / 1000;
+    samples as usize
+}
+
+/// Computes the duration (in milliseconds) of `samples` audio samples captured at `sample_rate`.
+///
+/// The result is rounded to the nearest millisecond, and is `0` if the sample rate is `0`.
+pub fn samples_to_ms(samples: usize, sample_rate: usize) -> u64 {
+    if sample_rate == 0 {
+        return 0;
+    }
+    let samples = samples as u64;
+    let sample_rate = sample_rate as u64;
+    (samples * 1000 + sample_rate / 2) / sample_rate
+}
+
+/// Computes the size (in bytes) of `samples` `f32` audio samples.
+pub fn samples_to_bytes(samples: usize) -> usize {
+    samples.saturating_mul(size_of::<f32>())
+}
+
+/// Converts samples of any `cpal` sample format to `f32` samples in `[-1, 1]`.
+///
+/// Signed integers map their minimum to `-1` (so their maximum maps to just under `1`), and
+/// unsigned integers are centered on their midpoint first.
+pub fn convert_to_f32<T: Sample>(samples: &[T]) -> Vec<f32>
+where
+    f32: FromSample<T>,
+{
+    samples.iter().map(|&sample| sample.to_sample()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_to_ms_exact() {
+        assert_eq!(samples_to_ms(0, 16_000), 0);
+        assert_eq!(samples_to_ms(16, 16_000), 1);
+        assert_eq!(samples_to_ms(16_000, 16_000), 1_000);
+        assert_eq!(samples_to_ms(44_100 * 60, 44_100), 60_000);
+    }
+
+    #[test]
+    fn samples_to_ms_rounds_to_nearest() {
+        // 7 samples at 16kHz are 0.4375 ms, and 8 are 0.5 ms (which rounds up)
+        assert_eq!(samples_to_ms(7, 16_000), 0);
+        assert_eq!(samples_to_ms(8, 16_000), 1);
+        assert_eq!(samples_to_ms(23, 16_000), 1);
+        assert_eq!(samples_to_ms(24, 16_000), 2);
+        // 1 sample at 44.1kHz is ~0.0227 ms, and 100 are ~2.268 ms
+        assert_eq!(samples_to_ms(1, 44_100), 0);
+        assert_eq!(samples_to_ms(100, 44_100), 2);
+    }
+
+    #[test]
+    fn samples_to_ms_handles_zero_sample_rate() {
+        assert_eq!(samples_to_ms(16_000, 0), 0);
+    }
+
+    #[test]
+    fn ms_to_samples_exact() {
+        assert_eq!(ms_to_samples(0, 16_000), 0);
+        assert_eq!(ms_to_samples(1, 16_000), 16);
+        assert_eq!(ms_to_samples(1_000, 16_000), 16_000);
+        assert_eq!(ms_to_samples(30_000, 48_000), 1_440_000);
+        assert_eq!(ms_to_samples(1_000, 0), 0);
+    }
+
+    #[test]
+    fn ms_to_samples_rounds_to_nearest() {
+        // 1 ms at 44.1kHz is 44.1 samples, 5 ms is 220.5 (which rounds up), and 3 ms is 132.3
+        assert_eq!(ms_to_samples(1, 44_100), 44);
+        assert_eq!(ms_to_samples(5, 44_100), 221);
+        assert_eq!(ms_to_samples(3, 44_100), 132);
+        // 1 ms at 500Hz is half a sample
+        assert_eq!(ms_to_samples(1, 500), 1);
+        assert_eq!(ms_to_samples(1, 499), 0);
+    }
+
+    #[test]
+    fn ms_to_samples_does_not_overflow() {
+        // NOTE: The product is computed in 64 bits, so this doesn't overflow on 32-bit targets.
+        assert_eq!(ms_to_samples(3_600_000, 192_000), 691_200_000);
+    }
+
+    #[test]
+    fn ms_and_samples_round_trip() {
+        for sample_rate in [8_000, 16_000, 22_050, 44_100, 48_000] {
+            for ms in 0..=2_000 {
+                let samples = ms_to_samples(ms, sample_rate);
+                assert_eq!(samples_to_ms(samples, sample_rate), ms as u64);
+            }
+        }
+    }
+
+    #[test]
+    fn samples_to_bytes_counts_f32_samples() {
+        assert_eq!(samples_to_bytes(0), 0);
+        assert_eq!(samples_to_bytes(1), 4);
+        assert_eq!(samples_to_bytes(16_000), 64_000);
+        assert_eq!(samples_to_bytes(usize::MAX), usize::MAX);
+    }
+
+    #[test]
+    fn convert_to_f32_scales_to_unit_range() {
+        assert_eq!(convert_to_f32(&[i16::MIN, 0]), [-1.0, 0.0]);
+        assert_eq!(convert_to_f32(&[0u8, 128]), [-1.0, 0.0]);
+        assert_eq!(convert_to_f32(&[0.25f32]), [0.25]);
+    }
+
+    /// Asserts that the samples were converted to `-1`, `0` and just under (or at) `1`.
+    fn assert_extremes(converted: Vec<f32>) {
+        assert_eq!(converted[..2], [-1.0, 0.0]);
+        assert!(converted[2] <= 1.0 && converted[2] > 0.99, "{converted:?}");
+    }
+
+    #[test]
+    fn convert_to_f32_maps_min_and_max_of_each_format() {
+        assert_extremes(convert_to_f32(&[i8::MIN, 0, i8::MAX]));
+        assert_extremes(convert_to_f32(&[i16::MIN, 0, i16::MAX]));
+        assert_extremes(convert_to_f32(&[i32::MIN, 0, i32::MAX]));
+        assert_extremes(convert_to_f32(&[u8::MIN, 128, u8::MAX]));
+        assert_extremes(convert_to_f32(&[u16::MIN, 32_768, u16::MAX]));
+        assert_extremes(convert_to_f32(&[u32::MIN, 1 << 31, u32::MAX]));
+        assert_extremes(convert_to_f32(&[-1.0f32, 0.0, 1.0]));
+        assert_extremes(convert_to_f32(&[-1.0f64, 0.0, 1.0]));
+
+        // NOTE: The signed maximum maps to just under `1`.
+        assert_eq!(convert_to_f32(&[i16::MAX]), [32_767.0 / 32_768.0]);
+    }
+}
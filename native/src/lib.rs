@@ -1,4 +1,5 @@
 mod api;
+mod audio_math;
 mod messages;
 mod port;
 mod utils;
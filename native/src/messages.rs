@@ -1,30 +1,379 @@
-use bincode::{Decode, Encode};
+use bincode::{
+    Decode, Encode,
+    enc::{EncoderImpl, write::SizeWriter},
+};
+use serde::Serialize;
 
-use crate::utils::Context;
+use crate::utils::{
+    Alternative, BINCODE_CONFIG, Context, ContextOverrides, Diagnostics, EventLogEntry,
+    ListenConfig, ModelMemoryEstimate, Transcript, WordTiming,
+};
+
+/// The status of an FFI call.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MessageStatus {
+    Success = 0,
+    Error = 1,
+    AlreadyListening = 2,
+    AlreadyLoaded = 3,
+    Queued = 4,
+}
+
+/// The state of the listening pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum ListeningState {
+    /// No session is running.
+    Idle,
+
+    /// The microphone is capturing audio, waiting for a wake word.
+    Listening,
+
+    /// A wake word was detected (or wake words are ignored), so commands are being captured.
+    Armed,
+
+    /// A command is being transcribed.
+    Transcribing,
+
+    /// The microphone is paused.
+    Paused,
+
+    /// The session stopped because of an error (until the next session starts).
+    Error,
+}
+
+/// An event sent to Dart through the port.
+///
+/// Events are sent as `bincode` bytes, or as JSON strings for the kinds enabled in
+/// [crate::utils::JsonEvents] (tagged with their `"type"`, e.g. `{"type": "noSpeech"}`).
+#[derive(Debug, Clone, Encode, Decode, Serialize)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum Event {
+    /// A wake word was detected, and the command is about to be transcribed.
+    WakeWordDetected { word: String, confidence: f32 },
+
+    /// A transcript, with the raw output it was post-processed from and its detected language.
+    Transcript(Transcript),
+
+    /// A window was transcribed, but no speech was recognized in it (the post-processed
+    /// transcript is empty).
+    NoSpeech,
+
+    /// A transcript was suppressed because its confidence is below the context's minimum.
+    LowConfidence { text: String, confidence: f32 },
+
+    /// The first word of an utterance was recognized, sent (once per utterance) as soon as it's
+    /// available so the UI can react before the whole command is transcribed.
+    FirstWord { word: String },
+
+    /// An utterance was transcribed in dictation mode (see
+    /// [crate::utils::AccumulationMode::Dictation]), with its start and end (in milliseconds since
+    /// the session started).
+    Utterance {
+        text: String,
+        start_ms: u64,
+        end_ms: u64,
+    },
+
+    /// A session failed to start, or failed to process a window, after its FFI call returned (so
+    /// the failure can't be reported through its [MessageStatus]).
+    SessionError {
+        status: MessageStatus,
+        detail: String,
+    },
+
+    /// A segment of a transcript, sent as soon as it's decoded when streaming (see
+    /// [crate::utils::Context::streaming]).
+    ///
+    /// The text is the transcriber's raw output; the post-processed transcript follows once the
+    /// window is done.
+    Partial { text: String },
+}
+
+/// A session lifecycle or detection event, recorded in the event log (see
+/// [crate::utils::EventLog]) regardless of which events are sent to Dart.
+#[derive(Debug, Clone, Encode, Decode)]
+pub enum LifecycleEvent {
+    /// A listening session started processing audio.
+    SessionStarted,
+
+    /// A wake word was detected.
+    WakeWordDetected { word: String, confidence: f32 },
+
+    /// A window was transcribed.
+    Transcribed { text: String },
+
+    /// A window was transcribed, but no speech was recognized in it.
+    NoSpeech,
+
+    /// A transcript was suppressed because of its low confidence.
+    LowConfidence { text: String, confidence: f32 },
+
+    /// A listening session stopped processing audio.
+    SessionStopped,
+}
 
 /// Represents a message sent **from** Rust **to** Dart.
 pub trait Message: Encode + Decode<()> {
+    /// The number of bytes the message is encoded to (with [BINCODE_CONFIG]).
+    ///
+    /// This sizes the buffer the message is encoded into: an underestimate costs a re-encode, and
+    /// an overestimate wastes memory until the buffer is freed.
     fn byte_len(&self) -> usize;
 }
 
+/// Computes the exact number of bytes the value is encoded to (with [BINCODE_CONFIG]), by
+/// encoding it without writing anything.
+///
+/// Returns `0` if the value can't be encoded.
+pub fn encoded_len<T: Encode>(value: &T) -> usize {
+    let mut encoder = EncoderImpl::new(SizeWriter::default(), BINCODE_CONFIG);
+    match value.encode(&mut encoder) {
+        Ok(()) => encoder.into_writer().bytes_written,
+        Err(_) => 0,
+    }
+}
+
 impl Message for String {
     fn byte_len(&self) -> usize {
-        size_of::<Self>() + self.len()
+        // NOTE: Fixed-int encoding writes the length as a `u64`.
+        size_of::<u64>() + self.len()
     }
 }
 
 impl Message for Vec<String> {
     fn byte_len(&self) -> usize {
-        let len = self.iter().fold(
-            size_of::<Self>() + self.len() * size_of::<String>(),
-            |acc, v| acc + v.byte_len(),
-        );
-        len
+        self.iter()
+            .fold(size_of::<u64>(), |acc, v| acc + v.byte_len())
     }
 }
 
 impl Message for Context {
     fn byte_len(&self) -> usize {
-        size_of::<Self>() + self.model_path.byte_len() + self.wake_words.byte_len()
+        // NOTE: The context has too many fields to size by hand reliably, and is only sent once
+        // per `init_context` call.
+        encoded_len(self)
+    }
+}
+
+impl Message for ContextOverrides {
+    fn byte_len(&self) -> usize {
+        size_of::<Self>() + self.language.as_ref().map_or(0, |l| l.byte_len())
+    }
+}
+
+impl Message for ListenConfig {
+    fn byte_len(&self) -> usize {
+        encoded_len(self)
+    }
+}
+
+impl Message for ModelMemoryEstimate {
+    fn byte_len(&self) -> usize {
+        size_of::<Self>()
+    }
+}
+
+impl Message for Diagnostics {
+    fn byte_len(&self) -> usize {
+        size_of::<Self>()
+            + self.crate_version.byte_len()
+            + self.whisper_cpp_version.byte_len()
+            + self.model_path.byte_len()
+            + self.input_device.byte_len()
+    }
+}
+
+impl Message for ListeningState {
+    fn byte_len(&self) -> usize {
+        size_of::<u32>()
+    }
+}
+
+impl Message for Event {
+    fn byte_len(&self) -> usize {
+        match self {
+            Event::WakeWordDetected { word, .. } => size_of::<Self>() + word.byte_len(),
+            Event::Transcript(transcript) => {
+                size_of::<Self>()
+                    + transcript.raw.byte_len()
+                    + transcript.clean.byte_len()
+                    + transcript.language.as_ref().map_or(0, |l| l.byte_len())
+                    + transcript.alternatives.iter().fold(0, |acc, alternative| {
+                        acc + size_of::<Alternative>() + alternative.text.byte_len()
+                    })
+            }
+            Event::NoSpeech => size_of::<Self>(),
+            Event::LowConfidence { text, .. } => size_of::<Self>() + text.byte_len(),
+            Event::FirstWord { word } => size_of::<Self>() + word.byte_len(),
+            Event::Utterance { text, .. } => size_of::<Self>() + text.byte_len(),
+            Event::SessionError { detail, .. } => size_of::<Self>() + detail.byte_len(),
+            Event::Partial { text } => size_of::<Self>() + text.byte_len(),
+        }
+    }
+}
+
+impl Message for Vec<WordTiming> {
+    fn byte_len(&self) -> usize {
+        self.iter().fold(size_of::<Self>(), |acc, word| {
+            acc + size_of::<WordTiming>() + word.word.byte_len()
+        })
+    }
+}
+
+impl Message for Vec<EventLogEntry> {
+    fn byte_len(&self) -> usize {
+        self.iter().fold(size_of::<Self>(), |acc, entry| {
+            acc + size_of::<EventLogEntry>()
+                + match &entry.event {
+                    LifecycleEvent::WakeWordDetected { word, .. } => word.byte_len(),
+                    LifecycleEvent::Transcribed { text }
+                    | LifecycleEvent::LowConfidence { text, .. } => text.byte_len(),
+                    _ => 0,
+                }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bincode::{decode_from_slice, encode_into_slice, encode_to_vec};
+
+    use super::*;
+    use crate::utils::{JsonEvents, Segment};
+
+    /// Encodes the message into a buffer larger than it needs, returning the bytes written.
+    fn written_len<T: Message>(message: &T) -> usize {
+        let mut buffer = vec![0; message.byte_len() + 1024];
+        encode_into_slice(message, &mut buffer, BINCODE_CONFIG).unwrap()
+    }
+
+    #[test]
+    fn string_byte_len_is_exact() {
+        for text in ["", "hey", "héllo wörld"] {
+            let text = text.to_string();
+            assert_eq!(text.byte_len(), written_len(&text));
+        }
+    }
+
+    #[test]
+    fn string_vec_byte_len_is_exact() {
+        for words in [
+            vec![],
+            vec!["hey virgil".to_string(), "ok".into(), "".into()],
+        ] {
+            assert_eq!(words.byte_len(), written_len(&words));
+        }
+    }
+
+    #[test]
+    fn context_byte_len_is_exact() {
+        let mut ctx = Context::new("models/ggml-tiny.en.bin".into(), vec!["hey virgil".into()]);
+        assert_eq!(ctx.byte_len(), written_len(&ctx));
+
+        ctx.asset_dir = Some("/data/app".into());
+        ctx.language = Some("en".into());
+        assert_eq!(ctx.byte_len(), written_len(&ctx));
+    }
+
+    #[test]
+    fn events_serialize_to_tagged_json() {
+        let event = Event::WakeWordDetected {
+            word: "hey virgil".into(),
+            confidence: 0.5,
+        };
+        let json: serde_json::Value =
+            serde_json::from_str(&serde_json::to_string(&event).unwrap()).unwrap();
+        assert_eq!(json["type"], "wakeWordDetected");
+        assert_eq!(json["word"], "hey virgil");
+        assert_eq!(json["confidence"], 0.5);
+
+        let event = Event::SessionError {
+            status: MessageStatus::Error,
+            detail: "no device".into(),
+        };
+        let json: serde_json::Value =
+            serde_json::from_str(&serde_json::to_string(&event).unwrap()).unwrap();
+        assert_eq!(json["type"], "sessionError");
+        assert_eq!(json["status"], "error");
+        assert_eq!(json["detail"], "no device");
+
+        let event = Event::Utterance {
+            text: "lights on".into(),
+            start_ms: 100,
+            end_ms: 900,
+        };
+        let json: serde_json::Value =
+            serde_json::from_str(&serde_json::to_string(&event).unwrap()).unwrap();
+        assert_eq!(json["type"], "utterance");
+        assert_eq!(json["startMs"], 100);
+        assert_eq!(json["endMs"], 900);
+
+        let json: serde_json::Value =
+            serde_json::from_str(&serde_json::to_string(&Event::NoSpeech).unwrap()).unwrap();
+        assert_eq!(json, serde_json::json!({ "type": "noSpeech" }));
+    }
+
+    /// A transcript of two segments, with a detected language.
+    fn test_transcript() -> Transcript {
+        let segments = vec![
+            Segment {
+                text: " Turn on".into(),
+                start_ms: 0,
+                end_ms: 600,
+                confidence: 0.75,
+            },
+            Segment {
+                text: " the lights.".into(),
+                start_ms: 600,
+                end_ms: 1_200,
+                confidence: 0.25,
+            },
+        ];
+        let ctx = Context::new(String::new(), Vec::new());
+        Transcript::new(segments, Some(("en".into(), 0.5)), &ctx)
+    }
+
+    #[test]
+    fn transcript_event_round_trips_through_bincode() {
+        let event = Event::Transcript(test_transcript());
+        let bytes = encode_to_vec(&event, BINCODE_CONFIG).unwrap();
+        assert_eq!(bytes.len(), encoded_len(&event));
+
+        let (decoded, _): (Event, _) = decode_from_slice(&bytes, BINCODE_CONFIG).unwrap();
+        let Event::Transcript(transcript) = decoded else {
+            panic!("Decoded the wrong event: {decoded:?}");
+        };
+        assert_eq!(transcript.raw, " Turn on the lights.");
+        assert_eq!(transcript.clean, "Turn on the lights.");
+        assert_eq!(transcript.confidence, 0.5);
+    }
+
+    #[test]
+    fn transcript_event_serializes_to_structured_json() {
+        let event = Event::Transcript(test_transcript());
+        let json: serde_json::Value =
+            serde_json::from_str(&serde_json::to_string(&event).unwrap()).unwrap();
+        assert_eq!(json["type"], "transcript");
+        assert_eq!(json["raw"], " Turn on the lights.");
+        assert_eq!(json["clean"], "Turn on the lights.");
+        assert_eq!(json["confidence"], 0.5);
+        assert_eq!(json["alternatives"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn json_events_only_include_enabled_kinds() {
+        let json_events = JsonEvents {
+            transcript: true,
+            ..Default::default()
+        };
+        assert!(json_events.includes(&Event::Transcript(test_transcript())));
+        assert!(!json_events.includes(&Event::NoSpeech));
+        assert!(!JsonEvents::default().includes(&Event::Transcript(test_transcript())));
     }
 }
@@ -1,20 +1,100 @@
 use std::{
+    collections::HashMap,
     ffi,
     sync::atomic::{AtomicI64, Ordering},
+    time::{Duration, Instant},
 };
 
+use bincode::encode_to_vec;
 use dart_sys::{
-    self, _Dart_CObject, _Dart_CObject__bindgen_ty_1, Dart_CObject,
-    Dart_CObject_Type_Dart_CObject_kString, Dart_PostCObject_DL,
+    self, _Dart_CObject, _Dart_CObject__bindgen_ty_1, _Dart_CObject__bindgen_ty_1__bindgen_ty_4,
+    Dart_CObject, Dart_CObject_Type_Dart_CObject_kInt64, Dart_CObject_Type_Dart_CObject_kString,
+    Dart_CObject_Type_Dart_CObject_kTypedData, Dart_PostCObject_DL,
+    Dart_TypedData_Type_Dart_TypedData_kFloat32, Dart_TypedData_Type_Dart_TypedData_kUint8,
 };
-use tracing::{Level, error, span, trace};
+use thiserror::Error;
+use tracing::{Level, span, trace};
 
-use crate::utils::VirgilResult;
+use crate::{
+    messages::{Event, MessageStatus},
+    utils::{BINCODE_CONFIG, VirgilResult},
+};
+
+#[derive(Debug, Error)]
+pub enum PortError {
+    #[error("PortError: Dart API has not been initialized")]
+    ApiUninitialized,
+
+    #[error("PortError: Dart port has not been set")]
+    Uninitialized,
+
+    #[error("PortError: Failed to post object ({0}) to Dart")]
+    PostFailed(String),
+
+    #[error("PortError: Text can't be sent to Dart ({0})")]
+    InvalidText(String),
+}
+
+/// The kinds of messages that can be sent often enough to flood the Dart isolate, and are
+/// rate-limited by a [ThrottledSender].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ThrottledKind {
+    /// Raw audio windows (see [send_audio_to_dart]).
+    RawAudio,
+
+    /// [Event::NoSpeech] events, which can be sent for every silent window.
+    NoSpeech,
+}
+
+/// Rate-limits messages sent to Dart, so that each kind is sent at most once per its minimum
+/// interval.
+///
+/// Messages sent within the interval are dropped rather than queued, so posting never blocks the
+/// audio pipeline.
+pub struct ThrottledSender {
+    intervals: HashMap<ThrottledKind, Duration>,
+    last_sent: HashMap<ThrottledKind, Instant>,
+}
+
+impl ThrottledSender {
+    /// Creates a sender with the given minimum interval per kind (kinds without one, or with a
+    /// zero interval, aren't throttled).
+    pub fn new(intervals: impl IntoIterator<Item = (ThrottledKind, Duration)>) -> Self {
+        Self {
+            intervals: intervals.into_iter().collect(),
+            last_sent: HashMap::new(),
+        }
+    }
+
+    /// Sends the message with `send`, unless a message of the same kind was sent within its
+    /// interval.
+    ///
+    /// Returns whether the message was sent.
+    pub fn send(
+        &mut self,
+        kind: ThrottledKind,
+        send: impl FnOnce() -> VirgilResult<()>,
+    ) -> VirgilResult<bool> {
+        let now = Instant::now();
+        let interval = self.intervals.get(&kind).copied().unwrap_or_default();
+        if let Some(last_sent) = self.last_sent.get(&kind)
+            && now - *last_sent < interval
+        {
+            trace!("Dropping throttled message: {kind:?}");
+            return Ok(false);
+        }
+        send()?;
+        self.last_sent.insert(kind, now);
+        Ok(true)
+    }
+}
 
 /// Represents a port in Dart.
 pub type DartPort = i64;
 
 /// Global atomic to store the Dart SendPort native port.
+///
+/// This is `0` (an invalid port) until [set_dart_port] is called.
 pub static DART_PORT: AtomicI64 = AtomicI64::new(0);
 
 /// Sets the current port for FFI communication.
@@ -28,7 +108,7 @@ pub fn send_text_to_dart(text: String) -> VirgilResult<()> {
     let _enter = span.enter();
 
     // Create Dart object
-    let cstr = ffi::CString::new(text).map_err(|e| error!("{e}")).unwrap();
+    let cstr = ffi::CString::new(text).map_err(|e| PortError::InvalidText(e.to_string()))?;
     let mut dart_obj = Dart_CObject {
         type_: Dart_CObject_Type_Dart_CObject_kString,
         value: _Dart_CObject__bindgen_ty_1 {
@@ -38,13 +118,329 @@ pub fn send_text_to_dart(text: String) -> VirgilResult<()> {
     trace!("Dart object created");
 
     // Send object to Dart isolate
-    let port = DART_PORT.load(Ordering::SeqCst);
-    let success =
-        unsafe { Dart_PostCObject_DL.unwrap()(port, &mut dart_obj as *mut _Dart_CObject) };
+    post_to_dart(&mut dart_obj)?;
+    trace!("Dart object sent to isolate");
+
+    Ok(())
+}
+
+/// Converts the values into a little-endian byte buffer, regardless of the host's byte order.
+///
+/// Typed data posted to Dart is read as little-endian, so buffers of multi-byte values must be
+/// converted with this before being sent.
+pub fn to_le_bytes_buffer<T: Copy, const N: usize>(
+    values: &[T],
+    to_le_bytes: fn(T) -> [u8; N],
+) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|&value| to_le_bytes(value))
+        .collect()
+}
+
+/// Sends the given event to Dart, encoded as (little-endian) bytes.
+pub fn send_event_to_dart(event: Event) -> VirgilResult<()> {
+    let span = span!(Level::TRACE, "send_event_to_dart");
+    let _enter = span.enter();
+
+    // Create Dart object
+    //
+    // NOTE: Dart copies typed data when it's posted, so the bytes only need to outlive the post.
+    let bytes = encode_event(&event)?;
+    let mut dart_obj = Dart_CObject {
+        type_: Dart_CObject_Type_Dart_CObject_kTypedData,
+        value: _Dart_CObject__bindgen_ty_1 {
+            as_typed_data: _Dart_CObject__bindgen_ty_1__bindgen_ty_4 {
+                type_: Dart_TypedData_Type_Dart_TypedData_kUint8,
+                length: bytes.len() as isize,
+                values: bytes.as_ptr(),
+            },
+        },
+    };
+    trace!("Dart object created");
+
+    post_to_dart(&mut dart_obj)?;
+    trace!("Event sent to isolate: {event:?}");
+
+    Ok(())
+}
+
+/// Sends the status of a failed call to Dart, with a detail of what went wrong, as an
+/// [Event::SessionError].
+///
+/// This is for failures that happen after the FFI call has returned its [MessageStatus].
+pub fn send_status_to_dart(status: MessageStatus, detail: String) -> VirgilResult<()> {
+    send_event_to_dart(Event::SessionError { status, detail })
+}
+
+/// Encodes the event into the bytes sent to Dart.
+fn encode_event(event: &Event) -> VirgilResult<Vec<u8>> {
+    Ok(encode_to_vec(event, BINCODE_CONFIG)?)
+}
+
+/// Sends the given event to Dart, encoded as a JSON string.
+pub fn send_json_event_to_dart(event: Event) -> VirgilResult<()> {
+    let span = span!(Level::TRACE, "send_json_event_to_dart");
+    let _enter = span.enter();
+
+    let json = serde_json::to_string(&event)?;
+    trace!("Event encoded as JSON: {json}");
+    send_text_to_dart(json)
+}
+
+/// Sends the audio data to Dart, as (little-endian) `f32` typed data.
+///
+/// Dart receives it as a `Float32List`, which keeps it apart from encoded events.
+pub fn send_audio_to_dart(audio_data: &[f32]) -> VirgilResult<()> {
+    let span = span!(Level::TRACE, "send_audio_to_dart");
+    let _enter = span.enter();
+
+    let bytes = to_le_bytes_buffer(audio_data, f32::to_le_bytes);
+    let mut dart_obj = Dart_CObject {
+        type_: Dart_CObject_Type_Dart_CObject_kTypedData,
+        value: _Dart_CObject__bindgen_ty_1 {
+            as_typed_data: _Dart_CObject__bindgen_ty_1__bindgen_ty_4 {
+                type_: Dart_TypedData_Type_Dart_TypedData_kFloat32,
+                length: audio_data.len() as isize,
+                values: bytes.as_ptr(),
+            },
+        },
+    };
+    trace!("Dart object created");
+
+    post_to_dart(&mut dart_obj)?;
+    trace!("{} samples sent to isolate", audio_data.len());
+
+    Ok(())
+}
+
+/// Sends the given nonce back to Dart, to verify that the port works.
+pub fn send_nonce_to_dart(nonce: i64) -> VirgilResult<()> {
+    let span = span!(Level::TRACE, "send_nonce_to_dart");
+    let _enter = span.enter();
+
+    let mut dart_obj = Dart_CObject {
+        type_: Dart_CObject_Type_Dart_CObject_kInt64,
+        value: _Dart_CObject__bindgen_ty_1 { as_int64: nonce },
+    };
+    post_to_dart(&mut dart_obj)?;
+    trace!("Nonce sent to isolate");
+
+    Ok(())
+}
+
+/// Posts the Dart object to the current port.
+///
+/// Fails with [PortError::Uninitialized] if the port hasn't been set yet, so messages sent before
+/// Dart is listening are reported instead of posted to an invalid port.
+fn post_to_dart(dart_obj: &mut Dart_CObject) -> Result<(), PortError> {
+    let (port, post) = post_target()?;
+    let success = unsafe { post(port, dart_obj as *mut _Dart_CObject) };
     if !success {
-        error!("Failed to send objet ({:?}) to Dart", dart_obj.type_);
+        return Err(PortError::PostFailed(format!("{:?}", dart_obj.type_)));
     }
-    trace!("Dart object sent to isolate");
 
     Ok(())
 }
+
+/// A function that posts Dart objects to a port (the signature of `Dart_PostCObject_DL`).
+type PostFn = unsafe extern "C" fn(DartPort, *mut Dart_CObject) -> bool;
+
+/// Returns the port objects are posted to, and the function that posts them.
+fn post_target() -> Result<(DartPort, PostFn), PortError> {
+    #[cfg(test)]
+    if let Some(post) = capture::POST_STUB.get() {
+        return Ok((capture::STUB_PORT, post));
+    }
+
+    let port = DART_PORT.load(Ordering::SeqCst);
+    if port == 0 {
+        return Err(PortError::Uninitialized);
+    }
+
+    let post = unsafe { Dart_PostCObject_DL }.ok_or(PortError::ApiUninitialized)?;
+    Ok((port, post))
+}
+
+/// Captures the objects posted to Dart, so tests can check what would have been sent without a
+/// Dart isolate.
+#[cfg(test)]
+pub mod capture {
+    use std::cell::{Cell, RefCell};
+
+    use bincode::decode_from_slice;
+
+    use super::*;
+
+    /// The port posts are sent to while captured.
+    pub const STUB_PORT: DartPort = 1;
+
+    thread_local! {
+        /// The function posts from this thread are sent to instead of `Dart_PostCObject_DL`.
+        pub static POST_STUB: Cell<Option<PostFn>> = const { Cell::new(None) };
+
+        static POSTED: RefCell<Vec<Posted>> = const { RefCell::new(Vec::new()) };
+    }
+
+    /// An object posted to Dart.
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Posted {
+        Int64(i64),
+        Text(String),
+        Bytes(Vec<u8>),
+        Float32(Vec<f32>),
+    }
+
+    impl Posted {
+        /// Decodes the event, if this is one.
+        pub fn event(&self) -> Option<Event> {
+            match self {
+                Posted::Bytes(bytes) => decode_from_slice(bytes, BINCODE_CONFIG)
+                    .ok()
+                    .map(|(event, _)| event),
+                _ => None,
+            }
+        }
+
+        /// The text, if this is text (e.g. a transcript).
+        pub fn text(&self) -> Option<&str> {
+            match self {
+                Posted::Text(text) => Some(text),
+                _ => None,
+            }
+        }
+    }
+
+    /// Runs `f`, capturing everything it posts to Dart from the current thread.
+    pub fn capture_posts(f: impl FnOnce()) -> Vec<Posted> {
+        POST_STUB.set(Some(record));
+        POSTED.take();
+        f();
+        POST_STUB.set(None);
+        POSTED.take()
+    }
+
+    /// Records the posted object, copying its value (Dart copies it too, so it only needs to
+    /// outlive the post).
+    unsafe extern "C" fn record(port: DartPort, dart_obj: *mut Dart_CObject) -> bool {
+        assert_eq!(port, STUB_PORT);
+        let dart_obj = unsafe { &*dart_obj };
+        let posted = match dart_obj.type_ {
+            type_ if type_ == Dart_CObject_Type_Dart_CObject_kInt64 => {
+                Posted::Int64(unsafe { dart_obj.value.as_int64 })
+            }
+            type_ if type_ == Dart_CObject_Type_Dart_CObject_kString => {
+                let text = unsafe { ffi::CStr::from_ptr(dart_obj.value.as_string) };
+                Posted::Text(text.to_string_lossy().into_owned())
+            }
+            type_ if type_ == Dart_CObject_Type_Dart_CObject_kTypedData => {
+                let typed_data = unsafe { dart_obj.value.as_typed_data };
+                let len = typed_data.length as usize;
+                if typed_data.type_ == Dart_TypedData_Type_Dart_TypedData_kFloat32 {
+                    let samples =
+                        unsafe { std::slice::from_raw_parts(typed_data.values as *const f32, len) };
+                    Posted::Float32(samples.to_vec())
+                } else {
+                    let bytes = unsafe { std::slice::from_raw_parts(typed_data.values, len) };
+                    Posted::Bytes(bytes.to_vec())
+                }
+            }
+            type_ => panic!("Unexpected Dart object type: {type_:?}"),
+        };
+        POSTED.with_borrow_mut(|posted_objects| posted_objects.push(posted));
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bincode::decode_from_slice;
+
+    use super::{
+        capture::{Posted, capture_posts},
+        *,
+    };
+
+    #[test]
+    fn status_payload_contains_detail() {
+        let detail = "Failed to start listening to mic: no device".to_string();
+        let event = Event::SessionError {
+            status: MessageStatus::Error,
+            detail: detail.clone(),
+        };
+        let bytes = encode_event(&event).unwrap();
+        assert!(
+            bytes
+                .windows(detail.len())
+                .any(|window| window == detail.as_bytes())
+        );
+
+        let (decoded, _): (Event, _) = decode_from_slice(&bytes, BINCODE_CONFIG).unwrap();
+        let Event::SessionError {
+            status,
+            detail: decoded_detail,
+        } = decoded
+        else {
+            panic!("Decoded the wrong event: {decoded:?}");
+        };
+        assert_eq!(status, MessageStatus::Error);
+        assert_eq!(decoded_detail, detail);
+    }
+
+    #[test]
+    fn buffers_are_little_endian_regardless_of_host() {
+        assert_eq!(
+            to_le_bytes_buffer(&[0x0102_u16, 0xA0B0], u16::to_le_bytes),
+            [0x02, 0x01, 0xB0, 0xA0]
+        );
+        assert_eq!(
+            to_le_bytes_buffer(&[1.0_f32, -2.5], f32::to_le_bytes),
+            [0x00, 0x00, 0x80, 0x3F, 0x00, 0x00, 0x20, 0xC0]
+        );
+    }
+
+    #[test]
+    fn text_with_nul_byte_is_rejected() {
+        let err = send_text_to_dart("hey\0virgil".into()).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<PortError>(),
+            Some(PortError::InvalidText(_))
+        ));
+    }
+
+    #[test]
+    fn posting_before_port_is_set_fails() {
+        // NOTE: No test sets the port, so it's still the initial (invalid) port.
+        assert_eq!(DART_PORT.load(Ordering::SeqCst), 0);
+        for result in [
+            send_nonce_to_dart(42),
+            send_text_to_dart("Turn on the lights.".into()),
+            send_event_to_dart(Event::NoSpeech),
+            send_audio_to_dart(&[0.0; 16]),
+        ] {
+            assert!(matches!(
+                result.unwrap_err().downcast_ref::<PortError>(),
+                Some(PortError::Uninitialized)
+            ));
+        }
+    }
+
+    #[test]
+    fn nonce_is_posted_as_int64() {
+        let posted = capture_posts(|| send_nonce_to_dart(42).unwrap());
+        assert_eq!(posted, [Posted::Int64(42)]);
+    }
+
+    #[test]
+    fn messages_are_posted_with_their_types() {
+        let posted = capture_posts(|| {
+            send_text_to_dart("Turn on the lights.".into()).unwrap();
+            send_event_to_dart(Event::NoSpeech).unwrap();
+            send_audio_to_dart(&[0.25, -0.5]).unwrap();
+        });
+        assert_eq!(posted.len(), 3);
+        assert_eq!(posted[0].text(), Some("Turn on the lights."));
+        assert!(matches!(posted[1].event(), Some(Event::NoSpeech)));
+        assert_eq!(posted[2], Posted::Float32(vec![0.25, -0.5]));
+    }
+}
@@ -1,60 +1,880 @@
-use std::{ffi, ptr::slice_from_raw_parts};
+use std::{
+    any::type_name,
+    cell::Cell,
+    collections::{HashMap, VecDeque},
+    ffi, fs,
+    io::Write,
+    marker::PhantomData,
+    path::Path,
+    ptr::{slice_from_raw_parts, slice_from_raw_parts_mut},
+    sync::{
+        Arc, LazyLock, Mutex,
+        atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+        mpsc as std_mpsc,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
-use bincode::{Decode, Encode, decode_from_slice, encode_into_slice};
+use bincode::{
+    Decode, Encode,
+    config::{Configuration, Fixint, LittleEndian},
+    decode_from_slice, encode_into_slice, encode_to_vec,
+    error::EncodeError,
+};
 use cpal::{
-    InputCallbackInfo, SampleRate, Stream,
-    traits::{DeviceTrait, HostTrait},
+    BuildStreamError, Device, FromSample, InputCallbackInfo, SampleFormat, SampleRate, SizedSample,
+    Stream, StreamConfig, SupportedStreamConfig, SupportedStreamConfigRange,
+    SupportedStreamConfigsError,
+    traits::{DeviceTrait, HostTrait, StreamTrait},
 };
+use flate2::{Compression, write::ZlibEncoder};
+use memmap2::Mmap;
+use samplerate::ConverterType;
+use serde::Serialize;
+use static_assertions::{assert_impl_all, assert_not_impl_any};
 use thiserror::Error;
-use tokio::sync::mpsc;
-use tracing::{Level, error, info, span};
-use whisper_rs::{FullParams, WhisperContext, WhisperContextParameters, WhisperState};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{Level, Span, debug, error, info, span, warn};
+use whisper_rs::{
+    FullParams, SamplingStrategy, SegmentCallbackData, WhisperContext, WhisperContextParameters,
+    WhisperState, get_lang_id, get_lang_max_id, get_lang_str,
+};
 
-use crate::messages::Message;
+use crate::{
+    audio_math::{convert_to_f32, ms_to_samples, samples_to_ms},
+    messages::{Event, LifecycleEvent, Message},
+};
 
 pub type VirgilResult<T> = Result<T, anyhow::Error>;
 
 /// The context passed around for FFI functions.
-#[derive(Encode, Decode)]
+#[derive(Clone, Encode, Decode)]
 pub struct Context {
+    /// The path to the `Whisper` model; relative paths are resolved against
+    /// [Context::asset_dir] (if set).
     pub model_path: String,
+
+    /// The directory that relative asset paths (e.g. [Context::model_path]) are resolved against,
+    /// such as an app-specific directory on mobile.
+    ///
+    /// Relative paths are used as is if this isn't set.
+    pub asset_dir: Option<String>,
+
+    /// The wake words to listen for.
+    ///
+    /// If there are none when listening starts, all audio is transcribed (like
+    /// [Context::ignore_wake_words]).
     pub wake_words: Vec<String>,
+
+    /// The audio/listening settings.
+    pub listen: ListenConfig,
+
+    /// Determines if a silent warmup inference is run after loading the model.
+    ///
+    /// The first inference on a new model state is much slower than subsequent ones, so warming
+    /// up keeps the first real utterance from being penalized (at the cost of a slower start).
+    pub warmup_model: bool,
+
+    /// The language spoken in the audio (uses the model's default if `None`).
+    ///
+    /// This is a language code (e.g. `"es"`), or [AUTO_LANGUAGE] to have `Whisper` detect the
+    /// language of each window (which needs a multilingual model).
+    pub language: Option<String>,
+
+    /// The sampling settings (and number of threads) used for transcription.
+    pub transcription: TranscriptionConfig,
+
+    /// The number of threads used for wake word detection (uses `Whisper`'s default if `0`).
+    pub detect_n_threads: usize,
+
+    /// The minimum time (in milliseconds) between partial transcripts sent to Dart when streaming
+    /// (see [Context::streaming]).
+    ///
+    /// Segments decoded within the interval are coalesced into a single partial transcript, while
+    /// final transcripts are always sent immediately. Every segment is sent as soon as it's
+    /// decoded if this is `0`.
+    pub commit_interval_ms: usize,
+
+    /// The compression ratio above which a transcribed segment is dropped.
+    ///
+    /// Highly compressible text is a sign of the model hallucinating repeated words/phrases.
+    /// Filtering is disabled if this is `0`.
+    pub compression_ratio_threshold: f32,
+
+    /// Determines if all audio is transcribed, without waiting for a wake word.
+    ///
+    /// This is meant for debugging transcription.
+    pub ignore_wake_words: bool,
+
+    /// Wake words are only detected on every `n`th window while waiting for one (on every window
+    /// if this is `0` or `1`).
+    ///
+    /// A wake word usually spans multiple windows, so skipping some saves CPU while idle; once a
+    /// wake word is detected, every window is transcribed.
+    pub detect_every_n_windows: usize,
+
+    /// Determines if a [crate::messages::Event::WakeWordDetected] event is sent to Dart when a
+    /// wake word is detected, before the command is transcribed.
+    pub wake_word_events: bool,
+
+    /// Determines if a [crate::messages::Event::FirstWord] event is sent to Dart as soon as the
+    /// first word of each utterance is recognized.
+    ///
+    /// An utterance ends when the speaker pauses (see [ListenConfig::endpoint_silence_ms]) or the
+    /// active listen period ends.
+    pub first_word_events: bool,
+
+    /// Determines if a [crate::messages::Event::NoSpeech] event is sent to Dart when a
+    /// transcript comes out empty after post-processing, instead of sending nothing.
+    ///
+    /// This lets the UI tell "didn't catch that" apart from an error.
+    pub no_speech_events: bool,
+
+    /// The minimum time (in milliseconds) between [crate::messages::Event::NoSpeech] events;
+    /// events within the interval are dropped (unthrottled if `0`).
+    pub no_speech_interval_ms: usize,
+
+    /// The mean segment confidence (from `0` to `1`) below which a transcript is suppressed.
+    ///
+    /// Suppressed transcripts are sent as a [crate::messages::Event::LowConfidence] event
+    /// instead, and aren't added to the transcript history. Nothing is suppressed if this is `0`.
+    pub min_confidence: f32,
+
+    /// Determines if segments are trimmed and joined with a single space (collapsing any other
+    /// runs of whitespace too).
+    pub trim_segments: bool,
+
+    /// The maximum number of segments included in a transcript (unlimited if `0`).
+    ///
+    /// This is useful when only the first utterance (the command) matters.
+    pub max_segments: usize,
+
+    /// Determines if consecutive segments are merged into sentences (see [merge_into_sentences]).
+    pub merge_sentences: bool,
+
+    /// Determines if every window of (resampled) audio is sent to Dart before it's transcribed,
+    /// so the exact audio can be recorded or visualized.
+    ///
+    /// This is meant for debugging, and sends a lot of data (64KB per second of audio).
+    pub send_raw_audio: bool,
+
+    /// The minimum time (in milliseconds) between raw audio windows sent to Dart; windows within
+    /// the interval are dropped (unthrottled if `0`).
+    pub raw_audio_interval_ms: usize,
+
+    /// Determines if the raw transcriber output is sent alongside the post-processed transcript,
+    /// as a [crate::messages::Event::Transcript] event.
+    ///
+    /// This is meant for debugging post-processing.
+    pub include_raw_transcript: bool,
+
+    /// Determines if the detected language (and its probability) is sent with each transcript,
+    /// as a [crate::messages::Event::Transcript] event.
+    ///
+    /// Detection is most useful with a multilingual model and the language set to `"auto"`.
+    /// Transcripts aren't batched when it's set.
+    pub report_language: bool,
+
+    /// The kinds of events sent to Dart as JSON strings instead of `bincode` bytes.
+    pub json_events: JsonEvents,
+
+    /// Determines if transcription is retried once (with a higher temperature and more
+    /// candidates) when the transcript has no content, i.e. only whitespace/punctuation.
+    ///
+    /// `Whisper` occasionally returns something like `"..."` for real speech at temperature `0`.
+    pub retry_empty_transcripts: bool,
+
+    /// Determines if the model runs on the GPU (if `Whisper` was built with GPU support).
+    pub use_gpu: bool,
+
+    /// Determines if the model is reloaded on the CPU when inference on the GPU fails.
+    ///
+    /// Inference is only retried (on the CPU) once per failure.
+    pub gpu_fallback: bool,
+
+    /// Determines if the model file is memory-mapped while the model is built, instead of being
+    /// read from the file (the `Whisper` default).
+    ///
+    /// Mapped pages are only loaded as they're used, and can be reclaimed by the OS under memory
+    /// pressure, which helps with large models on low-memory devices.
+    ///
+    /// # Note
+    /// `Whisper` copies the weights out of the mapping, so this doesn't lower the memory used by
+    /// the loaded model itself.
+    pub mmap_model: bool,
+
+    /// The input channel used on multi-channel devices (all channels are downmixed if `None`).
+    ///
+    /// This is useful when only one channel has the good microphone.
+    pub input_channel: Option<usize>,
+
+    /// The sample formats the microphone may capture in, from most to least preferred.
+    ///
+    /// Captured samples are converted to `f32` regardless of the format.
+    pub sample_formats: Vec<InputSampleFormat>,
+
+    /// Determines if audio is resampled from the rate the microphone actually delivers, when it
+    /// drifts from the rate it was configured with (see [RateEstimator]).
+    ///
+    /// Drift is logged either way.
+    pub correct_rate_drift: bool,
+
+    /// The size of `Whisper`'s audio context (uses the full context if `None`).
+    ///
+    /// The context covers 30s of audio at [MAX_AUDIO_CTX], so a smaller one speeds up short
+    /// commands considerably with little loss in accuracy (e.g. `768` covers ~15s).
+    pub audio_ctx: Option<i32>,
+
+    /// The maximum number of workers used to transcribe multiple audio buffers concurrently (see
+    /// [TranscriberPool]).
+    ///
+    /// Each worker has its own `Whisper` state, so every worker adds to the memory usage.
+    pub max_pool_workers: usize,
+
+    /// What happens when listening is requested while a session is already running.
+    pub overlap_policy: OverlapPolicy,
+
+    /// The maximum number of audio sources (e.g. recordings) transcribed together (unlimited if
+    /// `0`).
+    pub max_audio_sources: usize,
+
+    /// Determines if audio sources are transcribed separately, or mixed into one (see
+    /// [mix_sources]).
+    pub source_mode: SourceMode,
+
+    /// The number of alternative transcriptions decoded for each utterance (see
+    /// [Transcript::alternatives]); only the best one is decoded if this is `1`.
+    ///
+    /// Alternatives are sent as a [crate::messages::Event::Transcript] event, so a command matcher
+    /// can consider them. Each alternative takes another decoding pass, and transcripts aren't
+    /// batched when this is set.
+    pub n_best: usize,
+
+    /// How live audio is accumulated into windows before it's transcribed.
+    pub accumulation_mode: AccumulationMode,
+
+    /// Determines if each segment of a live session's transcripts is sent (as a
+    /// [crate::messages::Event::Partial] event) as soon as it's decoded, so the UI can update
+    /// before the whole window is transcribed.
+    ///
+    /// The final transcript is still sent once the window is done.
+    pub streaming: bool,
+
+    /// The maximum edit distance (in characters) between a wake word and the words heard for it to
+    /// be detected, so slight mishearings (e.g. "Hey Vergil" for "Hey Virgil") still count.
+    ///
+    /// Wake words must be heard exactly if this is `0`.
+    pub wake_word_max_distance: usize,
+
+    /// Determines if bracketed annotations (e.g. `[BLANK_AUDIO]` or `(music)`) are removed from
+    /// transcripts, so they don't count as speech.
+    pub strip_annotations: bool,
+}
+
+/// The kinds of [Event]s sent to Dart as JSON strings (which Dart can `jsonDecode`) instead of
+/// `bincode` bytes.
+///
+/// JSON is larger, but doesn't need the event types mirrored in Dart and is easier to debug.
+#[derive(Debug, Default, Clone, Copy, Encode, Decode)]
+pub struct JsonEvents {
+    pub wake_word_detected: bool,
+    pub transcript: bool,
+    pub no_speech: bool,
+    pub low_confidence: bool,
+    pub first_word: bool,
+    pub utterance: bool,
+    pub session_error: bool,
+    pub partial: bool,
+}
+
+impl JsonEvents {
+    /// Determines if the event should be sent as JSON.
+    pub fn includes(&self, event: &Event) -> bool {
+        match event {
+            Event::WakeWordDetected { .. } => self.wake_word_detected,
+            Event::Transcript(_) => self.transcript,
+            Event::NoSpeech => self.no_speech,
+            Event::LowConfidence { .. } => self.low_confidence,
+            Event::FirstWord { .. } => self.first_word,
+            Event::Utterance { .. } => self.utterance,
+            Event::SessionError { .. } => self.session_error,
+            Event::Partial { .. } => self.partial,
+        }
+    }
+}
+
+/// What happens when listening is requested while a session is already running.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum OverlapPolicy {
+    /// The request is rejected with [crate::messages::MessageStatus::AlreadyListening].
+    #[default]
+    Reject,
+
+    /// The request is queued (returning [crate::messages::MessageStatus::Queued]), and its session
+    /// starts once the running one ends.
+    Queue,
+
+    /// The running session is stopped, and the request's session replaces it.
+    ReplaceLatest,
+}
+
+/// How multiple audio sources are transcribed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum SourceMode {
+    /// Each source is transcribed on its own, producing a transcript per source.
+    #[default]
+    PerSource,
+
+    /// The sources are mixed to mono (see [mix_sources]), producing a single transcript.
+    Mix,
+}
+
+/// How live audio is accumulated into windows before it's transcribed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum AccumulationMode {
+    /// Audio is transcribed in fixed-length windows, ending early when the speaker pauses.
+    #[default]
+    Window,
+
+    /// Wake words are ignored, and each utterance (delimited by the VAD endpointing, see
+    /// [Endpointer]) is transcribed as a whole and sent as a
+    /// [crate::messages::Event::Utterance] event, for live dictation.
+    ///
+    /// Silence between utterances isn't transcribed.
+    Dictation,
+}
+
+/// The default maximum number of audio sources transcribed together.
+pub const DEFAULT_MAX_AUDIO_SOURCES: usize = 4;
+
+/// A sample format the microphone can capture in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum InputSampleFormat {
+    F32,
+    I16,
+    U16,
+}
+
+impl InputSampleFormat {
+    /// Returns the matching `cpal` sample format.
+    pub fn sample_format(self) -> SampleFormat {
+        match self {
+            InputSampleFormat::F32 => SampleFormat::F32,
+            InputSampleFormat::I16 => SampleFormat::I16,
+            InputSampleFormat::U16 => SampleFormat::U16,
+        }
+    }
+}
+
+/// The default sample format preference: `F32`, then `I16`, then `U16`.
+pub const DEFAULT_SAMPLE_FORMATS: [InputSampleFormat; 3] = [
+    InputSampleFormat::F32,
+    InputSampleFormat::I16,
+    InputSampleFormat::U16,
+];
+
+impl Context {
+    /// Creates a new context with the default settings.
+    pub fn new(model_path: String, wake_words: Vec<String>) -> Self {
+        Self {
+            model_path,
+            asset_dir: None,
+            wake_words,
+            listen: ListenConfig::default(),
+            warmup_model: true,
+            language: None,
+            transcription: TranscriptionConfig::default(),
+            detect_n_threads: default_n_threads(),
+            commit_interval_ms: 0,
+            compression_ratio_threshold: DEFAULT_COMPRESSION_RATIO_THRESHOLD,
+            ignore_wake_words: false,
+            detect_every_n_windows: 1,
+            wake_word_events: false,
+            first_word_events: false,
+            no_speech_events: false,
+            no_speech_interval_ms: DEFAULT_NO_SPEECH_INTERVAL_MS,
+            min_confidence: 0.0,
+            trim_segments: true,
+            max_segments: 0,
+            merge_sentences: false,
+            send_raw_audio: false,
+            raw_audio_interval_ms: 0,
+            include_raw_transcript: false,
+            report_language: false,
+            json_events: JsonEvents::default(),
+            retry_empty_transcripts: false,
+            use_gpu: true,
+            gpu_fallback: true,
+            mmap_model: false,
+            input_channel: None,
+            sample_formats: DEFAULT_SAMPLE_FORMATS.to_vec(),
+            correct_rate_drift: false,
+            audio_ctx: None,
+            max_pool_workers: DEFAULT_MAX_POOL_WORKERS,
+            overlap_policy: OverlapPolicy::default(),
+            max_audio_sources: DEFAULT_MAX_AUDIO_SOURCES,
+            source_mode: SourceMode::default(),
+            n_best: 1,
+            accumulation_mode: AccumulationMode::default(),
+            streaming: false,
+            wake_word_max_distance: DEFAULT_WAKE_WORD_MAX_DISTANCE,
+            strip_annotations: true,
+        }
+    }
+
+    /// Returns a copy of the context with the given overrides applied.
+    pub fn with_overrides(&self, overrides: &ContextOverrides) -> Self {
+        let mut ctx = self.clone();
+        if let Some(language) = &overrides.language {
+            ctx.language = Some(language.clone());
+        }
+        if let Some(temperature) = overrides.temperature {
+            ctx.transcription.temperature = temperature;
+        }
+        if let Some(n_threads) = overrides.n_threads {
+            ctx.detect_n_threads = n_threads;
+            ctx.transcription.n_threads = n_threads;
+        }
+        ctx
+    }
+
+    /// The model path, resolved against [Context::asset_dir] (see [resolve_asset_path]).
+    pub fn resolved_model_path(&self) -> String {
+        resolve_asset_path(&self.model_path, self.asset_dir.as_deref())
+    }
+}
+
+/// The settings for how audio is captured and split into windows.
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub struct ListenConfig {
+    /// The maximum length (in milliseconds) of a single transcription window.
+    ///
+    /// Continuous speech longer than this is chunked into multiple windows instead of being
+    /// buffered indefinitely.
+    pub max_window_ms: usize,
+
+    /// The amount of audio (in milliseconds) carried over into the next window when a window is
+    /// force-flushed, so words spoken across the boundary aren't lost.
+    pub overlap_ms: usize,
+
+    /// The RMS energy above which audio is considered speech.
+    pub vad_threshold: f32,
+
+    /// The length of silence (in milliseconds) considered a short pause between words/clauses.
+    ///
+    /// Short pauses don't end the utterance; audio keeps being accumulated.
+    pub short_pause_ms: usize,
+
+    /// The length of silence (in milliseconds) after speech that ends the utterance.
+    ///
+    /// Endpointing is disabled if this is `0`.
+    pub endpoint_silence_ms: usize,
+
+    /// The maximum length (in milliseconds) of an utterance in dictation mode (see
+    /// [AccumulationMode::Dictation]), limited to [ListenConfig::max_window_ms] (which is used if
+    /// this is `0`).
+    ///
+    /// Longer utterances are finalized and continued as a new one, carrying over
+    /// [ListenConfig::overlap_ms] of audio so words at the boundary aren't lost.
+    pub max_utterance_ms: usize,
+
+    /// Determines if automatic gain control is applied to the audio.
+    pub agc_enabled: bool,
+
+    /// The RMS level the automatic gain control adjusts speech towards.
+    pub agc_target_rms: f32,
+
+    /// How quickly (from `0` to `1`) the gain is lowered when the audio gets louder.
+    pub agc_attack: f32,
+
+    /// How quickly (from `0` to `1`) the gain is raised when the audio gets quieter.
+    pub agc_release: f32,
+
+    /// The amount of audio (in milliseconds) from before the window a wake word is detected in
+    /// that's included when the command is transcribed.
+    ///
+    /// This keeps the start of a command from being clipped when the wake word straddles two
+    /// windows. Pre-roll is disabled if this is `0`.
+    pub preroll_ms: usize,
+}
+
+impl Default for ListenConfig {
+    fn default() -> Self {
+        Self {
+            max_window_ms: DEFAULT_MAX_WINDOW_MS,
+            overlap_ms: DEFAULT_OVERLAP_MS,
+            vad_threshold: DEFAULT_VAD_THRESHOLD,
+            short_pause_ms: DEFAULT_SHORT_PAUSE_MS,
+            endpoint_silence_ms: DEFAULT_ENDPOINT_SILENCE_MS,
+            max_utterance_ms: 0,
+            agc_enabled: false,
+            agc_target_rms: DEFAULT_AGC_TARGET_RMS,
+            agc_attack: DEFAULT_AGC_ATTACK,
+            agc_release: DEFAULT_AGC_RELEASE,
+            preroll_ms: DEFAULT_PREROLL_MS,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("ListenConfigError: {0}")]
+pub struct ListenConfigError(String);
+
+impl ListenConfig {
+    /// Checks that the settings are usable, returning an error describing the first invalid one.
+    pub fn validate(&self) -> VirgilResult<()> {
+        let error = |msg: &str| Err(ListenConfigError(msg.into()).into());
+        if self.max_window_ms == 0 {
+            return error("max_window_ms must be greater than 0");
+        }
+        if self.overlap_ms >= self.max_window_ms {
+            return error("overlap_ms must be less than max_window_ms");
+        }
+        if self.max_utterance_ms != 0 && self.overlap_ms >= self.max_utterance_ms {
+            return error("overlap_ms must be less than max_utterance_ms");
+        }
+        if self.preroll_ms > self.max_window_ms {
+            return error("preroll_ms must not exceed max_window_ms");
+        }
+        if !(self.vad_threshold.is_finite() && self.vad_threshold >= 0.0) {
+            return error("vad_threshold must be a non-negative number");
+        }
+        if !(self.agc_target_rms > 0.0 && self.agc_target_rms <= 1.0) {
+            return error("agc_target_rms must be in (0, 1]");
+        }
+        if !((0.0..=1.0).contains(&self.agc_attack) && (0.0..=1.0).contains(&self.agc_release)) {
+            return error("agc_attack and agc_release must be in [0, 1]");
+        }
+        Ok(())
+    }
+
+    /// The maximum number of samples (captured at `sample_rate`) in a single transcription window.
+    pub fn max_window_samples(&self, sample_rate: usize) -> usize {
+        ms_to_samples(self.max_window_ms, sample_rate).max(1)
+    }
+
+    /// The maximum number of samples (captured at `sample_rate`) in a single dictated utterance.
+    pub fn max_utterance_samples(&self, sample_rate: usize) -> usize {
+        let max_window_samples = self.max_window_samples(sample_rate);
+        if self.max_utterance_ms == 0 {
+            return max_window_samples;
+        }
+        ms_to_samples(self.max_utterance_ms, sample_rate).clamp(1, max_window_samples)
+    }
+
+    /// The number of samples (captured at `sample_rate`) carried over into the next window.
+    pub fn overlap_samples(&self, sample_rate: usize) -> usize {
+        ms_to_samples(self.overlap_ms, sample_rate).min(self.max_window_samples(sample_rate))
+    }
+
+    /// The number of samples (captured at `sample_rate`) kept as pre-roll.
+    pub fn preroll_samples(&self, sample_rate: usize) -> usize {
+        ms_to_samples(self.preroll_ms, sample_rate).min(self.max_window_samples(sample_rate))
+    }
+}
+
+/// The default number of threads used for inference (the number of available cores).
+pub fn default_n_threads() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// The settings `Whisper` decodes with, so every inference builds its parameters the same way
+/// (see [TranscriptionConfig::to_full_params]).
+#[derive(Debug, Clone, Copy, Encode, Decode)]
+pub struct TranscriptionConfig {
+    /// The number of candidates sampled when decoding greedily (the most likely one is kept).
+    pub best_of: usize,
+
+    /// The number of beams used for beam search (decodes greedily if `0`).
+    pub beam_size: usize,
+
+    /// The number of threads used for inference (uses `Whisper`'s default if `0`).
+    pub n_threads: usize,
+
+    /// The temperature used when sampling.
+    pub temperature: f32,
+}
+
+impl Default for TranscriptionConfig {
+    fn default() -> Self {
+        Self {
+            best_of: 1,
+            beam_size: 0,
+            n_threads: default_n_threads(),
+            temperature: 0.0,
+        }
+    }
+}
+
+impl TranscriptionConfig {
+    /// The same settings, decoding greedily with the given number of candidates.
+    pub fn greedy(self, best_of: usize) -> Self {
+        Self {
+            best_of,
+            beam_size: 0,
+            ..self
+        }
+    }
+
+    /// The sampling strategy to decode with.
+    pub fn sampling_strategy(&self) -> SamplingStrategy {
+        if self.beam_size > 0 {
+            SamplingStrategy::BeamSearch {
+                beam_size: self.beam_size.min(MAX_BEAM_SIZE) as i32,
+                // NOTE: `Whisper` doesn't implement patience yet.
+                patience: -1.0,
+            }
+        } else {
+            SamplingStrategy::Greedy {
+                best_of: self.best_of.max(1) as i32,
+            }
+        }
+    }
+
+    /// Creates the `Whisper` parameters for these settings.
+    pub fn to_full_params<'a, 'b>(self) -> FullParams<'a, 'b> {
+        let mut params = FullParams::new(self.sampling_strategy());
+        params.set_temperature(self.temperature);
+        if self.n_threads > 0 {
+            params.set_n_threads(self.n_threads as i32);
+        }
+        params
+    }
+}
+
+/// Overrides for [Context] fields that only apply to a single request.
+#[derive(Debug, Default, Encode, Decode)]
+pub struct ContextOverrides {
+    pub language: Option<String>,
+    pub temperature: Option<f32>,
+    pub n_threads: Option<usize>,
+}
+
+/// The default maximum length of a transcription window (`Whisper` processes 30s at a time).
+pub const DEFAULT_MAX_WINDOW_MS: usize = 30_000;
+
+/// The default amount of audio (in milliseconds) carried over into the next window.
+pub const DEFAULT_OVERLAP_MS: usize = 500;
+
+/// The default RMS energy above which audio is considered speech.
+pub const DEFAULT_VAD_THRESHOLD: f32 = 0.01;
+
+/// The default length of a short pause (in milliseconds).
+pub const DEFAULT_SHORT_PAUSE_MS: usize = 300;
+
+/// The default length of silence (in milliseconds) that ends an utterance.
+pub const DEFAULT_ENDPOINT_SILENCE_MS: usize = 1_000;
+
+/// The default minimum time (in milliseconds) between no-speech events.
+pub const DEFAULT_NO_SPEECH_INTERVAL_MS: usize = 1_000;
+
+/// The default maximum number of [TranscriberPool] workers.
+pub const DEFAULT_MAX_POOL_WORKERS: usize = 2;
+
+/// The default compression ratio above which a segment is dropped (the same as `Whisper`'s).
+pub const DEFAULT_COMPRESSION_RATIO_THRESHOLD: f32 = 2.4;
+
+/// The default value of [Context::wake_word_max_distance].
+pub const DEFAULT_WAKE_WORD_MAX_DISTANCE: usize = 1;
+
+/// A byte buffer passed across the FFI boundary, bundling the pointer with its length.
+///
+/// Buffers allocated by Rust are boxed slices whose length is exactly the number of bytes
+/// reported to Dart, so they can always be freed with the same length.
+#[derive(Debug)]
+pub struct FfiBuffer {
+    ptr: *mut ffi::c_void,
+    len: usize,
+}
+
+impl FfiBuffer {
+    /// Creates a buffer that owns the given bytes.
+    ///
+    /// Any spare capacity is released, so the allocation is exactly as long as the buffer (which
+    /// is what [FfiBuffer::free] expects).
+    pub fn from_vec(bytes: Vec<u8>) -> Self {
+        let len = bytes.len();
+        let ptr: *mut ffi::c_void = Box::into_raw(bytes.into_boxed_slice()).cast();
+        Self { ptr, len }
+    }
+
+    /// Creates a buffer from a pointer and length received over FFI.
+    pub fn from_raw_parts(ptr: *mut ffi::c_void, len: usize) -> Self {
+        Self { ptr, len }
+    }
+
+    /// Returns the pointer and length of the buffer, handing ownership to the caller.
+    pub fn into_raw_parts(self) -> (*mut ffi::c_void, usize) {
+        (self.ptr, self.len)
+    }
+
+    /// Returns the bytes in the buffer, or `None` if the pointer is null.
+    pub fn as_slice(&self) -> Option<&[u8]> {
+        if self.ptr.is_null() {
+            return None;
+        }
+        unsafe { slice_from_raw_parts(self.ptr.cast::<u8>(), self.len).as_ref() }
+    }
+
+    /// Frees the buffer.
+    ///
+    /// # Note
+    /// The buffer must have been allocated by [FfiBuffer::from_vec]; null buffers are ignored.
+    pub fn free(self) {
+        if self.ptr.is_null() {
+            return;
+        }
+        unsafe {
+            let _ = Box::from_raw(slice_from_raw_parts_mut(self.ptr.cast::<u8>(), self.len));
+        }
+    }
 }
 
-/// Serialize the given encodable value.
+/// The `bincode` configuration shared with Dart.
+///
+/// # Note
+/// Everything sent to Dart is little-endian, regardless of the host's byte order.
+pub const BINCODE_CONFIG: Configuration<LittleEndian, Fixint> = bincode::config::standard()
+    .with_fixed_int_encoding()
+    .with_little_endian();
+
+/// The magic bytes at the start of every buffer written by [serialize].
+pub const BUFFER_MAGIC: [u8; 4] = *b"VRGL";
+
+/// Identifies [BINCODE_CONFIG] in the header of buffers written by [serialize].
+///
+/// # Note
+/// This must be bumped whenever [BINCODE_CONFIG] changes, so that buffers written with the old
+/// config are rejected instead of misinterpreted.
+pub const BINCODE_CONFIG_TAG: u8 = 1;
+
+/// The length (in bytes) of the header written by [serialize]: [BUFFER_MAGIC] followed by
+/// [BINCODE_CONFIG_TAG].
+pub const BUFFER_HEADER_LEN: usize = BUFFER_MAGIC.len() + 1;
+
+/// The default RMS level the automatic gain control adjusts speech towards.
+pub const DEFAULT_AGC_TARGET_RMS: f32 = 0.1;
+
+/// The default rate at which the gain is lowered.
+pub const DEFAULT_AGC_ATTACK: f32 = 0.5;
+
+/// The default rate at which the gain is raised.
+pub const DEFAULT_AGC_RELEASE: f32 = 0.05;
+
+/// The default amount of pre-roll audio (in milliseconds).
+pub const DEFAULT_PREROLL_MS: usize = 300;
+
+/// The maximum gain applied by the automatic gain control.
+pub const MAX_AGC_GAIN: f32 = 20.0;
+
+/// The default for [MAX_PAYLOAD_BYTES] (64 MiB).
+pub const DEFAULT_MAX_PAYLOAD_BYTES: usize = 64 * 1024 * 1024;
+
+/// The maximum size (in bytes) of a buffer written by [serialize].
+pub static MAX_PAYLOAD_BYTES: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_PAYLOAD_BYTES);
+
+#[derive(Debug, Error)]
+#[error("SerializeError: {0}")]
+pub struct SerializeError(String);
+
+/// Serialize the given encodable value, prefixed with a [BUFFER_HEADER_LEN] byte header.
+///
+/// [Message::byte_len] is only used as a size hint: the returned buffer is always exactly as long
+/// as the header and encoded value (the length written to `value_len_out`), so freeing it with
+/// that length frees the whole allocation.
+///
+/// Payloads larger than [MAX_PAYLOAD_BYTES] (including ones whose size hint is, e.g. because of a
+/// bad [Message::byte_len]) and failed allocations are returned as errors, rather than aborting.
 ///
 /// # Note
-/// The caller must free the the returned pointer with [free_rust_ptr].
+/// The caller must free the returned pointer with [free_rust_ptr].
 pub fn serialize<T: Message>(
     value: T,
     value_len_out: *mut usize,
 ) -> VirgilResult<*mut ffi::c_void> {
-    let mut bytes = vec![0; value.byte_len()];
-    let written = encode_into_slice(
-        value,
-        bytes.as_mut_slice(),
-        bincode::config::standard().with_fixed_int_encoding(),
-    )?;
-    unsafe { *value_len_out = written };
-    let response_ptr: *mut ffi::c_void = Box::into_raw(bytes.into_boxed_slice()).cast();
+    let max_len = MAX_PAYLOAD_BYTES.load(Ordering::Relaxed);
+    let check_len = |len: usize| {
+        if len > max_len {
+            return Err(SerializeError(format!(
+                "Payload of {} ({len} bytes) exceeds the maximum of {max_len} bytes",
+                type_name::<T>()
+            )));
+        }
+        Ok(())
+    };
+
+    let len = BUFFER_HEADER_LEN.saturating_add(value.byte_len());
+    check_len(len)?;
+    let mut bytes = Vec::new();
+    bytes
+        .try_reserve_exact(len)
+        .map_err(|e| SerializeError(format!("Unable to allocate {len} bytes: {e}")))?;
+    bytes.resize(len, 0);
+    bytes[..BUFFER_MAGIC.len()].copy_from_slice(&BUFFER_MAGIC);
+    bytes[BUFFER_MAGIC.len()] = BINCODE_CONFIG_TAG;
+    match encode_into_slice(&value, &mut bytes[BUFFER_HEADER_LEN..], BINCODE_CONFIG) {
+        Ok(written) => bytes.truncate(BUFFER_HEADER_LEN + written),
+        Err(EncodeError::UnexpectedEnd) => {
+            warn!(
+                "Byte length of {} underestimated ({} bytes), re-encoding",
+                type_name::<T>(),
+                bytes.len() - BUFFER_HEADER_LEN
+            );
+            let encoded = encode_to_vec(&value, BINCODE_CONFIG)?;
+            check_len(BUFFER_HEADER_LEN + encoded.len())?;
+            bytes.truncate(BUFFER_HEADER_LEN);
+            bytes.extend(encoded);
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    let (response_ptr, response_len) = FfiBuffer::from_vec(bytes).into_raw_parts();
+    unsafe { *value_len_out = response_len };
     Ok(response_ptr)
 }
 
 #[derive(Debug, Error)]
-#[error("DeserializeError: {0}")]
-pub struct DeserializeError(String);
+pub enum DeserializeError {
+    #[error("DeserializeError: Buffer pointer is null")]
+    NullPointer,
+
+    #[error("DeserializeError: {0}")]
+    InvalidBuffer(String),
+}
 
 /// Deserialize the value represented by the given pointer and length.
+///
+/// Null pointers (which a Dart isolate can pass during teardown) are rejected before the buffer
+/// is read, as are buffers without the header written by [serialize] (or written with a
+/// different [BINCODE_CONFIG_TAG]).
 pub fn deserialize<T: Decode<()>>(ptr: *mut ffi::c_void, len: usize) -> VirgilResult<T> {
-    let slice = unsafe {
-        let ptr: *mut u8 = ptr.cast();
-        slice_from_raw_parts(ptr, len)
-            .as_ref()
-            .ok_or_else(|| DeserializeError("Unable to get reference to raw slice".into()))?
+    if ptr.is_null() {
+        return Err(DeserializeError::NullPointer.into());
+    }
+    let buffer = FfiBuffer::from_raw_parts(ptr, len);
+    let slice = buffer.as_slice().ok_or(DeserializeError::NullPointer)?;
+
+    let Some((header, body)) = slice.split_at_checked(BUFFER_HEADER_LEN) else {
+        return Err(DeserializeError::InvalidBuffer(format!(
+            "Buffer too short for header ({len} bytes)"
+        ))
+        .into());
     };
+    if header[..BUFFER_MAGIC.len()] != BUFFER_MAGIC {
+        return Err(
+            DeserializeError::InvalidBuffer("Buffer is missing the magic bytes".into()).into(),
+        );
+    }
+    let tag = header[BUFFER_MAGIC.len()];
+    if tag != BINCODE_CONFIG_TAG {
+        return Err(DeserializeError::InvalidBuffer(format!(
+            "Incompatible bincode config (expected tag {BINCODE_CONFIG_TAG}, found {tag})"
+        ))
+        .into());
+    }
 
-    let (decoded, _): (T, usize) =
-        decode_from_slice(slice, bincode::config::standard().with_fixed_int_encoding())?;
+    let (decoded, _): (T, usize) = decode_from_slice(body, BINCODE_CONFIG)?;
 
     Ok(decoded)
 }
@@ -62,102 +882,3912 @@ pub fn deserialize<T: Decode<()>>(ptr: *mut ffi::c_void, len: usize) -> VirgilRe
 /// The expected sample rate of the microphone.
 pub const EXPECTED_SAMPLE_RATE: usize = 16_000;
 
-/// Initialize the `Whisper` model.
-pub fn init_model(model_path: &str) -> VirgilResult<WhisperState> {
-    let span = span!(Level::TRACE, "init_model");
-    let _enter = span.enter();
+/// The `libsamplerate` converter used by [resample_to_16k].
+const RESAMPLER: ConverterType = ConverterType::SincMediumQuality;
 
-    let model_ctx =
-        WhisperContext::new_with_params(model_path, WhisperContextParameters::default())?;
-    let model = model_ctx.create_state().map_err(|e| e)?;
-    info!("Model created: {model:?}");
+/// Resamples audio captured at `sample_rate` to [EXPECTED_SAMPLE_RATE], using `libsamplerate`'s
+/// band-limited sinc converter (see [RESAMPLER]).
+///
+/// Frequencies above 8kHz are filtered out when downsampling, so they don't alias into the speech
+/// band. Audio captured below 16kHz (e.g. 8kHz Bluetooth headsets) is upsampled, which can't
+/// restore the frequencies the device didn't capture.
+///
+/// Audio at an unknown rate (`0`), or at a rate `libsamplerate` can't convert from, is left as is.
+///
+/// # Note
+/// This should be run on a whole window of audio at once; resampling each chunk separately
+/// introduces artifacts at the chunk boundaries.
+pub fn resample_to_16k(audio_data: &[f32], sample_rate: usize) -> Vec<f32> {
+    if sample_rate == EXPECTED_SAMPLE_RATE || sample_rate == 0 || audio_data.is_empty() {
+        return audio_data.to_vec();
+    }
 
-    Ok(model)
+    samplerate::convert(
+        sample_rate as u32,
+        EXPECTED_SAMPLE_RATE as u32,
+        1,
+        RESAMPLER,
+        audio_data,
+    )
+    .unwrap_or_else(|e| {
+        error!("Unable to resample audio from {sample_rate}Hz: {e}");
+        audio_data.to_vec()
+    })
 }
 
-/// Converts audio data to text using the provided `Whisper` model and parameters.
-pub fn transcribe(
-    model: &mut WhisperState,
-    params: FullParams,
-    audio_data: &[f32],
-) -> VirgilResult<String> {
-    model.full(params, audio_data)?;
-    let mut transcript = String::with_capacity(2048);
-    let num_segments = model.full_n_segments()?;
-    for i in 0..num_segments {
-        let segment = model.full_get_segment_text(i)?;
-        transcript.push_str(&segment);
+#[derive(Debug, Error)]
+#[error("ModelPathError: {0}")]
+pub struct ModelPathError(String);
+
+/// Resolves a relative path against the asset directory (if any).
+///
+/// Absolute paths, and all paths if there's no asset directory, are returned as is.
+pub fn resolve_asset_path(path: &str, asset_dir: Option<&str>) -> String {
+    match asset_dir {
+        Some(asset_dir) if Path::new(path).is_relative() => Path::new(asset_dir)
+            .join(path)
+            .to_string_lossy()
+            .into_owned(),
+        _ => path.into(),
     }
-    Ok(transcript)
 }
 
-/// Check for the specified wake words in the audio data.
-pub fn detect_wake_words(
-    model: &mut WhisperState,
-    params: FullParams,
-    audio_data: &[f32],
-    wake_words: &Vec<String>,
-) -> VirgilResult<bool> {
-    let span = span!(Level::TRACE, "detect_wake_words");
-    let _enter = span.enter();
+/// Checks that the model at the given path exists and is readable.
+pub fn check_model_path(model_path: &str) -> VirgilResult<()> {
+    let metadata = fs::metadata(model_path)
+        .map_err(|e| ModelPathError(format!("Unable to access model at `{model_path}`: {e}")))?;
+    if !metadata.is_file() {
+        return Err(ModelPathError(format!("Model path `{model_path}` is not a file")).into());
+    }
+    fs::File::open(model_path)
+        .map_err(|e| ModelPathError(format!("Unable to read model at `{model_path}`: {e}")))?;
+    Ok(())
+}
 
-    let transcript = transcribe(model, params, audio_data)?.to_lowercase();
-    for word in wake_words {
-        if transcript.contains(&word.to_lowercase()) {
-            info!("Wake word detected: {word}");
-            return Ok(true);
+#[derive(Debug, Error)]
+#[error("WavError: {0}")]
+pub struct WavError(String);
+
+/// Reads the WAV file at the given path, returning the (mono) samples and the sample rate.
+///
+/// See [wav_samples_to_f32] for the supported sample formats; multiple channels are averaged.
+pub fn read_wav(path: &str) -> VirgilResult<(Vec<f32>, usize)> {
+    let bytes =
+        fs::read(path).map_err(|e| WavError(format!("Unable to read WAV at `{path}`: {e}")))?;
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(WavError(format!("`{path}` is not a WAV file")).into());
+    }
+
+    // Find the format and data chunks
+    let mut format = None;
+    let mut data = None;
+    let mut offset = 12;
+    while offset + 8 <= bytes.len() {
+        let id = &bytes[offset..offset + 4];
+        let len = u32::from_le_bytes(bytes[offset + 4..offset + 8].try_into()?) as usize;
+        let body = &bytes[offset + 8..(offset + 8 + len).min(bytes.len())];
+        match id {
+            b"fmt " if body.len() >= 16 => format = Some(body),
+            b"data" => data = Some(body),
+            _ => {}
         }
+        // NOTE: Chunks are padded to an even length.
+        offset += 8 + len + len % 2;
+    }
+    let format = format.ok_or_else(|| WavError("Missing `fmt ` chunk".into()))?;
+    let data = data.ok_or_else(|| WavError("Missing `data` chunk".into()))?;
+
+    let mut audio_format = u16::from_le_bytes([format[0], format[1]]);
+    let channels = u16::from_le_bytes([format[2], format[3]]).max(1) as usize;
+    let sample_rate = u32::from_le_bytes(format[4..8].try_into()?) as usize;
+    let bits_per_sample = u16::from_le_bytes([format[14], format[15]]);
+
+    // NOTE: Extensible WAVs store the actual format at the start of their sub-format GUID.
+    if audio_format == WAV_FORMAT_EXTENSIBLE && format.len() >= 26 {
+        audio_format = u16::from_le_bytes([format[24], format[25]]);
     }
-    Ok(false)
+
+    let samples = wav_samples_to_f32(audio_format, bits_per_sample, data)?;
+    Ok((extract_channel(&samples, channels, None), sample_rate))
 }
 
-#[derive(Debug, Error)]
-#[error("MicrophoneConfigError: {0}")]
-pub struct MicrophoneConfigError(String);
+/// The `fmt ` chunk format tag of integer PCM.
+const WAV_FORMAT_PCM: u16 = 1;
 
-/// Initializes the microphone.
-pub fn init_microphone(audio_data_tx: mpsc::Sender<Vec<f32>>) -> VirgilResult<Stream> {
-    let span = span!(Level::TRACE, "init_microphone");
-    let _enter = span.enter();
+/// The `fmt ` chunk format tag of floating-point PCM.
+const WAV_FORMAT_IEEE_FLOAT: u16 = 3;
 
-    // Initialize microphone
-    let host = cpal::default_host();
-    let microphone = host
-        .default_input_device()
-        .ok_or_else(|| MicrophoneConfigError("Default input device not found".into()))?;
-    let config = microphone
-        .supported_input_configs()?
-        .next()
-        .ok_or_else(|| MicrophoneConfigError("No supported configs found".into()))?
-        .try_with_sample_rate(SampleRate(EXPECTED_SAMPLE_RATE as u32))
-        .ok_or_else(|| MicrophoneConfigError(format!("No supported configs found with the the specified sample rate: {EXPECTED_SAMPLE_RATE} Hz")))?
-        .config();
+/// The `fmt ` chunk format tag of WAVs whose actual format is in their sub-format GUID.
+const WAV_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
 
-    // Initialize input stream
-    fn input_stream_listener(sender: mpsc::Sender<Vec<f32>>, data: &[f32]) {
-        let span = span!(Level::TRACE, "input_stream_listener");
-        let _enter = span.enter();
+/// Normalizes the samples of a WAV `data` chunk to `f32` samples in `[-1, 1]`, according to the
+/// format tag and bit depth of its `fmt ` chunk.
+///
+/// 8-bit (unsigned), 16/24/32-bit (signed) integer and 32-bit float PCM are supported; other
+/// (e.g. compressed) formats are rejected.
+pub fn wav_samples_to_f32(
+    audio_format: u16,
+    bits_per_sample: u16,
+    data: &[u8],
+) -> VirgilResult<Vec<f32>> {
+    let samples = match (audio_format, bits_per_sample) {
+        (WAV_FORMAT_PCM, 8) => data.iter().map(|&b| (b as f32 - 128.0) / 128.0).collect(),
+        (WAV_FORMAT_PCM, 16) => data
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32_768.0)
+            .collect(),
+        (WAV_FORMAT_PCM, 24) => data
+            .chunks_exact(3)
+            // NOTE: Shifting back down sign-extends the 24-bit value.
+            .map(|b| (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f32 / 8_388_608.0)
+            .collect(),
+        (WAV_FORMAT_PCM, 32) => data
+            .chunks_exact(4)
+            .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2_147_483_648.0)
+            .collect(),
+        (WAV_FORMAT_IEEE_FLOAT, 32) => data
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect(),
+        (WAV_FORMAT_PCM | WAV_FORMAT_IEEE_FLOAT, _) => {
+            return Err(WavError(format!("Unsupported WAV bit depth: {bits_per_sample}")).into());
+        }
+        _ => {
+            return Err(WavError(format!(
+                "Unsupported WAV format: {audio_format:#06x} (only uncompressed PCM is supported)"
+            ))
+            .into());
+        }
+    };
+    Ok(samples)
+}
 
-        match sender.try_send(data.into()) {
-            Ok(_) => {}
-            Err(e) => {
-                error!("Unable to send audio data: {e}");
-            }
+/// The length (in milliseconds) of the chunks recordings are split into for transcription.
+///
+/// `Whisper` only looks at 30s of audio at a time, and silently drops anything past that.
+pub const FILE_CHUNK_MS: usize = 30_000;
+
+/// The amount of audio (in milliseconds) shared by consecutive chunks of a recording, so words
+/// spoken across a chunk boundary aren't cut in half.
+pub const FILE_CHUNK_OVERLAP_MS: usize = 1_000;
+
+/// Splits the (16kHz) audio data into [FILE_CHUNK_MS] chunks that overlap by
+/// [FILE_CHUNK_OVERLAP_MS], returning each chunk with its offset (in milliseconds).
+pub fn chunk_audio(audio_data: &[f32]) -> Vec<(u64, Vec<f32>)> {
+    let chunk_len = ms_to_samples(FILE_CHUNK_MS, EXPECTED_SAMPLE_RATE);
+    let step = chunk_len - ms_to_samples(FILE_CHUNK_OVERLAP_MS, EXPECTED_SAMPLE_RATE);
+    let mut chunks = Vec::with_capacity(audio_data.len() / step + 1);
+    let mut start = 0;
+    loop {
+        let end = (start + chunk_len).min(audio_data.len());
+        let offset_ms = samples_to_ms(start, EXPECTED_SAMPLE_RATE);
+        chunks.push((offset_ms, audio_data[start..end].to_vec()));
+        if end == audio_data.len() {
+            return chunks;
         }
+        start += step;
     }
-    let stream = microphone.build_input_stream(
-        &config,
-        move |data: &[f32], _: &InputCallbackInfo| {
-            input_stream_listener(audio_data_tx.clone(), data)
-        },
-        move |err| error!("MicrophoneListenerError: {err}"),
-        None,
-    )?;
-
-    info!("Microphone initalized");
-    Ok(stream)
 }
 
-pub struct SendStream(pub Stream);
-unsafe impl Send for SendStream {}
-unsafe impl Sync for SendStream {}
+/// Merges the segments transcribed from each chunk (see [chunk_audio]) into segments for the
+/// whole recording.
+///
+/// Segment times are shifted by their chunk's offset, so they're relative to the start of the
+/// recording; segments starting before the end of the previous chunk's last segment (i.e. in the
+/// overlap) are dropped as duplicates.
+pub fn merge_chunk_segments(chunks: Vec<(u64, Vec<Segment>)>) -> Vec<Segment> {
+    let mut merged: Vec<Segment> = Vec::new();
+    for (offset_ms, segments) in chunks {
+        let covered_ms = merged.last().map_or(0, |segment| segment.end_ms);
+        for mut segment in segments {
+            segment.start_ms += offset_ms;
+            segment.end_ms += offset_ms;
+            if !merged.is_empty() && segment.start_ms < covered_ms {
+                continue;
+            }
+            merged.push(segment);
+        }
+    }
+    merged
+}
+
+/// Mixes the (mono, same-rate) audio sources into one, by averaging them sample by sample.
+///
+/// The sources are aligned at their start, and shorter sources are padded with silence, so the
+/// mix is as long as the longest source. Every sample is divided by the number of sources, so
+/// the level doesn't jump when a source ends.
+pub fn mix_sources(sources: &[Vec<f32>]) -> Vec<f32> {
+    let len = sources.iter().map(Vec::len).max().unwrap_or(0);
+    let mut mixed = vec![0.0; len];
+    for source in sources {
+        for (mixed, sample) in mixed.iter_mut().zip(source) {
+            *mixed += sample;
+        }
+    }
+    let num_sources = sources.len().max(1) as f32;
+    for sample in &mut mixed {
+        *sample /= num_sources;
+    }
+    mixed
+}
+
+/// Converts interleaved multi-channel audio to mono, by extracting the `channel`th channel or, if
+/// `channel` is `None`, downmixing (averaging) all channels.
+///
+/// Incomplete trailing frames are dropped.
+pub fn extract_channel(audio_data: &[f32], channels: usize, channel: Option<usize>) -> Vec<f32> {
+    if channels <= 1 {
+        return audio_data.to_vec();
+    }
+    let frames = audio_data.chunks_exact(channels);
+    match channel {
+        Some(channel) => frames.map(|frame| frame[channel]).collect(),
+        None => frames
+            .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+            .collect(),
+    }
+}
+
+/// Information about a loaded `Whisper` model.
+#[derive(Debug, Clone)]
+pub struct ModelInfo {
+    pub path: String,
+    pub use_gpu: bool,
+    pub memory_estimate: ModelMemoryEstimate,
+
+    /// Determines if the model supports languages other than English.
+    pub multilingual: bool,
+}
+
+/// An estimate of the memory used by a loaded `Whisper` model.
+#[derive(Debug, Clone, Copy, Encode, Decode)]
+pub struct ModelMemoryEstimate {
+    /// The size of the model weights (in bytes).
+    pub model_bytes: u64,
+
+    /// The estimated size of the buffers used during inference (in bytes).
+    pub working_bytes: u64,
+}
+
+impl ModelMemoryEstimate {
+    /// The rough size of the compute buffers allocated by `whisper.cpp`, per layer.
+    const COMPUTE_BYTES_PER_LAYER: u64 = 4 * 1024 * 1024;
+
+    /// Estimates the memory used by the given model, whose weights take up `model_bytes`.
+    pub fn new(model_ctx: &WhisperContext, model_bytes: u64) -> Self {
+        let n_text_layer = model_ctx.model_n_text_layer() as u64;
+        let n_text_state = model_ctx.model_n_text_state() as u64;
+        let n_text_ctx = model_ctx.model_n_text_ctx() as u64;
+        let n_audio_layer = model_ctx.model_n_audio_layer() as u64;
+        let n_audio_ctx = model_ctx.model_n_audio_ctx() as u64;
+
+        // NOTE: The self-attention and cross-attention KV caches store `f16` keys and values for
+        // every text layer.
+        let f16_bytes = 2;
+        let kv_self = 2 * n_text_layer * n_text_ctx * n_text_state * f16_bytes;
+        let kv_cross = 2 * n_text_layer * n_audio_ctx * n_text_state * f16_bytes;
+        let compute = (n_audio_layer + n_text_layer) * Self::COMPUTE_BYTES_PER_LAYER;
+
+        Self {
+            model_bytes,
+            working_bytes: kv_self + kv_cross + compute,
+        }
+    }
+
+    /// The total estimated memory usage (in bytes).
+    pub fn total_bytes(&self) -> u64 {
+        self.model_bytes + self.working_bytes
+    }
+}
+
+/// A loaded model's weights, and whether they were loaded on the GPU.
+type CachedModelContext = (bool, Arc<WhisperContext>);
+
+/// The loaded model weights, keyed by model path, so that models aren't reloaded every time a
+/// model state is needed.
+///
+/// Entries are kept until they're evicted (see [evict_model_context]); the weights are only freed
+/// once every model state created from them is dropped too.
+static MODEL_CONTEXTS: LazyLock<Mutex<HashMap<String, CachedModelContext>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Returns the cached `Whisper` model weights for the path, loading them if they aren't cached
+/// (or were loaded on a different device).
+///
+/// Any number of model states can be created from the weights.
+fn model_context(model_path: &str, use_gpu: bool, mmap: bool) -> VirgilResult<Arc<WhisperContext>> {
+    let mut model_contexts = MODEL_CONTEXTS
+        .lock()
+        .map_err(|e| anyhow::anyhow!("Model cache poisoned: {e}"))?;
+    if let Some((cached_use_gpu, model_ctx)) = model_contexts.get(model_path)
+        && *cached_use_gpu == use_gpu
+    {
+        debug!("Reusing cached model weights: {model_path}");
+        return Ok(model_ctx.clone());
+    }
+
+    let model_ctx = Arc::new(load_model_context(model_path, use_gpu, mmap)?);
+    model_contexts.insert(model_path.into(), (use_gpu, model_ctx.clone()));
+    Ok(model_ctx)
+}
+
+/// Removes the model weights for the path from the cache.
+///
+/// Returns whether they were cached.
+pub fn evict_model_context(model_path: &str) -> bool {
+    let evicted = MODEL_CONTEXTS
+        .lock()
+        .map(|mut model_contexts| model_contexts.remove(model_path).is_some())
+        .unwrap_or_default();
+    if evicted {
+        debug!("Model weights evicted from the cache: {model_path}");
+    }
+    evicted
+}
+
+/// Loads the `Whisper` model weights, which any number of model states can be created from.
+///
+/// If `mmap` is set, the model file is memory-mapped instead of read (see [Context::mmap_model]).
+fn load_model_context(model_path: &str, use_gpu: bool, mmap: bool) -> VirgilResult<WhisperContext> {
+    let mut ctx_params = WhisperContextParameters::default();
+    ctx_params.use_gpu(use_gpu);
+    if mmap {
+        let model_file = fs::File::open(model_path)?;
+        // NOTE: The mapping is only read while the model is built, and is dropped right after;
+        // model files aren't expected to be modified while they're being loaded.
+        let model_bytes = unsafe { Mmap::map(&model_file)? };
+        debug!("Model file memory-mapped ({} bytes)", model_bytes.len());
+        Ok(WhisperContext::new_from_buffer_with_params(
+            &model_bytes,
+            ctx_params,
+        )?)
+    } else {
+        Ok(WhisperContext::new_with_params(model_path, ctx_params)?)
+    }
+}
+
+/// Initialize the `Whisper` model, reusing the model weights if they're cached.
+///
+/// If `warmup` is set, a silent inference is run on the new model (with the given settings) so that
+/// later calls aren't slowed down by first-run initialization.
+pub fn init_model(
+    model_path: &str,
+    warmup: Option<TranscriptionConfig>,
+    use_gpu: bool,
+    mmap: bool,
+) -> VirgilResult<(ModelHandle, ModelInfo)> {
+    let span = span!(Level::TRACE, "init_model");
+    let _enter = span.enter();
+
+    let model_ctx = model_context(model_path, use_gpu, mmap)?;
+    let mut model = model_ctx.create_state()?;
+    info!("Model created: {model:?}");
+
+    let model_bytes = std::fs::metadata(model_path)?.len();
+    let model_info = ModelInfo {
+        path: model_path.into(),
+        use_gpu,
+        memory_estimate: ModelMemoryEstimate::new(&model_ctx, model_bytes),
+        multilingual: model_ctx.is_multilingual(),
+    };
+    debug!("Model info: {model_info:?}");
+
+    if let Some(transcription) = warmup {
+        let silence = vec![0.0; EXPECTED_SAMPLE_RATE];
+        model.full(transcription.to_full_params(), &silence)?;
+        debug!("Model warmed up");
+    }
+
+    Ok((ModelHandle::new(model), model_info))
+}
+
+/// Returns the codes of the languages a model supports (only `"en"` for English-only models).
+pub fn model_languages(multilingual: bool) -> Vec<String> {
+    if !multilingual {
+        return vec!["en".into()];
+    }
+    (0..=get_lang_max_id())
+        .filter_map(get_lang_str)
+        .map(String::from)
+        .collect()
+}
+
+/// Computes the root-mean-square energy of the audio data.
+pub fn rms(audio_data: &[f32]) -> f32 {
+    if audio_data.is_empty() {
+        return 0.0;
+    }
+    let sum_squares: f32 = audio_data.iter().map(|s| s * s).sum();
+    (sum_squares / audio_data.len() as f32).sqrt()
+}
+
+/// The length (in milliseconds) of the frames whose energy [is_speech] measures.
+pub const VAD_FRAME_MS: usize = 30;
+
+/// Determines if the (16kHz) audio data contains speech, i.e. if the RMS energy of any of its
+/// [VAD_FRAME_MS] frames reaches the threshold.
+///
+/// Frames are measured separately so a short word in an otherwise silent buffer isn't averaged
+/// away.
+pub fn is_speech(audio_data: &[f32], threshold: f32) -> bool {
+    let frame_len = ms_to_samples(VAD_FRAME_MS, EXPECTED_SAMPLE_RATE);
+    audio_data
+        .chunks(frame_len)
+        .any(|frame| rms(frame) >= threshold)
+}
+
+/// The state of the speaker, as determined by an [Endpointer].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpeechState {
+    /// No speech has been heard yet.
+    Silence,
+
+    /// The speaker is talking.
+    Speech,
+
+    /// The speaker paused briefly (e.g. between clauses); the utterance continues.
+    Pause,
+
+    /// The speaker has been silent long enough for the utterance to be over.
+    Endpoint,
+}
+
+/// Detects the end of an utterance by tracking the silence following speech.
+///
+/// Silences shorter than the endpoint silence are treated as natural pauses, so a sentence
+/// isn't cut off at a comma-length pause.
+pub struct Endpointer {
+    threshold: f32,
+    short_pause_samples: usize,
+    endpoint_samples: usize,
+    heard_speech: bool,
+    silent_samples: usize,
+}
+
+impl Endpointer {
+    /// Creates an endpointer using the VAD settings from the given config, for audio captured at
+    /// `sample_rate`.
+    pub fn new(config: &ListenConfig, sample_rate: usize) -> Self {
+        Self {
+            threshold: config.vad_threshold,
+            short_pause_samples: ms_to_samples(config.short_pause_ms, sample_rate),
+            endpoint_samples: ms_to_samples(config.endpoint_silence_ms, sample_rate),
+            heard_speech: false,
+            silent_samples: 0,
+        }
+    }
+
+    /// Updates the endpointer with the next chunk of audio, returning the current speech state.
+    pub fn push(&mut self, audio_data: &[f32]) -> SpeechState {
+        if rms(audio_data) >= self.threshold {
+            self.heard_speech = true;
+            self.silent_samples = 0;
+            return SpeechState::Speech;
+        }
+
+        self.silent_samples += audio_data.len();
+        if !self.heard_speech {
+            SpeechState::Silence
+        } else if self.endpoint_samples > 0 && self.silent_samples >= self.endpoint_samples {
+            SpeechState::Endpoint
+        } else if self.silent_samples >= self.short_pause_samples {
+            SpeechState::Pause
+        } else {
+            SpeechState::Speech
+        }
+    }
+
+    /// Resets the endpointer for the next utterance.
+    pub fn reset(&mut self) {
+        self.heard_speech = false;
+        self.silent_samples = 0;
+    }
+}
+
+/// Automatic gain control, which smoothly adjusts the gain of the audio so speech is at a
+/// consistent level.
+pub struct Agc {
+    target_rms: f32,
+    attack: f32,
+    release: f32,
+    threshold: f32,
+    gain: f32,
+}
+
+impl Agc {
+    /// Creates an AGC using the settings from the given config.
+    pub fn new(config: &ListenConfig) -> Self {
+        Self {
+            target_rms: config.agc_target_rms,
+            attack: config.agc_attack.clamp(0.0, 1.0),
+            release: config.agc_release.clamp(0.0, 1.0),
+            threshold: config.vad_threshold,
+            gain: 1.0,
+        }
+    }
+
+    /// Updates the gain with the next chunk of audio, then applies it to the chunk.
+    ///
+    /// # Note
+    /// The gain is only adjusted on speech, so silence/background noise isn't boosted.
+    pub fn process(&mut self, audio_data: &mut [f32]) {
+        let level = rms(audio_data);
+        if level >= self.threshold && level > 0.0 {
+            let desired = (self.target_rms / level).min(MAX_AGC_GAIN);
+            let rate = if desired < self.gain {
+                self.attack
+            } else {
+                self.release
+            };
+            self.gain += (desired - self.gain) * rate;
+        }
+
+        for sample in audio_data {
+            *sample = (*sample * self.gain).clamp(-1.0, 1.0);
+        }
+    }
+}
+
+/// A ring buffer holding the most recent audio, up to a fixed number of samples.
+pub struct PreRoll {
+    samples: VecDeque<f32>,
+    capacity: usize,
+}
+
+impl PreRoll {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Adds the audio data, dropping the oldest samples once the buffer is full.
+    pub fn push(&mut self, audio_data: &[f32]) {
+        if self.capacity == 0 {
+            return;
+        }
+        let audio_data = &audio_data[audio_data.len().saturating_sub(self.capacity)..];
+        let overflow = (self.samples.len() + audio_data.len()).saturating_sub(self.capacity);
+        self.samples.drain(..overflow);
+        self.samples.extend(audio_data);
+    }
+
+    /// Removes and returns the buffered audio (oldest first).
+    pub fn take(&mut self) -> Vec<f32> {
+        self.samples.drain(..).collect()
+    }
+}
+
+/// The maximum number of entries kept in the event log.
+pub const EVENT_LOG_CAPACITY: usize = 128;
+
+/// An event recorded in the event log.
+#[derive(Debug, Clone, Encode, Decode)]
+pub struct EventLogEntry {
+    /// When the event happened (in milliseconds since the Unix epoch).
+    pub timestamp_ms: u64,
+
+    pub event: LifecycleEvent,
+}
+
+/// A ring of the most recent session events, used to show a timeline for debugging.
+pub struct EventLog {
+    entries: VecDeque<EventLogEntry>,
+    capacity: usize,
+}
+
+impl EventLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Records the event with the current time, dropping the oldest entry once the log is full.
+    pub fn push(&mut self, event: LifecycleEvent) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+        self.entries.push_back(EventLogEntry {
+            timestamp_ms,
+            event,
+        });
+    }
+
+    /// Returns the recorded entries (oldest first).
+    pub fn entries(&self) -> Vec<EventLogEntry> {
+        self.entries.iter().cloned().collect()
+    }
+
+    /// Removes every entry.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+/// Coalesces transcripts so they're committed at most once per interval.
+pub struct TranscriptBatcher {
+    interval: Duration,
+    pending: Vec<String>,
+    last_commit: Option<Instant>,
+}
+
+impl TranscriptBatcher {
+    pub fn new(commit_interval_ms: usize) -> Self {
+        Self {
+            interval: Duration::from_millis(commit_interval_ms as u64),
+            pending: Vec::new(),
+            last_commit: None,
+        }
+    }
+
+    /// Adds a transcript to the batch, returning the coalesced transcripts if they're due to be
+    /// committed.
+    pub fn push(&mut self, text: String) -> Option<String> {
+        self.pending.push(text);
+
+        let now = Instant::now();
+        if self
+            .last_commit
+            .is_some_and(|last_commit| now - last_commit < self.interval)
+        {
+            return None;
+        }
+        self.last_commit = Some(now);
+        self.flush()
+    }
+
+    /// Returns all pending transcripts (separated by spaces), regardless of the commit interval.
+    pub fn flush(&mut self) -> Option<String> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        let text = self.pending.join(" ");
+        self.pending.clear();
+        Some(text)
+    }
+
+    /// Drops all pending transcripts, without committing them.
+    pub fn discard(&mut self) {
+        self.pending.clear();
+    }
+}
+
+/// A transcribed segment of audio.
+#[derive(Debug, Clone, Encode, Decode)]
+pub struct Segment {
+    pub text: String,
+
+    /// The start of the segment (in milliseconds).
+    pub start_ms: u64,
+
+    /// The end of the segment (in milliseconds).
+    pub end_ms: u64,
+
+    /// The mean probability of the segment's tokens.
+    pub confidence: f32,
+}
+
+/// A decoded token, with its timing.
+///
+/// Tokens are kept as bytes, since a multi-byte character can be split across tokens.
+#[derive(Debug, Clone)]
+pub struct TimedToken {
+    pub bytes: Vec<u8>,
+
+    /// The start of the token (in milliseconds).
+    pub start_ms: u64,
+
+    /// The end of the token (in milliseconds).
+    pub end_ms: u64,
+}
+
+/// A transcribed word, with its timing (e.g. for karaoke-style highlighting).
+#[derive(Debug, Clone, Encode, Decode)]
+pub struct WordTiming {
+    pub word: String,
+
+    /// The start of the word (in milliseconds).
+    pub start_ms: u64,
+
+    /// The end of the word (in milliseconds).
+    pub end_ms: u64,
+}
+
+/// Groups tokens into words, since token boundaries don't align with words.
+///
+/// A token starting with whitespace starts a new word, and any other token (a subword) is merged
+/// into the current one. Punctuation-only tokens are attached to the preceding word (or dropped
+/// if there isn't one).
+pub fn group_words(tokens: &[TimedToken]) -> Vec<WordTiming> {
+    let mut words: Vec<(Vec<u8>, u64, u64)> = Vec::new();
+    for token in tokens {
+        let text = token.bytes.trim_ascii();
+        if text.is_empty() {
+            continue;
+        }
+        let punctuation = text.iter().all(u8::is_ascii_punctuation);
+        let starts_word = token.bytes[0].is_ascii_whitespace();
+        match words.last_mut() {
+            Some((word, _, end_ms)) if punctuation || !starts_word => {
+                word.extend_from_slice(text);
+                *end_ms = token.end_ms;
+            }
+            None if punctuation => {}
+            _ => words.push((text.to_vec(), token.start_ms, token.end_ms)),
+        }
+    }
+    words
+        .into_iter()
+        .map(|(word, start_ms, end_ms)| WordTiming {
+            word: String::from_utf8_lossy(&word).into_owned(),
+            start_ms,
+            end_ms,
+        })
+        .collect()
+}
+
+/// Computes the ratio of the text's length to its `zlib` compressed length.
+///
+/// Repetitive text compresses well, so it has a higher ratio than normal speech.
+pub fn compression_ratio(text: &str) -> f32 {
+    if text.is_empty() {
+        return 0.0;
+    }
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    let compressed_len = encoder
+        .write_all(text.as_bytes())
+        .and_then(|_| encoder.finish())
+        .map_or(text.len(), |compressed| compressed.len());
+    text.len() as f32 / compressed_len.max(1) as f32
+}
+
+/// A speech-to-text engine used to transcribe audio data.
+pub trait Transcriber: Send {
+    /// Transcribes the audio data into segments of text.
+    fn transcribe(&mut self, audio_data: &[f32]) -> VirgilResult<Vec<Segment>>;
+
+    /// Transcribes the audio data for wake word detection.
+    ///
+    /// Defaults to [Transcriber::transcribe]; engines can override this to use cheaper settings
+    /// for detection.
+    fn detect(&mut self, audio_data: &[f32]) -> VirgilResult<Vec<Segment>> {
+        self.transcribe(audio_data)
+    }
+
+    /// The language detected in the most recently transcribed audio, and its probability.
+    ///
+    /// Defaults to `None`, for engines that don't detect languages.
+    fn detected_language(&self) -> Option<(String, f32)> {
+        None
+    }
+
+    /// Decodes up to `n_best` candidate transcriptions of the audio data, for
+    /// [Transcript::alternatives].
+    ///
+    /// Defaults to no candidates, for engines that can't decode alternatives.
+    fn alternatives(
+        &mut self,
+        _audio_data: &[f32],
+        _n_best: usize,
+    ) -> VirgilResult<Vec<Vec<Segment>>> {
+        Ok(Vec::new())
+    }
+}
+
+/// The size of `Whisper`'s full audio context (30s of audio).
+pub const MAX_AUDIO_CTX: i32 = 1500;
+
+/// The language that has `Whisper` detect the spoken language itself.
+pub const AUTO_LANGUAGE: &str = "auto";
+
+/// Returns the language if it's [AUTO_LANGUAGE] or a language code `Whisper` knows.
+///
+/// Unknown codes would give `Whisper` an invalid language token, so the model's default is used
+/// instead.
+fn valid_language(language: Option<&str>) -> Option<String> {
+    let language = language?;
+    if language != AUTO_LANGUAGE && (language.contains('\0') || get_lang_id(language).is_none()) {
+        warn!("Ignoring unknown language (`{language}`), using the model's default");
+        return None;
+    }
+    Some(language.into())
+}
+
+/// Returns the audio context if it's in the valid range (`1..=MAX_AUDIO_CTX`).
+fn valid_audio_ctx(audio_ctx: Option<i32>) -> Option<i32> {
+    let audio_ctx = audio_ctx?;
+    if !(1..=MAX_AUDIO_CTX).contains(&audio_ctx) {
+        warn!("Ignoring invalid audio context ({audio_ctx}), expected 1..={MAX_AUDIO_CTX}");
+        return None;
+    }
+    Some(audio_ctx)
+}
+
+/// The minimum temperature used when retrying a transcript without content.
+pub const RETRY_TEMPERATURE: f32 = 0.4;
+
+/// The number of candidates sampled when retrying a transcript without content.
+pub const RETRY_BEST_OF: usize = 5;
+
+/// The maximum beam size used when decoding alternatives (`Whisper` doesn't support more).
+pub const MAX_BEAM_SIZE: usize = 8;
+
+/// How much the temperature is raised for each alternative sampled after the beam search.
+pub const ALTERNATIVE_TEMPERATURE_STEP: f32 = 0.2;
+
+/// Determines if any of the segments contain actual words (alphanumeric characters), rather than
+/// just whitespace/punctuation.
+pub fn has_content(segments: &[Segment]) -> bool {
+    segments
+        .iter()
+        .any(|segment| segment.text.chars().any(char::is_alphanumeric))
+}
+
+/// Transcribes with the `config`, retrying once with a higher temperature (and more candidates)
+/// if `retry` is set and the transcript has no content (see [has_content]).
+fn retry_without_content(
+    config: TranscriptionConfig,
+    retry: bool,
+    mut transcribe: impl FnMut(TranscriptionConfig) -> VirgilResult<Vec<Segment>>,
+) -> VirgilResult<Vec<Segment>> {
+    let segments = transcribe(config)?;
+    if !retry || has_content(&segments) {
+        return Ok(segments);
+    }
+    debug!("Transcript has no content, retrying with a higher temperature");
+    transcribe(TranscriptionConfig {
+        temperature: config.temperature.max(RETRY_TEMPERATURE),
+        ..config.greedy(RETRY_BEST_OF)
+    })
+}
+
+/// A loaded `Whisper` model, kept in [crate::api::MODEL] between sessions.
+///
+/// `whisper-rs` marks `WhisperState` as `Sync` (with an `unsafe impl`), but `whisper.cpp` doesn't
+/// make a state safe to share: inference mutates it through a raw pointer, so it must never be
+/// used from two threads at once. The handle (like the [WhisperTranscriber] it's turned into) is
+/// `Send` but not `Sync`, and can only be used by turning it into a [WhisperTranscriber] (which
+/// only exposes [Transcriber::transcribe] and [Transcriber::detect]), so whoever takes it out of
+/// the global owns it exclusively.
+pub struct ModelHandle {
+    state: WhisperState,
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+// NOTE: The model is moved to the processing thread, so it must be `Send` for sessions to work,
+// and must not be `Sync` since its state can't be shared.
+assert_impl_all!(ModelHandle: Send);
+assert_not_impl_any!(ModelHandle: Sync);
+assert_impl_all!(WhisperTranscriber: Send);
+assert_not_impl_any!(WhisperTranscriber: Sync);
+
+impl ModelHandle {
+    fn new(state: WhisperState) -> Self {
+        Self {
+            state,
+            _not_sync: PhantomData,
+        }
+    }
+}
+
+/// Runs the inference on the `engine`, retrying it once after falling back to the CPU (with
+/// `fall_back_to_cpu`) if it fails and `fallback` is set.
+///
+/// NOTE: `Whisper` doesn't report why inference failed, so any failure on the GPU is treated as a
+/// GPU failure (OOM, driver errors, etc).
+fn retry_on_cpu<E>(
+    engine: &mut E,
+    fallback: bool,
+    mut infer: impl FnMut(&mut E) -> VirgilResult<()>,
+    fall_back_to_cpu: impl FnOnce(&mut E) -> VirgilResult<()>,
+) -> VirgilResult<()> {
+    let Err(e) = infer(engine) else {
+        return Ok(());
+    };
+    if !fallback {
+        return Err(e);
+    }
+    warn!("Inference on the GPU failed ({e}), falling back to the CPU");
+    fall_back_to_cpu(engine)?;
+    infer(engine)
+}
+
+/// Receives the text of each segment as soon as it's decoded (see [Context::streaming]).
+pub type SegmentSink = Arc<dyn Fn(String) + Send + Sync>;
+
+/// The default [Transcriber], backed by a `Whisper` model.
+pub struct WhisperTranscriber {
+    model: WhisperState,
+    _not_sync: PhantomData<Cell<()>>,
+    model_path: String,
+    use_gpu: bool,
+    gpu_fallback: bool,
+    mmap_model: bool,
+    language: Option<String>,
+    transcription: TranscriptionConfig,
+    detect_n_threads: usize,
+    report_language: bool,
+    detected_language: Option<(String, f32)>,
+    audio_ctx: Option<i32>,
+    retry_empty_transcripts: bool,
+    segment_sink: Option<SegmentSink>,
+
+    /// Coalesces the segments sent to the sink (see [Context::commit_interval_ms]).
+    segment_batcher: Arc<Mutex<TranscriptBatcher>>,
+}
+
+impl WhisperTranscriber {
+    /// Creates a new transcriber using the inference settings from the given context.
+    pub fn new(model: ModelHandle, ctx: &Context) -> Self {
+        Self {
+            model: model.state,
+            _not_sync: PhantomData,
+            model_path: ctx.resolved_model_path(),
+            use_gpu: ctx.use_gpu,
+            gpu_fallback: ctx.gpu_fallback,
+            mmap_model: ctx.mmap_model,
+            language: valid_language(ctx.language.as_deref()),
+            transcription: ctx.transcription,
+            detect_n_threads: ctx.detect_n_threads,
+            report_language: ctx.report_language,
+            detected_language: None,
+            audio_ctx: valid_audio_ctx(ctx.audio_ctx),
+            retry_empty_transcripts: ctx.retry_empty_transcripts,
+            segment_sink: None,
+            segment_batcher: Arc::new(Mutex::new(TranscriptBatcher::new(ctx.commit_interval_ms))),
+        }
+    }
+
+    /// Sends the text of each transcribed segment to the sink as soon as it's decoded, coalescing
+    /// the segments decoded within [Context::commit_interval_ms] of the last one sent.
+    ///
+    /// Only [Transcriber::transcribe] streams its segments; wake word detection and alternatives
+    /// don't. Segments still held back once a window is transcribed are never sent, since the
+    /// window's transcript supersedes them.
+    pub fn set_segment_sink(&mut self, sink: SegmentSink) {
+        self.segment_sink = Some(sink);
+    }
+
+    /// Sets the callback that sends each new segment to the sink, if there is one.
+    fn stream_segments(
+        params: &mut FullParams,
+        sink: Option<SegmentSink>,
+        batcher: &Arc<Mutex<TranscriptBatcher>>,
+    ) {
+        if let Some(sink) = sink {
+            params.set_segment_callback_safe::<_, Box<dyn FnMut(SegmentCallbackData)>>(
+                Self::segment_callback(sink, batcher.clone()),
+            );
+        }
+    }
+
+    /// Creates the callback `Whisper` calls with each decoded segment, which passes the
+    /// segment's text (if any) to the sink once the batcher commits it.
+    fn segment_callback(
+        sink: SegmentSink,
+        batcher: Arc<Mutex<TranscriptBatcher>>,
+    ) -> Box<dyn FnMut(SegmentCallbackData)> {
+        Box::new(move |segment| {
+            let text = segment.text.trim();
+            if text.is_empty() {
+                return;
+            }
+            let committed = batcher
+                .lock()
+                .map(|mut batcher| batcher.push(text.into()))
+                .unwrap_or_else(|_| Some(text.into()));
+            if let Some(text) = committed {
+                sink(text);
+            }
+        })
+    }
+
+    /// Creates the `Whisper` parameters used for inference.
+    fn params(
+        config: TranscriptionConfig,
+        language: Option<&str>,
+        audio_ctx: Option<i32>,
+    ) -> FullParams<'_, '_> {
+        let mut params = config.to_full_params();
+        if language.is_some() {
+            params.set_language(language);
+        }
+        if let Some(audio_ctx) = audio_ctx {
+            params.set_audio_ctx(audio_ctx);
+        }
+        params
+    }
+
+    /// The settings wake word detection runs with.
+    fn detect_config(&self) -> TranscriptionConfig {
+        // NOTE: Detection runs on every window, so it always decodes greedily.
+        TranscriptionConfig {
+            n_threads: self.detect_n_threads,
+            ..self.transcription.greedy(1)
+        }
+    }
+
+    /// Detects the language of the most recently transcribed audio, returning it with its
+    /// probability.
+    ///
+    /// # Note
+    /// This re-runs the encoder, so it's only done when requested.
+    fn detect_language(&self, n_threads: usize) -> VirgilResult<(String, f32)> {
+        let (lang_id, probs) = self.model.lang_detect(0, n_threads.max(1))?;
+        let lang = get_lang_str(lang_id)
+            .ok_or_else(|| anyhow::anyhow!("Unknown language id: {lang_id}"))?;
+        let probability = probs.get(lang_id as usize).copied().unwrap_or(0.0);
+        debug!("Detected language: {lang} ({probability:.2})");
+        Ok((lang.into(), probability))
+    }
+
+    /// Returns the underlying model.
+    pub fn into_model(self) -> ModelHandle {
+        ModelHandle::new(self.model)
+    }
+
+    /// Reloads the model on the CPU.
+    fn fall_back_to_cpu(&mut self) -> VirgilResult<()> {
+        let (model, model_info) = init_model(&self.model_path, None, false, self.mmap_model)?;
+        info!("Model reloaded on the CPU: {model_info:?}");
+        self.model = model.state;
+        self.use_gpu = false;
+        Ok(())
+    }
+
+    /// Runs the model on the audio data using the given settings, falling back to the CPU if
+    /// inference on the GPU fails (see [retry_on_cpu]).
+    ///
+    /// If `stream` is set, segments are sent to the segment sink as they're decoded.
+    fn infer(
+        &mut self,
+        audio_data: &[f32],
+        config: TranscriptionConfig,
+        token_timestamps: bool,
+        stream: bool,
+    ) -> VirgilResult<()> {
+        let sink = self.segment_sink.clone().filter(|_| stream);
+        let fallback = self.use_gpu && self.gpu_fallback;
+        retry_on_cpu(
+            self,
+            fallback,
+            |transcriber| {
+                let mut params = Self::params(
+                    config,
+                    transcriber.language.as_deref(),
+                    transcriber.audio_ctx,
+                );
+                params.set_token_timestamps(token_timestamps);
+                Self::stream_segments(&mut params, sink.clone(), &transcriber.segment_batcher);
+                transcriber.model.full(params, audio_data)?;
+                Ok(())
+            },
+            Self::fall_back_to_cpu,
+        )
+    }
+
+    /// Runs the model on the audio data using the given settings, streaming its segments if
+    /// `stream` is set.
+    fn run(
+        &mut self,
+        audio_data: &[f32],
+        config: TranscriptionConfig,
+        stream: bool,
+    ) -> VirgilResult<Vec<Segment>> {
+        self.infer(audio_data, config, false, stream)?;
+
+        let num_segments = self.model.full_n_segments()?;
+        let mut segments = Vec::with_capacity(num_segments as usize);
+        for i in 0..num_segments {
+            let text = self.model.full_get_segment_text(i)?;
+            let num_tokens = self.model.full_n_tokens(i)?;
+            let mut prob_sum = 0.0;
+            for j in 0..num_tokens {
+                prob_sum += self.model.full_get_token_prob(i, j)?;
+            }
+            let confidence = if num_tokens > 0 {
+                prob_sum / num_tokens as f32
+            } else {
+                0.0
+            };
+
+            // NOTE: `Whisper` timestamps are in centiseconds.
+            segments.push(Segment {
+                text,
+                confidence,
+                start_ms: self.model.full_get_segment_t0(i)?.max(0) as u64 * 10,
+                end_ms: self.model.full_get_segment_t1(i)?.max(0) as u64 * 10,
+            });
+        }
+        Ok(segments)
+    }
+
+    /// Transcribes the audio data into its (text) tokens, with their timestamps.
+    ///
+    /// Special tokens (e.g. timestamps and the end of the transcript) are left out.
+    pub fn transcribe_tokens(&mut self, audio_data: &[f32]) -> VirgilResult<Vec<TimedToken>> {
+        self.infer(audio_data, self.transcription, true, false)?;
+
+        let mut tokens = Vec::new();
+        for i in 0..self.model.full_n_segments()? {
+            for j in 0..self.model.full_n_tokens(i)? {
+                let bytes = self.model.full_get_token_bytes(i, j)?.to_vec();
+                if bytes.starts_with(b"[_") || bytes.starts_with(b"<|") {
+                    continue;
+                }
+
+                // NOTE: `Whisper` timestamps are in centiseconds.
+                let data = self.model.full_get_token_data(i, j)?;
+                tokens.push(TimedToken {
+                    bytes,
+                    start_ms: data.t0.max(0) as u64 * 10,
+                    end_ms: data.t1.max(0) as u64 * 10,
+                });
+            }
+        }
+        Ok(tokens)
+    }
+}
+
+impl Transcriber for WhisperTranscriber {
+    fn transcribe(&mut self, audio_data: &[f32]) -> VirgilResult<Vec<Segment>> {
+        // NOTE: Segments held back from the previous window were superseded by its transcript.
+        if let Ok(mut batcher) = self.segment_batcher.lock() {
+            batcher.discard();
+        }
+        let (config, retry) = (self.transcription, self.retry_empty_transcripts);
+        let segments =
+            retry_without_content(config, retry, |config| self.run(audio_data, config, true))?;
+        if self.report_language {
+            self.detected_language = self
+                .detect_language(self.transcription.n_threads)
+                .map_err(|e| warn!("Unable to detect language: {e}"))
+                .ok();
+        }
+        Ok(segments)
+    }
+
+    fn detect(&mut self, audio_data: &[f32]) -> VirgilResult<Vec<Segment>> {
+        self.run(audio_data, self.detect_config(), false)
+    }
+
+    fn detected_language(&self) -> Option<(String, f32)> {
+        self.detected_language.clone()
+    }
+
+    // NOTE: `Whisper` only returns the best beam of a beam search, so the rest of the candidates
+    // are sampled at increasing temperatures.
+    fn alternatives(
+        &mut self,
+        audio_data: &[f32],
+        n_best: usize,
+    ) -> VirgilResult<Vec<Vec<Segment>>> {
+        let mut candidates = Vec::with_capacity(n_best);
+        let beam_search = TranscriptionConfig {
+            beam_size: n_best.max(1),
+            ..self.transcription
+        };
+        candidates.push(self.run(audio_data, beam_search, false)?);
+        for i in 1..n_best {
+            let temperature =
+                self.transcription.temperature + i as f32 * ALTERNATIVE_TEMPERATURE_STEP;
+            let sampled = TranscriptionConfig {
+                temperature: temperature.min(1.0),
+                ..self.transcription.greedy(1)
+            };
+            candidates.push(self.run(audio_data, sampled, false)?);
+        }
+        Ok(candidates)
+    }
+}
+
+/// Returned when a [TranscriberPool] worker stops before finishing a job.
+#[derive(Debug, Error)]
+#[error("TranscriberPoolError: {0}")]
+pub struct TranscriberPoolError(String);
+
+/// A job for a [TranscriberPool] worker: the audio data, and where to send its segments.
+type PoolJob = (Vec<f32>, oneshot::Sender<VirgilResult<Vec<Segment>>>);
+
+/// A pool of [WhisperTranscriber]s that share one loaded model, so multiple audio buffers (e.g.
+/// from different sources) can be transcribed concurrently.
+///
+/// Each worker runs on its own thread with its own `Whisper` state; the model weights are only
+/// loaded once. Jobs are picked up by whichever worker is free.
+pub struct TranscriberPool {
+    jobs_tx: Option<mpsc::UnboundedSender<PoolJob>>,
+    workers: Vec<thread::JoinHandle<()>>,
+}
+
+impl TranscriberPool {
+    /// Loads the context's model (unless it's cached) and starts `num_workers` workers (at least
+    /// one), using the inference settings from the context.
+    pub fn new(ctx: &Context, num_workers: usize) -> VirgilResult<Self> {
+        let span = span!(Level::TRACE, "TranscriberPool::new");
+        let _enter = span.enter();
+
+        let model_path = ctx.resolved_model_path();
+        check_model_path(&model_path)?;
+        let model_ctx = model_context(&model_path, ctx.use_gpu, ctx.mmap_model)?;
+        let (jobs_tx, jobs_rx) = mpsc::unbounded_channel::<PoolJob>();
+        let jobs_rx = Arc::new(Mutex::new(jobs_rx));
+
+        let num_workers = num_workers.max(1);
+        let mut workers = Vec::with_capacity(num_workers);
+        for i in 0..num_workers {
+            let mut transcriber =
+                WhisperTranscriber::new(ModelHandle::new(model_ctx.create_state()?), ctx);
+            let jobs_rx = jobs_rx.clone();
+            let worker = thread::Builder::new()
+                .name(format!("virgil-worker-{i}"))
+                .spawn(move || {
+                    // NOTE: The lock is only held while waiting for a job, so idle workers take
+                    // turns waiting.
+                    while let Ok(Some((audio_data, result_tx))) =
+                        jobs_rx.lock().map(|mut jobs_rx| jobs_rx.blocking_recv())
+                    {
+                        let _ = result_tx.send(transcriber.transcribe(&audio_data));
+                    }
+                    debug!("Transcriber pool worker {i} stopped");
+                })?;
+            workers.push(worker);
+        }
+        info!("Transcriber pool started with {num_workers} workers");
+
+        Ok(Self {
+            jobs_tx: Some(jobs_tx),
+            workers,
+        })
+    }
+
+    /// Transcribes the audio data on the next free worker.
+    pub async fn submit(&self, audio_data: Vec<f32>) -> VirgilResult<Vec<Segment>> {
+        let (result_tx, result_rx) = oneshot::channel();
+        if let Some(jobs_tx) = &self.jobs_tx {
+            // NOTE: If the workers are gone, the result sender is dropped with the job.
+            let _ = jobs_tx.send((audio_data, result_tx));
+        }
+        result_rx
+            .await
+            .map_err(|_| TranscriberPoolError("Worker stopped before finishing".into()))?
+    }
+}
+
+impl Drop for TranscriberPool {
+    fn drop(&mut self) {
+        // Closing the job queue stops the workers once they're idle
+        self.jobs_tx.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// A transcript, before and after post-processing.
+#[derive(Debug, Clone, Encode, Decode, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Transcript {
+    /// The concatenated output of the transcriber.
+    pub raw: String,
+
+    /// The post-processed transcript.
+    pub clean: String,
+
+    /// The language detected in the audio (only set if [Context::report_language] is set).
+    pub language: Option<String>,
+
+    /// The probability of the detected language.
+    pub language_probability: f32,
+
+    /// The mean confidence of the post-processed segments (`0` if there are none).
+    pub confidence: f32,
+
+    /// The distinct candidate transcriptions of the audio, most likely first (only set if
+    /// [Context::n_best] is greater than `1`).
+    pub alternatives: Vec<Alternative>,
+}
+
+/// A candidate transcription of an utterance.
+#[derive(Debug, Clone, Encode, Decode, Serialize)]
+pub struct Alternative {
+    /// The post-processed text.
+    pub text: String,
+
+    /// The mean confidence of the post-processed segments (`0` if there are none).
+    pub score: f32,
+}
+
+impl Transcript {
+    /// Creates a transcript from the transcribed segments and the detected language (if any).
+    ///
+    /// See [clean_segments] and [join_segments] for the post-processing applied to the segments.
+    pub fn new(segments: Vec<Segment>, language: Option<(String, f32)>, ctx: &Context) -> Self {
+        let raw = join_segments(&segments, false);
+        let segments = clean_segments(segments, ctx);
+        let confidence = segments
+            .iter()
+            .map(|segment| segment.confidence)
+            .sum::<f32>()
+            / segments.len().max(1) as f32;
+        let clean = join_segments(&segments, ctx.trim_segments);
+        let (language, language_probability) = match language {
+            Some((language, probability)) => (Some(language), probability),
+            None => (None, 0.0),
+        };
+        Self {
+            raw,
+            clean,
+            language,
+            language_probability,
+            confidence,
+            alternatives: Vec::new(),
+        }
+    }
+
+    /// Determines if the transcript has text, but its confidence is below
+    /// [Context::min_confidence].
+    pub fn is_low_confidence(&self, ctx: &Context) -> bool {
+        !self.clean.is_empty() && self.confidence < ctx.min_confidence
+    }
+}
+
+/// Converts audio data to text using the provided transcriber.
+pub fn transcribe(
+    model: &mut dyn Transcriber,
+    audio_data: &[f32],
+    ctx: &Context,
+) -> VirgilResult<Transcript> {
+    let segments = model.transcribe(audio_data)?;
+    let mut transcript = Transcript::new(segments, model.detected_language(), ctx);
+    if ctx.n_best > 1 {
+        transcript.alternatives = alternatives(model, audio_data, ctx)?;
+    }
+    Ok(transcript)
+}
+
+/// Converts a recording to text using the provided transcriber, chunk by chunk (see
+/// [chunk_audio]), so it can be longer than a single `Whisper` window.
+///
+/// The chunks' segments are merged (see [merge_chunk_segments]) and post-processed like
+/// [transcribe]'s, so both give the same text for the same segments.
+pub fn transcribe_chunks(
+    model: &mut dyn Transcriber,
+    audio_data: &[f32],
+    ctx: &Context,
+) -> VirgilResult<Transcript> {
+    let segments = transcribe_chunk_segments(model, audio_data)?;
+    Ok(Transcript::new(segments, model.detected_language(), ctx))
+}
+
+/// Transcribes a recording chunk by chunk (see [chunk_audio]) into segments timed relative to
+/// the start of the recording (see [merge_chunk_segments]).
+pub fn transcribe_chunk_segments(
+    model: &mut dyn Transcriber,
+    audio_data: &[f32],
+) -> VirgilResult<Vec<Segment>> {
+    let chunks = chunk_audio(audio_data)
+        .into_iter()
+        .map(|(offset_ms, chunk)| {
+            model
+                .transcribe(&chunk)
+                .map(|segments| (offset_ms, segments))
+        })
+        .collect::<VirgilResult<Vec<_>>>()?;
+    Ok(merge_chunk_segments(chunks))
+}
+
+/// Decodes the distinct candidate transcriptions of the audio data (up to [Context::n_best]),
+/// most likely first.
+///
+/// Candidates are post-processed like transcripts, and those without text are dropped.
+fn alternatives(
+    model: &mut dyn Transcriber,
+    audio_data: &[f32],
+    ctx: &Context,
+) -> VirgilResult<Vec<Alternative>> {
+    let mut alternatives: Vec<Alternative> = Vec::with_capacity(ctx.n_best);
+    for segments in model.alternatives(audio_data, ctx.n_best)? {
+        let candidate = Transcript::new(segments, None, ctx);
+        if candidate.clean.is_empty()
+            || alternatives
+                .iter()
+                .any(|alternative| alternative.text.eq_ignore_ascii_case(&candidate.clean))
+        {
+            continue;
+        }
+        alternatives.push(Alternative {
+            text: candidate.clean,
+            score: candidate.confidence,
+        });
+    }
+    alternatives.sort_by(|a, b| b.score.total_cmp(&a.score));
+    alternatives.truncate(ctx.n_best);
+    Ok(alternatives)
+}
+
+/// Appends the text to the transcript history, separated by a space.
+pub fn append_transcript(history: &mut String, text: &str) {
+    let text = text.trim();
+    if text.is_empty() {
+        return;
+    }
+    if !history.is_empty() {
+        history.push(' ');
+    }
+    history.push_str(text);
+}
+
+/// Returns up to `len` characters of the transcript, starting at the `start`th character.
+///
+/// The slice is clamped to the end of the transcript; an empty string is returned if `start` is
+/// out of range.
+pub fn transcript_slice(transcript: &str, start: usize, len: usize) -> String {
+    transcript.chars().skip(start).take(len).collect()
+}
+
+/// Strips annotations from the segments (if [Context::strip_annotations] is set) and drops
+/// segments that are left empty or are likely hallucinated, then keeps only the first
+/// [Context::max_segments] (if set) and merges them into sentences (if
+/// [Context::merge_sentences] is set).
+pub fn clean_segments(segments: Vec<Segment>, ctx: &Context) -> Vec<Segment> {
+    let mut segments: Vec<Segment> = segments
+        .into_iter()
+        .map(|mut segment| {
+            if ctx.strip_annotations {
+                segment.text = strip_annotations(&segment.text);
+            }
+            segment
+        })
+        .filter(|segment| {
+            if ctx.strip_annotations && segment.text.trim().is_empty() {
+                return false;
+            }
+            if ctx.compression_ratio_threshold <= 0.0 {
+                return true;
+            }
+            let ratio = compression_ratio(&segment.text);
+            if ratio > ctx.compression_ratio_threshold {
+                warn!(
+                    "Dropping repetitive segment (compression ratio: {ratio:.2}): {}",
+                    segment.text
+                );
+                return false;
+            }
+            true
+        })
+        .collect();
+    if ctx.max_segments > 0 {
+        segments.truncate(ctx.max_segments);
+    }
+    if ctx.merge_sentences {
+        segments = merge_into_sentences(&segments);
+    }
+    segments
+}
+
+/// The maximum length (in characters) of a sentence merged by [merge_into_sentences] from
+/// segments without terminal punctuation.
+pub const MAX_SENTENCE_CHARS: usize = 200;
+
+/// Merges consecutive segments until one ends with sentence-ending punctuation (`.`, `?` or `!`),
+/// so each resulting segment is a whole sentence.
+///
+/// Merged segments span from the start of their first segment to the end of their last, with the
+/// mean confidence of the merged segments. Runs of segments without terminal punctuation are cut
+/// once they reach [MAX_SENTENCE_CHARS] characters.
+pub fn merge_into_sentences(segments: &[Segment]) -> Vec<Segment> {
+    let mut sentences = Vec::new();
+    let mut merged: Vec<&Segment> = Vec::new();
+    let mut merged_chars = 0;
+    for segment in segments {
+        merged.push(segment);
+        merged_chars += segment.text.trim().chars().count();
+
+        let ends_sentence = segment.text.trim_end().ends_with(['.', '?', '!']);
+        if ends_sentence || merged_chars >= MAX_SENTENCE_CHARS {
+            sentences.push(merge_segments(&merged));
+            merged.clear();
+            merged_chars = 0;
+        }
+    }
+    if !merged.is_empty() {
+        sentences.push(merge_segments(&merged));
+    }
+    sentences
+}
+
+/// Merges the (non-empty list of) segments into a single segment.
+fn merge_segments(segments: &[&Segment]) -> Segment {
+    Segment {
+        text: segments
+            .iter()
+            .map(|segment| segment.text.as_str())
+            .collect(),
+        start_ms: segments[0].start_ms,
+        end_ms: segments[segments.len() - 1].end_ms,
+        confidence: segments
+            .iter()
+            .map(|segment| segment.confidence)
+            .sum::<f32>()
+            / segments.len() as f32,
+    }
+}
+
+/// Joins the text of the segments into a single transcript.
+///
+/// If `trim` is set, each segment is normalized (see [normalize_transcript]) and the segments are
+/// separated by a single space (`Whisper` segments usually start with a space); otherwise they're
+/// concatenated as is.
+pub fn join_segments(segments: &[Segment], trim: bool) -> String {
+    let mut transcript = String::with_capacity(2048);
+    for segment in segments {
+        if !trim {
+            transcript.push_str(&segment.text);
+            continue;
+        }
+
+        let text = normalize_transcript(&segment.text);
+        if text.is_empty() {
+            continue;
+        }
+        if !transcript.is_empty() {
+            transcript.push(' ');
+        }
+        transcript.push_str(&text);
+    }
+    transcript
+}
+
+/// Trims the text and collapses every run of whitespace in it into a single space.
+pub fn normalize_transcript(raw: &str) -> String {
+    raw.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Removes the annotations `Whisper` adds for non-speech audio, i.e. any text in square brackets or
+/// parentheses (e.g. `[BLANK_AUDIO]` or `(music)`).
+///
+/// Each annotation is replaced by a space so the words around it aren't joined. Nested brackets
+/// are removed along with the annotation containing them; unclosed brackets are kept as is.
+pub fn strip_annotations(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(open_idx) = rest.find(['[', '(']) {
+        let Some(len) = annotation_len(&rest[open_idx..]) else {
+            stripped.push_str(&rest[..=open_idx]);
+            rest = &rest[open_idx + 1..];
+            continue;
+        };
+        stripped.push_str(&rest[..open_idx]);
+        stripped.push(' ');
+        rest = &rest[open_idx + len..];
+    }
+    stripped.push_str(rest);
+    stripped
+}
+
+/// The length (in bytes) of the annotation the text starts with, up to and including the bracket
+/// that closes it, or `None` if it's never closed.
+fn annotation_len(text: &str) -> Option<usize> {
+    let mut closers = Vec::new();
+    for (idx, c) in text.char_indices() {
+        match c {
+            '[' => closers.push(']'),
+            '(' => closers.push(')'),
+            ']' | ')' if closers.last() == Some(&c) => {
+                closers.pop();
+                if closers.is_empty() {
+                    return Some(idx + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// A wake word found in the audio data.
+#[derive(Debug, Clone)]
+pub struct WakeWordMatch {
+    pub word: String,
+
+    /// The mean confidence of the segments the wake word was detected in.
+    pub confidence: f32,
+
+    /// Where the wake word was heard in the detection transcript.
+    pub span: WakeWordSpan,
+}
+
+/// The words of a transcript a wake word was matched to, as a half-open range of word indices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WakeWordSpan {
+    pub start_idx: usize,
+    pub end_idx: usize,
+}
+
+/// Matches wake words to the words of a transcript, allowing for slight mishearings.
+///
+/// Transcripts and wake words are compared word by word, ignoring case and punctuation; a wake word
+/// matches a run of as many words whose text is within the maximum edit distance of it.
+#[derive(Debug, Clone, Copy)]
+pub struct WakeWordMatcher {
+    max_distance: usize,
+}
+
+impl WakeWordMatcher {
+    /// Creates a matcher that allows up to `max_distance` edits (see
+    /// [Context::wake_word_max_distance]).
+    pub fn new(max_distance: usize) -> Self {
+        Self { max_distance }
+    }
+
+    /// Finds the first run of words in the transcript that matches the wake word.
+    pub fn find(&self, transcript: &str, wake_word: &str) -> Option<WakeWordSpan> {
+        let wake_word = Self::words(wake_word);
+        let len = wake_word.len();
+        if len == 0 {
+            return None;
+        }
+        let wake_word = wake_word.join(" ");
+
+        let words = Self::words(transcript);
+        (0..=words.len().checked_sub(len)?)
+            .find(|&start_idx| {
+                levenshtein(&words[start_idx..start_idx + len].join(" "), &wake_word)
+                    <= self.max_distance
+            })
+            .map(|start_idx| WakeWordSpan {
+                start_idx,
+                end_idx: start_idx + len,
+            })
+    }
+
+    /// Splits the text into lowercase words, dropping punctuation.
+    fn words(text: &str) -> Vec<String> {
+        text.split(|c: char| !(c.is_alphanumeric() || c == '\''))
+            .filter(|word| !word.is_empty())
+            .map(str::to_lowercase)
+            .collect()
+    }
+}
+
+/// Computes the Levenshtein (edit) distance between the strings, in characters.
+pub fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut distances: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut diagonal = distances[0];
+        distances[0] = i + 1;
+        for (j, &b_char) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(a_char != b_char);
+            diagonal = distances[j + 1];
+            distances[j + 1] = substitution.min(distances[j] + 1).min(diagonal + 1);
+        }
+    }
+    distances[b.len()]
+}
+
+/// Check for the specified wake words in the audio data, returning the first one found.
+pub fn detect_wake_words(
+    model: &mut dyn Transcriber,
+    audio_data: &[f32],
+    wake_words: &Vec<String>,
+    matcher: WakeWordMatcher,
+) -> VirgilResult<Option<WakeWordMatch>> {
+    let span = span!(Level::TRACE, "detect_wake_words");
+    let _enter = span.enter();
+
+    let segments = model.detect(audio_data)?;
+    let transcript = segments
+        .iter()
+        .map(|segment| segment.text.as_str())
+        .collect::<String>();
+    for word in wake_words {
+        if let Some(span) = matcher.find(&transcript, word) {
+            info!("Wake word detected: {word}");
+            let confidence = segments
+                .iter()
+                .map(|segment| segment.confidence)
+                .sum::<f32>()
+                / segments.len().max(1) as f32;
+            return Ok(Some(WakeWordMatch {
+                word: word.clone(),
+                confidence,
+                span,
+            }));
+        }
+    }
+    Ok(None)
+}
+
+#[derive(Debug, Error)]
+pub enum MicrophoneConfigError {
+    #[error("MicrophoneConfigError: Default input device not found")]
+    NoDefaultDevice,
+
+    #[error("MicrophoneConfigError: Unable to query the input configs ({0})")]
+    ConfigsUnavailable(#[from] SupportedStreamConfigsError),
+
+    #[error("MicrophoneConfigError: No supported input configs found")]
+    NoSupportedConfig,
+
+    #[error(
+        "MicrophoneConfigError: Input channel {channel} is out of range, the device has \
+         {channels} channels"
+    )]
+    ChannelOutOfRange { channel: usize, channels: usize },
+
+    #[error("MicrophoneConfigError: Unsupported sample format: {0}")]
+    UnsupportedSampleFormat(SampleFormat),
+
+    #[error("MicrophoneConfigError: Unable to build the input stream ({0})")]
+    BuildStreamFailed(#[from] BuildStreamError),
+}
+
+/// Diagnostic information about the library, used for bug reports.
+///
+/// Fields of subsystems that haven't been initialized are left empty.
+#[derive(Debug, Default, Encode, Decode)]
+pub struct Diagnostics {
+    pub crate_version: String,
+    pub whisper_cpp_version: String,
+    pub model_path: String,
+    pub gpu_enabled: bool,
+    pub input_device: String,
+    pub sample_rate: u32,
+
+    /// The sample rate the microphone actually delivers (`0` if unknown).
+    pub detected_rate: u32,
+    pub error_count: usize,
+}
+
+/// Information about an initialized input device.
+#[derive(Debug, Clone)]
+pub struct InputDeviceInfo {
+    pub name: String,
+    pub sample_rate: u32,
+}
+
+/// Returns the input device, or [MicrophoneConfigError::NoDefaultDevice] if the host has none
+/// (e.g. on headless machines).
+fn require_input_device<D>(device: Option<D>) -> Result<D, MicrophoneConfigError> {
+    device.ok_or(MicrophoneConfigError::NoDefaultDevice)
+}
+
+/// Picks the input config of the device best suited for transcription.
+///
+/// See [best_input_config] for how configs are ranked.
+pub fn pick_best_input_config(
+    device: &Device,
+    sample_formats: &[InputSampleFormat],
+) -> VirgilResult<SupportedStreamConfig> {
+    let configs = device
+        .supported_input_configs()
+        .map_err(MicrophoneConfigError::from)?;
+    let config = best_input_config(configs, sample_formats)
+        .ok_or(MicrophoneConfigError::NoSupportedConfig)?;
+    debug!("Picked input config: {config:?}");
+    Ok(config)
+}
+
+/// Picks the best of the given input configs.
+///
+/// Configs are ranked by their position in `sample_formats` (configs in other formats are
+/// skipped), then the sample rate closest to [EXPECTED_SAMPLE_RATE], then the fewest channels.
+///
+/// # Note
+/// Configs that can't capture at the expected sample rate are used at the closest rate they
+/// support; the audio is resampled right before transcription.
+pub fn best_input_config(
+    configs: impl Iterator<Item = SupportedStreamConfigRange>,
+    sample_formats: &[InputSampleFormat],
+) -> Option<SupportedStreamConfig> {
+    let expected_rate = EXPECTED_SAMPLE_RATE as u32;
+    configs
+        .filter_map(|config| {
+            let preference = sample_formats
+                .iter()
+                .position(|format| format.sample_format() == config.sample_format())?;
+            let rate = expected_rate.clamp(config.min_sample_rate().0, config.max_sample_rate().0);
+            Some((preference, config.with_sample_rate(SampleRate(rate))))
+        })
+        .min_by_key(|(preference, config)| {
+            (
+                *preference,
+                config.sample_rate().0.abs_diff(expected_rate),
+                config.channels(),
+            )
+        })
+        .map(|(_, config)| config)
+}
+
+/// Sends the (mono) audio data of a microphone callback to the processing loop, unless the
+/// microphone is `paused` (see [MIC_PAUSED]).
+fn input_stream_listener(
+    paused: &AtomicBool,
+    sender: mpsc::Sender<Vec<f32>>,
+    data: &[f32],
+    channels: usize,
+    input_channel: Option<usize>,
+    rate_estimator: &mut RateEstimator,
+    sample_rate: u32,
+) {
+    // NOTE: Some hosts still deliver a few buffers after the stream is paused.
+    if paused.load(Ordering::SeqCst) {
+        rate_estimator.reset();
+        return;
+    }
+
+    if let Some(detected_rate) = rate_estimator.push(data.len() / channels, Instant::now()) {
+        let previous_rate = DETECTED_SAMPLE_RATE.swap(detected_rate, Ordering::SeqCst);
+        if rate_drifted(sample_rate, detected_rate) && !rate_drifted(sample_rate, previous_rate) {
+            warn!(
+                "Microphone delivers ~{detected_rate}Hz instead of the configured \
+                 {sample_rate}Hz"
+            );
+        }
+    }
+
+    match sender.try_send(extract_channel(data, channels, input_channel)) {
+        Ok(_) => {}
+        Err(e) => {
+            error!("Unable to send audio data: {e}");
+        }
+    }
+}
+
+/// Initializes the microphone.
+///
+/// Samples are converted to `f32` (see [convert_to_f32]), and multi-channel input is converted to
+/// mono (see [extract_channel]), using the `input_channel` if it's set.
+fn init_microphone(
+    audio_data_tx: mpsc::Sender<Vec<f32>>,
+    input_channel: Option<usize>,
+    sample_formats: &[InputSampleFormat],
+) -> VirgilResult<(Stream, InputDeviceInfo)> {
+    let span = span!(Level::TRACE, "init_microphone");
+    let _enter = span.enter();
+
+    // Initialize microphone
+    let host = cpal::default_host();
+    let microphone = require_input_device(host.default_input_device())?;
+    let supported_config = pick_best_input_config(&microphone, sample_formats)?;
+    let sample_format = supported_config.sample_format();
+    let config = supported_config.config();
+    let channels = config.channels as usize;
+    if let Some(channel) = input_channel
+        && channel >= channels
+    {
+        return Err(MicrophoneConfigError::ChannelOutOfRange { channel, channels }.into());
+    }
+
+    // Initialize input stream
+    fn build_input_stream<T: SizedSample>(
+        microphone: &Device,
+        config: &StreamConfig,
+        parent_span: Span,
+        audio_data_tx: mpsc::Sender<Vec<f32>>,
+        input_channel: Option<usize>,
+    ) -> VirgilResult<Stream>
+    where
+        f32: FromSample<T>,
+    {
+        let channels = config.channels as usize;
+        let sample_rate = config.sample_rate.0;
+        let mut rate_estimator = RateEstimator::default();
+        let stream = microphone
+            .build_input_stream(
+                config,
+                move |data: &[T], _: &InputCallbackInfo| {
+                    // NOTE: The callback runs on a thread owned by `cpal`, so the span is
+                    // propagated explicitly.
+                    let span = span!(parent: &parent_span, Level::TRACE, "input_stream_listener");
+                    let _enter = span.enter();
+                    input_stream_listener(
+                        &MIC_PAUSED,
+                        audio_data_tx.clone(),
+                        &convert_to_f32(data),
+                        channels,
+                        input_channel,
+                        &mut rate_estimator,
+                        sample_rate,
+                    )
+                },
+                move |err| error!("MicrophoneListenerError: {err}"),
+                None,
+            )
+            .map_err(MicrophoneConfigError::from)?;
+        Ok(stream)
+    }
+    let parent_span = span.clone();
+    DETECTED_SAMPLE_RATE.store(0, Ordering::SeqCst);
+    let stream = match sample_format {
+        SampleFormat::F32 => build_input_stream::<f32>(
+            &microphone,
+            &config,
+            parent_span,
+            audio_data_tx,
+            input_channel,
+        )?,
+        SampleFormat::I16 => build_input_stream::<i16>(
+            &microphone,
+            &config,
+            parent_span,
+            audio_data_tx,
+            input_channel,
+        )?,
+        SampleFormat::U16 => build_input_stream::<u16>(
+            &microphone,
+            &config,
+            parent_span,
+            audio_data_tx,
+            input_channel,
+        )?,
+        format => {
+            return Err(MicrophoneConfigError::UnsupportedSampleFormat(format).into());
+        }
+    };
+
+    let device_info = InputDeviceInfo {
+        name: microphone.name().unwrap_or_default(),
+        sample_rate: config.sample_rate.0,
+    };
+    info!("Microphone initalized: {device_info:?}");
+    if (device_info.sample_rate as usize) < EXPECTED_SAMPLE_RATE {
+        warn!(
+            "Microphone sample rate ({}Hz) is below {EXPECTED_SAMPLE_RATE}Hz, audio will be \
+             upsampled (accuracy may suffer)",
+            device_info.sample_rate
+        );
+    }
+    Ok((stream, device_info))
+}
+
+/// The sample rate the microphone actually delivers, inferred from the callback timing (`0` until
+/// enough audio has been captured).
+pub static DETECTED_SAMPLE_RATE: AtomicU32 = AtomicU32::new(0);
+
+/// How long audio is counted for before the delivered sample rate is estimated.
+const RATE_ESTIMATE_PERIOD: Duration = Duration::from_secs(2);
+
+/// The relative difference between the configured and delivered sample rates above which the
+/// rate is considered to have drifted.
+const RATE_DRIFT_TOLERANCE: f64 = 0.05;
+
+/// Estimates the sample rate the microphone actually delivers, by counting the frames received
+/// over [RATE_ESTIMATE_PERIOD].
+///
+/// Some drivers report a supported rate but deliver a different one, which speeds up or slows
+/// down the transcribed audio.
+#[derive(Debug, Default)]
+pub struct RateEstimator {
+    start: Option<Instant>,
+    frames: usize,
+}
+
+impl RateEstimator {
+    /// Counts the frames of a callback received at `now`, returning the estimated rate once a
+    /// whole period has been counted.
+    ///
+    /// The first callback only starts the period, since its frames were captured before it.
+    pub fn push(&mut self, frames: usize, now: Instant) -> Option<u32> {
+        let Some(start) = self.start else {
+            self.start = Some(now);
+            return None;
+        };
+        self.frames += frames;
+
+        let elapsed = now - start;
+        if elapsed < RATE_ESTIMATE_PERIOD {
+            return None;
+        }
+        let rate = self.frames as f64 / elapsed.as_secs_f64();
+        self.start = Some(now);
+        self.frames = 0;
+        Some(rate.round() as u32)
+    }
+
+    /// Restarts the estimate (e.g. after the stream was paused).
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Determines if the `detected` sample rate differs from the `configured` one by more than
+/// [RATE_DRIFT_TOLERANCE] (unknown rates, i.e. `0`, never drift).
+pub fn rate_drifted(configured: u32, detected: u32) -> bool {
+    if configured == 0 || detected == 0 {
+        return false;
+    }
+    (detected as f64 - configured as f64).abs() / configured as f64 > RATE_DRIFT_TOLERANCE
+}
+
+/// Determines if the microphone is paused, so straggler audio is ignored.
+pub static MIC_PAUSED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Error)]
+#[error("MicrophoneError: {0}")]
+pub struct MicrophoneError(String);
+
+/// A request sent to the thread that owns the microphone stream, with where to send the result.
+enum MicCommand {
+    Play(std_mpsc::Sender<Result<(), String>>),
+    Pause(std_mpsc::Sender<Result<(), String>>),
+}
+
+/// A handle to the microphone, which can be shared between threads.
+///
+/// `cpal` streams aren't `Send` (some hosts require them to stay on the thread they were created
+/// on), so the stream is created, controlled, and dropped on a dedicated thread; the handle only
+/// sends it commands. The stream is closed when the handle is dropped.
+pub struct Microphone {
+    commands_tx: Option<std_mpsc::Sender<MicCommand>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Microphone {
+    /// Initializes the microphone (see [init_microphone]) on its own thread.
+    ///
+    /// The stream starts out paused.
+    pub fn spawn(
+        audio_data_tx: mpsc::Sender<Vec<f32>>,
+        input_channel: Option<usize>,
+        sample_formats: Vec<InputSampleFormat>,
+    ) -> VirgilResult<(Self, InputDeviceInfo)> {
+        let (init_tx, init_rx) = std_mpsc::channel();
+        let (commands_tx, commands_rx) = std_mpsc::channel::<MicCommand>();
+        let parent_span = Span::current();
+        let thread = thread::Builder::new()
+            .name("virgil-mic".into())
+            .spawn(move || {
+                let _enter = parent_span.enter();
+                let stream = match init_microphone(audio_data_tx, input_channel, &sample_formats) {
+                    Ok((stream, device_info)) => {
+                        let _ = init_tx.send(Ok(device_info));
+                        stream
+                    }
+                    Err(e) => {
+                        let _ = init_tx.send(Err(e));
+                        return;
+                    }
+                };
+
+                serve_mic_commands(stream, commands_rx);
+            })?;
+
+        let device_info = init_rx
+            .recv()
+            .map_err(|_| MicrophoneError("Microphone thread exited during setup".into()))??;
+        Ok((
+            Self {
+                commands_tx: Some(commands_tx),
+                thread: Some(thread),
+            },
+            device_info,
+        ))
+    }
+
+    /// Starts (or resumes) the stream.
+    pub fn play(&self) -> VirgilResult<()> {
+        self.send(MicCommand::Play)
+    }
+
+    /// Pauses the stream.
+    pub fn pause(&self) -> VirgilResult<()> {
+        self.send(MicCommand::Pause)
+    }
+
+    /// Sends the command to the microphone thread and waits for its result.
+    fn send(
+        &self,
+        command: fn(std_mpsc::Sender<Result<(), String>>) -> MicCommand,
+    ) -> VirgilResult<()> {
+        let (result_tx, result_rx) = std_mpsc::channel();
+        self.commands_tx
+            .as_ref()
+            .and_then(|commands_tx| commands_tx.send(command(result_tx)).ok())
+            .ok_or_else(|| MicrophoneError("Microphone thread has exited".into()))?;
+        result_rx
+            .recv()
+            .map_err(|_| MicrophoneError("Microphone thread has exited".into()))?
+            .map_err(|e| MicrophoneError(e).into())
+    }
+}
+
+/// Runs the commands sent to the microphone thread on its `stream` until the handle is dropped,
+/// then closes the stream (on the same thread).
+fn serve_mic_commands(stream: impl StreamTrait, commands_rx: std_mpsc::Receiver<MicCommand>) {
+    while let Ok(command) = commands_rx.recv() {
+        match command {
+            MicCommand::Play(result_tx) => {
+                let _ = result_tx.send(stream.play().map_err(|e| e.to_string()));
+            }
+            MicCommand::Pause(result_tx) => {
+                let _ = result_tx.send(stream.pause().map_err(|e| e.to_string()));
+            }
+        }
+    }
+
+    // NOTE: The stream is paused before it's dropped, so capture stops right away even on hosts
+    // that tear streams down lazily.
+    if let Err(e) = stream.pause() {
+        warn!("Unable to pause the microphone before closing it: {e}");
+    }
+    drop(stream);
+    debug!("Microphone closed");
+}
+
+impl Drop for Microphone {
+    fn drop(&mut self) {
+        // Closing the command channel stops the thread, which drops the stream
+        self.commands_tx.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        alloc::{GlobalAlloc, Layout, System},
+        cell::Cell,
+    };
+
+    use super::*;
+    use crate::messages::encoded_len;
+
+    #[test]
+    fn max_window_samples_follows_max_window_ms() {
+        let mut listen = Context::new(String::new(), vec![]).listen;
+        assert_eq!(listen.max_window_samples(16_000), 30 * 16_000);
+
+        listen.max_window_ms = 2_000;
+        assert_eq!(listen.max_window_samples(16_000), 2 * 16_000);
+        assert_eq!(listen.max_window_samples(48_000), 2 * 48_000);
+
+        // NOTE: A zero window would never be flushed, so it is clamped to a single sample.
+        listen.max_window_ms = 0;
+        assert_eq!(listen.max_window_samples(16_000), 1);
+    }
+
+    /// A transcriber that decodes a fixed list of candidate transcriptions, the first being the
+    /// transcript.
+    struct CandidateTranscriber {
+        candidates: Vec<(&'static str, f32)>,
+    }
+
+    impl CandidateTranscriber {
+        fn segments(text: &str, confidence: f32) -> Vec<Segment> {
+            vec![Segment {
+                text: text.into(),
+                start_ms: 0,
+                end_ms: 1_000,
+                confidence,
+            }]
+        }
+    }
+
+    impl Transcriber for CandidateTranscriber {
+        fn transcribe(&mut self, _audio_data: &[f32]) -> VirgilResult<Vec<Segment>> {
+            let (text, confidence) = self.candidates[0];
+            Ok(Self::segments(text, confidence))
+        }
+
+        fn alternatives(
+            &mut self,
+            _audio_data: &[f32],
+            n_best: usize,
+        ) -> VirgilResult<Vec<Vec<Segment>>> {
+            Ok(self
+                .candidates
+                .iter()
+                .take(n_best)
+                .map(|&(text, confidence)| Self::segments(text, confidence))
+                .collect())
+        }
+    }
+
+    /// A transcriber that hears the same segments in every window.
+    struct SegmentsTranscriber {
+        segments: Vec<Segment>,
+    }
+
+    impl Transcriber for SegmentsTranscriber {
+        fn transcribe(&mut self, _audio_data: &[f32]) -> VirgilResult<Vec<Segment>> {
+            Ok(self.segments.clone())
+        }
+    }
+
+    #[test]
+    fn live_and_file_transcripts_trim_segments_alike() {
+        let mut model = SegmentsTranscriber {
+            segments: [" Turn on", "the", "  lights. "]
+                .iter()
+                .enumerate()
+                .map(|(i, text)| Segment {
+                    text: text.to_string(),
+                    start_ms: i as u64 * 500,
+                    end_ms: (i as u64 + 1) * 500,
+                    confidence: 1.0,
+                })
+                .collect(),
+        };
+        let ctx = Context::new(String::new(), Vec::new());
+        let audio_data = [0.0; 16_000];
+
+        let live = transcribe(&mut model, &audio_data, &ctx).unwrap();
+        let file = transcribe_chunks(&mut model, &audio_data, &ctx).unwrap();
+        assert_eq!(live.clean, "Turn on the lights.");
+        assert_eq!(file.clean, live.clean);
+        assert_eq!(file.raw, live.raw);
+    }
+
+    /// An engine whose inference fails on the GPU, recording whether each attempt ran on the GPU.
+    struct FlakyGpu {
+        use_gpu: bool,
+        attempts: Vec<bool>,
+    }
+
+    impl FlakyGpu {
+        fn infer(&mut self) -> VirgilResult<()> {
+            self.attempts.push(self.use_gpu);
+            if self.use_gpu {
+                return Err(anyhow::anyhow!("GPU out of memory"));
+            }
+            Ok(())
+        }
+
+        fn fall_back_to_cpu(&mut self) -> VirgilResult<()> {
+            self.use_gpu = false;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn failed_gpu_inference_is_retried_on_cpu() {
+        let mut engine = FlakyGpu {
+            use_gpu: true,
+            attempts: Vec::new(),
+        };
+        retry_on_cpu(
+            &mut engine,
+            true,
+            FlakyGpu::infer,
+            FlakyGpu::fall_back_to_cpu,
+        )
+        .unwrap();
+        assert_eq!(engine.attempts, [true, false]);
+
+        // NOTE: The model is on the CPU now, so there's nothing to fall back from.
+        retry_on_cpu(
+            &mut engine,
+            true,
+            FlakyGpu::infer,
+            FlakyGpu::fall_back_to_cpu,
+        )
+        .unwrap();
+        assert_eq!(engine.attempts, [true, false, false]);
+    }
+
+    #[test]
+    fn failed_gpu_inference_is_reported_without_fallback() {
+        let mut engine = FlakyGpu {
+            use_gpu: true,
+            attempts: Vec::new(),
+        };
+        let err = retry_on_cpu(
+            &mut engine,
+            false,
+            FlakyGpu::infer,
+            FlakyGpu::fall_back_to_cpu,
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "GPU out of memory");
+        assert_eq!(engine.attempts, [true]);
+    }
+
+    #[test]
+    fn transcript_without_content_is_retried_hotter() {
+        let config = TranscriptionConfig::default();
+        let mut temperatures = Vec::new();
+        let mut transcribe = |config: TranscriptionConfig| {
+            temperatures.push(config.temperature);
+            let text = if temperatures.len() == 1 {
+                " ..."
+            } else {
+                " Turn on the lights."
+            };
+            Ok(vec![text_segment(text)])
+        };
+
+        let segments = retry_without_content(config, true, &mut transcribe).unwrap();
+        assert_eq!(segment_texts(&segments), [" Turn on the lights."]);
+        assert_eq!(temperatures, [config.temperature, RETRY_TEMPERATURE]);
+    }
+
+    #[test]
+    fn transcript_without_content_is_kept_without_retry() {
+        let mut attempts = 0;
+        let segments = retry_without_content(TranscriptionConfig::default(), false, |_| {
+            attempts += 1;
+            Ok(vec![text_segment(" ...")])
+        })
+        .unwrap();
+        assert_eq!(segment_texts(&segments), [" ..."]);
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn transcripts_keep_only_max_segments() {
+        let mut model = SegmentsTranscriber {
+            segments: [
+                " Turn on the lights.",
+                " Play some music.",
+                " Lock the door.",
+            ]
+            .map(text_segment)
+            .to_vec(),
+        };
+        let mut ctx = Context::new(String::new(), Vec::new());
+        let transcript = transcribe(&mut model, &[0.0; 16], &ctx).unwrap();
+        assert_eq!(
+            transcript.clean,
+            "Turn on the lights. Play some music. Lock the door."
+        );
+
+        ctx.max_segments = 2;
+        let transcript = transcribe(&mut model, &[0.0; 16], &ctx).unwrap();
+        assert_eq!(transcript.clean, "Turn on the lights. Play some music.");
+    }
+
+    #[test]
+    fn transcribe_returns_distinct_alternatives_when_n_best_is_set() {
+        let mut model = CandidateTranscriber {
+            candidates: vec![
+                (" Turn on the light.", 0.5),
+                (" turn on the light.", 0.4),
+                (" Turn on the lights.", 0.75),
+                (" [BLANK_AUDIO]", 0.9),
+            ],
+        };
+        let mut ctx = Context::new(String::new(), Vec::new());
+        ctx.n_best = 4;
+        let transcript = transcribe(&mut model, &[0.0; 16], &ctx).unwrap();
+        let alternatives: Vec<_> = transcript
+            .alternatives
+            .iter()
+            .map(|alternative| (alternative.text.as_str(), alternative.score))
+            .collect();
+        assert_eq!(
+            alternatives,
+            [("Turn on the lights.", 0.75), ("Turn on the light.", 0.5)]
+        );
+
+        ctx.n_best = 1;
+        let transcript = transcribe(&mut model, &[0.0; 16], &ctx).unwrap();
+        assert!(transcript.alternatives.is_empty());
+    }
+
+    #[test]
+    fn whisper_decodes_n_best_candidates() {
+        let model_path = concat!(env!("CARGO_MANIFEST_DIR"), "/test_assets/ggml-tiny.en.bin");
+        let wav_path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/speech-16k-mono.wav"
+        );
+        let (audio_data, _) = read_wav(wav_path).unwrap();
+
+        let (model, _) = init_model(model_path, None, false, false).unwrap();
+        let ctx = Context::new(model_path.into(), Vec::new());
+        let candidates = WhisperTranscriber::new(model, &ctx)
+            .alternatives(&audio_data, 3)
+            .unwrap();
+        assert_eq!(candidates.len(), 3);
+        assert!(candidates.iter().all(|segments| !segments.is_empty()));
+    }
+
+    #[test]
+    fn detection_and_transcription_use_their_own_thread_counts() {
+        let model_path = concat!(env!("CARGO_MANIFEST_DIR"), "/test_assets/ggml-tiny.en.bin");
+        let (model, _) = init_model(model_path, None, false, false).unwrap();
+        let mut ctx = Context::new(model_path.into(), Vec::new());
+        assert_eq!(ctx.detect_n_threads, default_n_threads());
+        assert_eq!(ctx.transcription.n_threads, default_n_threads());
+        ctx.detect_n_threads = 1;
+        ctx.transcription.n_threads = 3;
+
+        let transcriber = WhisperTranscriber::new(model, &ctx);
+        assert_eq!(transcriber.detect_config().n_threads, 1);
+        assert_eq!(transcriber.transcription.n_threads, 3);
+    }
+
+    #[test]
+    fn resolve_asset_path_joins_relative_paths() {
+        assert_eq!(
+            resolve_asset_path("models/ggml-tiny.bin", Some("/data/app")),
+            "/data/app/models/ggml-tiny.bin"
+        );
+        assert_eq!(
+            resolve_asset_path("/models/ggml-tiny.bin", Some("/data/app")),
+            "/models/ggml-tiny.bin"
+        );
+        assert_eq!(
+            resolve_asset_path("models/ggml-tiny.bin", None),
+            "models/ggml-tiny.bin"
+        );
+    }
+
+    #[test]
+    fn check_model_path_rejects_missing_files_and_directories() {
+        let model_path = concat!(env!("CARGO_MANIFEST_DIR"), "/test_assets/ggml-tiny.en.bin");
+        assert!(check_model_path(model_path).is_ok());
+
+        let missing = concat!(env!("CARGO_MANIFEST_DIR"), "/test_assets/missing.bin");
+        let err = check_model_path(missing).unwrap_err();
+        assert!(err.downcast_ref::<ModelPathError>().is_some());
+        assert!(check_model_path(env!("CARGO_MANIFEST_DIR")).is_err());
+    }
+
+    /// A token with the given text and timing.
+    fn timed_token(text: &[u8], start_ms: u64, end_ms: u64) -> TimedToken {
+        TimedToken {
+            bytes: text.to_vec(),
+            start_ms,
+            end_ms,
+        }
+    }
+
+    /// The words, with their timings.
+    fn word_timings(words: &[WordTiming]) -> Vec<(&str, u64, u64)> {
+        words
+            .iter()
+            .map(|word| (word.word.as_str(), word.start_ms, word.end_ms))
+            .collect()
+    }
+
+    #[test]
+    fn group_words_merges_subword_tokens() {
+        let tokens = [
+            timed_token(b" Hey", 0, 200),
+            timed_token(b" Vir", 200, 350),
+            timed_token(b"gil", 350, 500),
+            timed_token(b" lights", 600, 900),
+        ];
+        assert_eq!(
+            word_timings(&group_words(&tokens)),
+            [("Hey", 0, 200), ("Virgil", 200, 500), ("lights", 600, 900)]
+        );
+    }
+
+    #[test]
+    fn group_words_attaches_punctuation_to_preceding_word() {
+        let tokens = [
+            timed_token(b" ...", 0, 100),
+            timed_token(b" Hey", 100, 300),
+            timed_token(b",", 300, 320),
+            timed_token(b" there", 400, 700),
+            timed_token(b" !", 700, 720),
+            timed_token(b" ", 720, 800),
+        ];
+        assert_eq!(
+            word_timings(&group_words(&tokens)),
+            [("Hey,", 100, 320), ("there!", 400, 720)]
+        );
+    }
+
+    #[test]
+    fn group_words_joins_characters_split_across_tokens() {
+        // NOTE: "é" is two bytes, split across the tokens.
+        let tokens = [
+            timed_token(b" caf\xc3", 0, 300),
+            timed_token(b"\xa9", 300, 400),
+        ];
+        assert_eq!(word_timings(&group_words(&tokens)), [("café", 0, 400)]);
+    }
+
+    #[test]
+    fn ffi_buffer_frees_what_it_allocates() {
+        let mut bytes = Vec::with_capacity(64);
+        bytes.extend_from_slice(b"Turn on the lights.");
+        let (ptr, len) = FfiBuffer::from_vec(bytes).into_raw_parts();
+        assert!(!ptr.is_null());
+        assert_eq!(len, b"Turn on the lights.".len());
+
+        let buffer = FfiBuffer::from_raw_parts(ptr, len);
+        assert_eq!(buffer.as_slice(), Some(&b"Turn on the lights."[..]));
+        buffer.free();
+
+        let (ptr, len) = FfiBuffer::from_vec(Vec::new()).into_raw_parts();
+        assert_eq!(len, 0);
+        FfiBuffer::from_raw_parts(ptr, len).free();
+    }
+
+    /// An allocator that counts the bytes allocated and freed on the current thread while it's
+    /// tracking (see [track_allocations]).
+    struct TrackingAllocator;
+
+    thread_local! {
+        static TRACKING: Cell<bool> = const { Cell::new(false) };
+        static ALLOCATED: Cell<usize> = const { Cell::new(0) };
+        static FREED: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for TrackingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            if TRACKING.try_with(Cell::get).unwrap_or_default() {
+                ALLOCATED.with(|allocated| allocated.set(allocated.get() + layout.size()));
+            }
+            unsafe { System.alloc(layout) }
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            if TRACKING.try_with(Cell::get).unwrap_or_default() {
+                FREED.with(|freed| freed.set(freed.get() + layout.size()));
+            }
+            unsafe { System.dealloc(ptr, layout) }
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: TrackingAllocator = TrackingAllocator;
+
+    /// Runs `f`, returning the number of bytes it allocated and freed (on this thread).
+    fn track_allocations(f: impl FnOnce()) -> (usize, usize) {
+        ALLOCATED.with(|allocated| allocated.set(0));
+        FREED.with(|freed| freed.set(0));
+        TRACKING.with(|tracking| tracking.set(true));
+        f();
+        TRACKING.with(|tracking| tracking.set(false));
+        (ALLOCATED.with(Cell::get), FREED.with(Cell::get))
+    }
+
+    /// A message whose byte length is overestimated.
+    #[derive(Encode, Decode)]
+    struct Overestimated(String);
+
+    impl Message for Overestimated {
+        fn byte_len(&self) -> usize {
+            self.0.byte_len() + 64
+        }
+    }
+
+    #[test]
+    fn overestimated_buffers_are_freed_with_their_allocated_length() {
+        let message = || Overestimated("Turn on the lights.".into());
+        let encoded_len = encoded_len(&message());
+        let (allocated, freed) = track_allocations(|| {
+            let message = message();
+            let mut len = 0;
+            let ptr = serialize(message, &mut len).unwrap();
+            assert_eq!(len, BUFFER_HEADER_LEN + encoded_len);
+            FfiBuffer::from_raw_parts(ptr, len).free();
+        });
+        assert!(allocated > 0);
+        assert_eq!(allocated, freed);
+    }
+
+    #[test]
+    fn null_ffi_buffer_is_ignored() {
+        let buffer = FfiBuffer::from_raw_parts(std::ptr::null_mut(), 16);
+        assert!(buffer.as_slice().is_none());
+        buffer.free();
+    }
+
+    #[test]
+    fn deserialize_rejects_null_pointer() {
+        let err = deserialize::<String>(std::ptr::null_mut(), 16).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<DeserializeError>(),
+            Some(DeserializeError::NullPointer)
+        ));
+    }
+
+    /// Deserializes a serialized string after overwriting the byte at `index` of its buffer.
+    fn deserialize_with_byte(index: usize, byte: u8) -> VirgilResult<String> {
+        let mut len = 0;
+        let ptr = serialize("hey virgil".to_string(), &mut len).unwrap();
+        // NOTE: The buffer was just allocated by `serialize`, and `index` is within its header.
+        unsafe { *ptr.cast::<u8>().add(index) = byte };
+        let decoded = deserialize(ptr, len);
+        FfiBuffer::from_raw_parts(ptr, len).free();
+        decoded
+    }
+
+    #[test]
+    fn deserialize_checks_the_buffer_header() {
+        assert_eq!(
+            deserialize_with_byte(BUFFER_MAGIC.len(), BINCODE_CONFIG_TAG).unwrap(),
+            "hey virgil"
+        );
+
+        let err = deserialize_with_byte(0, b'X').unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "DeserializeError: Buffer is missing the magic bytes"
+        );
+
+        let err = deserialize_with_byte(BUFFER_MAGIC.len(), BINCODE_CONFIG_TAG + 1).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<DeserializeError>(),
+            Some(DeserializeError::InvalidBuffer(_))
+        ));
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "DeserializeError: Incompatible bincode config (expected tag {BINCODE_CONFIG_TAG}, \
+                 found {})",
+                BINCODE_CONFIG_TAG + 1
+            )
+        );
+    }
+
+    #[test]
+    fn event_log_keeps_recent_events_in_order_with_timestamps() {
+        let now_ms = || {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64
+        };
+        let mut log = EventLog::new(3);
+        let before_ms = now_ms();
+        for event in [
+            LifecycleEvent::SessionStarted,
+            LifecycleEvent::WakeWordDetected {
+                word: "hey virgil".into(),
+                confidence: 1.0,
+            },
+            LifecycleEvent::Transcribed {
+                text: "Turn on the lights.".into(),
+            },
+            LifecycleEvent::SessionStopped,
+        ] {
+            log.push(event);
+            thread::sleep(Duration::from_millis(5));
+        }
+        let after_ms = now_ms();
+
+        // NOTE: The log is full, so the oldest event was dropped.
+        let entries = log.entries();
+        assert!(matches!(
+            entries.iter().map(|entry| &entry.event).collect::<Vec<_>>()[..],
+            [
+                LifecycleEvent::WakeWordDetected { .. },
+                LifecycleEvent::Transcribed { .. },
+                LifecycleEvent::SessionStopped,
+            ]
+        ));
+        assert!(
+            entries
+                .windows(2)
+                .all(|pair| pair[0].timestamp_ms < pair[1].timestamp_ms)
+        );
+        assert!(entries[0].timestamp_ms >= before_ms);
+        assert!(entries[2].timestamp_ms <= after_ms);
+
+        log.clear();
+        assert!(log.entries().is_empty());
+    }
+
+    #[test]
+    fn valid_language_accepts_known_codes_and_auto() {
+        assert_eq!(valid_language(Some("es")).as_deref(), Some("es"));
+        assert_eq!(
+            valid_language(Some(AUTO_LANGUAGE)).as_deref(),
+            Some(AUTO_LANGUAGE)
+        );
+        assert_eq!(valid_language(Some("xx")), None);
+        assert_eq!(valid_language(Some("e\0s")), None);
+        assert_eq!(valid_language(None), None);
+    }
+
+    #[test]
+    fn serialized_context_language_is_applied() {
+        let model_path = concat!(env!("CARGO_MANIFEST_DIR"), "/test_assets/ggml-tiny.en.bin");
+        let mut ctx = Context::new(model_path.into(), Vec::new());
+        ctx.language = Some("es".into());
+        let mut ctx_len = 0;
+        let ctx_ptr = serialize(ctx, &mut ctx_len).unwrap();
+        let decoded: VirgilResult<Context> = deserialize(ctx_ptr, ctx_len);
+        FfiBuffer::from_raw_parts(ctx_ptr, ctx_len).free();
+        let ctx = decoded.unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(ctx.language.as_deref(), Some("es"));
+
+        let (model, _) = init_model(model_path, None, false, false).unwrap();
+        let transcriber = WhisperTranscriber::new(model, &ctx);
+        assert_eq!(transcriber.language.as_deref(), Some("es"));
+    }
+
+    #[test]
+    fn listen_config_round_trips_through_ffi_buffer() {
+        let listen = ListenConfig {
+            max_window_ms: 4_000,
+            overlap_ms: 250,
+            vad_threshold: 0.02,
+            short_pause_ms: 150,
+            endpoint_silence_ms: 900,
+            max_utterance_ms: 3_000,
+            agc_enabled: true,
+            agc_target_rms: 0.2,
+            agc_attack: 0.5,
+            agc_release: 0.05,
+            preroll_ms: 300,
+        };
+        let mut listen_len = 0;
+        let listen_ptr = serialize(listen.clone(), &mut listen_len).unwrap();
+        let decoded: VirgilResult<ListenConfig> = deserialize(listen_ptr, listen_len);
+        FfiBuffer::from_raw_parts(listen_ptr, listen_len).free();
+        let decoded = decoded.unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(decoded, listen);
+
+        // NOTE: The listen config is also sent nested in the context.
+        let ctx = Context {
+            listen: listen.clone(),
+            ..Context::new(String::new(), Vec::new())
+        };
+        let mut ctx_len = 0;
+        let ctx_ptr = serialize(ctx, &mut ctx_len).unwrap();
+        let decoded: VirgilResult<Context> = deserialize(ctx_ptr, ctx_len);
+        FfiBuffer::from_raw_parts(ctx_ptr, ctx_len).free();
+        let decoded = decoded.unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(decoded.listen, listen);
+    }
+
+    #[test]
+    fn transcription_config_builds_beam_search_params() {
+        let config = TranscriptionConfig {
+            beam_size: 5,
+            ..Default::default()
+        };
+        assert!(matches!(
+            config.sampling_strategy(),
+            SamplingStrategy::BeamSearch { beam_size: 5, .. }
+        ));
+        assert!(matches!(
+            config.greedy(3).sampling_strategy(),
+            SamplingStrategy::Greedy { best_of: 3 }
+        ));
+
+        let large = TranscriptionConfig {
+            beam_size: MAX_BEAM_SIZE + 1,
+            ..config
+        };
+        assert!(matches!(
+            large.sampling_strategy(),
+            SamplingStrategy::BeamSearch { beam_size, .. } if beam_size == MAX_BEAM_SIZE as i32
+        ));
+
+        // NOTE: Building the parameters doesn't need a model.
+        let _params = config.to_full_params();
+    }
+
+    #[test]
+    fn extract_channel_downmixes_to_mono() {
+        let stereo = [0.5, -0.5, 1.0, 0.0, -1.0, -0.5];
+        assert_eq!(extract_channel(&stereo, 2, None), [0.0, 0.5, -0.75]);
+        assert_eq!(extract_channel(&stereo, 2, Some(0)), [0.5, 1.0, -1.0]);
+        assert_eq!(extract_channel(&stereo, 2, Some(1)), [-0.5, 0.0, -0.5]);
+        assert_eq!(extract_channel(&stereo, 1, None), stereo);
+
+        // NOTE: A trailing partial frame is dropped.
+        assert_eq!(extract_channel(&[0.3, 0.3, 0.0, 0.6, 0.9], 3, None), [0.2]);
+    }
+
+    #[test]
+    fn rate_drift_is_detected_beyond_tolerance() {
+        assert!(!rate_drifted(16_000, 16_000));
+        assert!(!rate_drifted(16_000, 16_700));
+        assert!(!rate_drifted(48_000, 46_000));
+        assert!(rate_drifted(16_000, 17_000));
+        assert!(rate_drifted(48_000, 44_100));
+        assert!(rate_drifted(44_100, 48_000));
+
+        // NOTE: Unknown rates never drift.
+        assert!(!rate_drifted(0, 16_000));
+        assert!(!rate_drifted(16_000, 0));
+    }
+
+    #[test]
+    fn rate_estimator_measures_delivered_rate() {
+        let mut estimator = RateEstimator::default();
+        let start = Instant::now();
+        // NOTE: The configured rate is 48kHz, but 44.1kHz is delivered in 10 ms callbacks.
+        let estimates: Vec<_> = (0..=200)
+            .filter_map(|i| estimator.push(441, start + Duration::from_millis(10 * i)))
+            .collect();
+
+        assert_eq!(estimates, [44_100]);
+        assert!(rate_drifted(48_000, estimates[0]));
+
+        estimator.reset();
+        assert_eq!(estimator.push(441, start), None);
+    }
+
+    /// A stream that records what was done to it, and on which thread.
+    struct FakeStream {
+        calls: std_mpsc::Sender<(&'static str, thread::ThreadId)>,
+    }
+
+    impl FakeStream {
+        fn record(&self, call: &'static str) {
+            let _ = self.calls.send((call, thread::current().id()));
+        }
+    }
+
+    impl StreamTrait for FakeStream {
+        fn play(&self) -> Result<(), cpal::PlayStreamError> {
+            self.record("play");
+            Ok(())
+        }
+
+        fn pause(&self) -> Result<(), cpal::PauseStreamError> {
+            self.record("pause");
+            Ok(())
+        }
+    }
+
+    impl Drop for FakeStream {
+        fn drop(&mut self) {
+            self.record("drop");
+        }
+    }
+
+    /// A [Microphone] whose thread serves the commands on a [FakeStream], and the calls made to
+    /// the stream.
+    fn fake_microphone() -> (
+        Microphone,
+        std_mpsc::Receiver<(&'static str, thread::ThreadId)>,
+    ) {
+        let (calls_tx, calls_rx) = std_mpsc::channel();
+        let (commands_tx, commands_rx) = std_mpsc::channel();
+        let thread =
+            thread::spawn(move || serve_mic_commands(FakeStream { calls: calls_tx }, commands_rx));
+        let mic = Microphone {
+            commands_tx: Some(commands_tx),
+            thread: Some(thread),
+        };
+        (mic, calls_rx)
+    }
+
+    #[test]
+    fn microphone_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Microphone>();
+    }
+
+    #[test]
+    fn microphone_stream_stays_on_its_thread() {
+        let (mic, calls_rx) = fake_microphone();
+        let mic_thread = mic.thread.as_ref().unwrap().thread().id();
+
+        // NOTE: The handle is used from another thread than the one that created it.
+        thread::spawn(move || {
+            mic.play().unwrap();
+            mic.pause().unwrap();
+            drop(mic);
+        })
+        .join()
+        .unwrap();
+
+        let calls: Vec<_> = calls_rx.try_iter().collect();
+        assert_eq!(
+            calls.iter().map(|(call, _)| *call).collect::<Vec<_>>(),
+            ["play", "pause", "pause", "drop"]
+        );
+        assert!(calls.iter().all(|(_, thread)| *thread == mic_thread));
+    }
+
+    #[test]
+    fn microphone_commands_fail_once_its_thread_exits() {
+        let mic = Microphone {
+            commands_tx: Some(std_mpsc::channel().0),
+            thread: Some(thread::spawn(|| {})),
+        };
+
+        for result in [mic.play(), mic.pause()] {
+            let err = result.unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "MicrophoneError: Microphone thread has exited"
+            );
+        }
+        // NOTE: Dropping the handle joins the thread, which must not hang once it has exited.
+        drop(mic);
+    }
+
+    #[test]
+    fn second_channel_is_extracted_from_stereo_callbacks() {
+        let left = [0.1, 0.2, 0.3, 0.4];
+        let right = [-0.1, -0.2, -0.3, -0.4];
+        let stereo: Vec<f32> = left
+            .iter()
+            .zip(&right)
+            .flat_map(|(&left, &right)| [left, right])
+            .collect();
+        assert_eq!(extract_channel(&stereo, 2, Some(1)), right);
+
+        let (audio_data_tx, mut audio_data_rx) = mpsc::channel(1);
+        input_stream_listener(
+            &AtomicBool::new(false),
+            audio_data_tx,
+            &stereo,
+            2,
+            Some(1),
+            &mut RateEstimator::default(),
+            16_000,
+        );
+        assert_eq!(audio_data_rx.try_recv().unwrap(), right);
+    }
+
+    #[test]
+    fn samples_flow_only_while_the_microphone_is_active() {
+        let paused = AtomicBool::new(false);
+        let (audio_data_tx, mut audio_data_rx) = mpsc::channel(8);
+        let mut rate_estimator = RateEstimator::default();
+        let mut listen = |data: &[f32]| {
+            input_stream_listener(
+                &paused,
+                audio_data_tx.clone(),
+                data,
+                1,
+                None,
+                &mut rate_estimator,
+                16_000,
+            )
+        };
+
+        listen(&[0.1, 0.2]);
+        paused.store(true, Ordering::SeqCst);
+        listen(&[0.3, 0.4]);
+        paused.store(false, Ordering::SeqCst);
+        listen(&[0.5, 0.6]);
+
+        assert_eq!(audio_data_rx.try_recv().unwrap(), [0.1, 0.2]);
+        assert_eq!(audio_data_rx.try_recv().unwrap(), [0.5, 0.6]);
+        assert!(audio_data_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn resample_to_16k_converts_length() {
+        let audio_data = sine(48_000);
+        let resampled = resample_to_16k(&audio_data, 48_000);
+        assert_eq!(resampled.len() * 3, audio_data.len());
+        assert!(sine_error(&resampled) < 0.05);
+        assert_eq!(resample_to_16k(&audio_data[..8_000], 8_000).len(), 16_000);
+        assert_eq!(resample_to_16k(&audio_data[..44_100], 44_100).len(), 16_000);
+
+        // 16kHz (or unknown rate) audio is left as is
+        assert_eq!(resample_to_16k(&[0.1, 0.2], 16_000), [0.1, 0.2]);
+        assert_eq!(resample_to_16k(&[0.1, 0.2], 0), [0.1, 0.2]);
+        assert!(resample_to_16k(&[], 48_000).is_empty());
+    }
+
+    #[test]
+    fn resample_to_16k_doubles_8khz_input() {
+        let audio_data = sine(8_000);
+        let resampled = resample_to_16k(&audio_data, 8_000);
+        assert_eq!(resampled.len(), 2 * audio_data.len());
+        assert!(sine_error(&resampled) < 0.05);
+    }
+
+    #[test]
+    fn resample_to_16k_filters_out_frequencies_above_8khz() {
+        // NOTE: Without filtering, the 12kHz tone would alias to 4kHz once downsampled.
+        let audio_data: Vec<f32> = sine(48_000)
+            .iter()
+            .enumerate()
+            .map(|(i, sample)| {
+                sample + 0.5 * (std::f32::consts::TAU * 12_000.0 * i as f32 / 48_000.0).sin()
+            })
+            .collect();
+        let resampled = resample_to_16k(&audio_data, 48_000);
+        assert!(sine_error(&resampled) < 0.05);
+    }
+
+    /// A second of a 440Hz sine at the given sample rate.
+    fn sine(sample_rate: usize) -> Vec<f32> {
+        (0..sample_rate)
+            .map(|i| (std::f32::consts::TAU * 440.0 * i as f32 / sample_rate as f32).sin())
+            .collect()
+    }
+
+    /// The RMS error of the resampled audio against a sine sampled at 16kHz directly, ignoring the
+    /// edges of the window.
+    fn sine_error(resampled: &[f32]) -> f32 {
+        let expected = sine(EXPECTED_SAMPLE_RATE);
+        let edge = ms_to_samples(10, EXPECTED_SAMPLE_RATE);
+        let end = resampled.len().min(expected.len()) - edge;
+        let errors: Vec<f32> = resampled[edge..end]
+            .iter()
+            .zip(&expected[edge..end])
+            .map(|(sample, expected)| sample - expected)
+            .collect();
+        rms(&errors)
+    }
+
+    #[test]
+    fn whole_window_resampling_beats_per_chunk_resampling() {
+        let audio_data = sine(44_100);
+        let whole = resample_to_16k(&audio_data, 44_100);
+        // NOTE: Callbacks deliver fixed-size buffers, which don't line up with the 16kHz samples.
+        let chunked: Vec<f32> = audio_data
+            .chunks(512)
+            .flat_map(|chunk| resample_to_16k(chunk, 44_100))
+            .collect();
+
+        let (whole_error, chunked_error) = (sine_error(&whole), sine_error(&chunked));
+        assert!(whole_error < 0.05, "Whole window error: {whole_error}");
+        assert!(
+            whole_error < chunked_error / 2.0,
+            "Whole window error: {whole_error}, per-chunk error: {chunked_error}"
+        );
+    }
+
+    /// A supported input config, as reported by a device.
+    fn input_config(
+        channels: u16,
+        min_rate: u32,
+        max_rate: u32,
+        sample_format: SampleFormat,
+    ) -> SupportedStreamConfigRange {
+        SupportedStreamConfigRange::new(
+            channels,
+            SampleRate(min_rate),
+            SampleRate(max_rate),
+            cpal::SupportedBufferSize::Unknown,
+            sample_format,
+        )
+    }
+
+    #[test]
+    fn best_input_config_prefers_16k_mono() {
+        let configs = [
+            input_config(2, 8_000, 48_000, SampleFormat::F32),
+            input_config(1, 44_100, 48_000, SampleFormat::F32),
+            input_config(1, 8_000, 48_000, SampleFormat::F32),
+        ];
+        let config = best_input_config(configs.into_iter(), &DEFAULT_SAMPLE_FORMATS).unwrap();
+        assert_eq!(config.channels(), 1);
+        assert_eq!(config.sample_rate(), SampleRate(16_000));
+    }
+
+    #[test]
+    fn best_input_config_picks_closest_rate_and_preferred_format() {
+        let configs = [
+            input_config(1, 44_100, 48_000, SampleFormat::F32),
+            input_config(1, 22_050, 22_050, SampleFormat::F32),
+            input_config(1, 16_000, 16_000, SampleFormat::I16),
+        ];
+        // NOTE: The sample format preference outranks the sample rate.
+        let config = best_input_config(configs.into_iter(), &DEFAULT_SAMPLE_FORMATS).unwrap();
+        assert_eq!(config.sample_format(), SampleFormat::F32);
+        assert_eq!(config.sample_rate(), SampleRate(22_050));
+
+        let config = best_input_config(configs.into_iter(), &[InputSampleFormat::I16]).unwrap();
+        assert_eq!(config.sample_format(), SampleFormat::I16);
+        assert_eq!(config.sample_rate(), SampleRate(16_000));
+
+        assert!(best_input_config(configs.into_iter(), &[InputSampleFormat::U16]).is_none());
+    }
+
+    #[test]
+    fn missing_input_device_is_a_typed_error() {
+        let Err(err): VirgilResult<Device> = require_input_device(None).map_err(Into::into) else {
+            panic!("Expected an error without a device");
+        };
+        assert!(matches!(
+            err.downcast_ref::<MicrophoneConfigError>(),
+            Some(MicrophoneConfigError::NoDefaultDevice)
+        ));
+        assert_eq!(
+            err.to_string(),
+            "MicrophoneConfigError: Default input device not found"
+        );
+    }
+
+    #[test]
+    fn no_matching_input_config_is_a_typed_error() {
+        let configs = [SupportedStreamConfigRange::new(
+            1,
+            SampleRate(16_000),
+            SampleRate(16_000),
+            cpal::SupportedBufferSize::Unknown,
+            SampleFormat::I32,
+        )];
+        assert!(best_input_config(configs.into_iter(), &DEFAULT_SAMPLE_FORMATS).is_none());
+        assert!(best_input_config(std::iter::empty(), &DEFAULT_SAMPLE_FORMATS).is_none());
+    }
+
+    #[test]
+    fn model_context_is_cached_by_path() {
+        // NOTE: No other test loads this model, so it isn't cached or evicted concurrently.
+        let model_path = concat!(env!("CARGO_MANIFEST_DIR"), "/test_assets/ggml-tiny.bin");
+        let model_ctx = model_context(model_path, false, false).unwrap();
+        let cached = model_context(model_path, false, false).unwrap();
+        assert!(Arc::ptr_eq(&model_ctx, &cached));
+
+        assert!(evict_model_context(model_path));
+        assert!(!evict_model_context(model_path));
+        let reloaded = model_context(model_path, false, false).unwrap();
+        assert!(!Arc::ptr_eq(&model_ctx, &reloaded));
+    }
+
+    #[test]
+    fn warmed_up_model_transcribes() {
+        let model_path = concat!(env!("CARGO_MANIFEST_DIR"), "/test_assets/ggml-tiny.en.bin");
+        let wav_path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/speech-16k-mono.wav"
+        );
+        let (audio_data, _) = read_wav(wav_path).unwrap();
+
+        let ctx = Context::new(model_path.into(), Vec::new());
+        assert!(ctx.warmup_model);
+        let (model, _) = init_model(model_path, Some(ctx.transcription), false, false).unwrap();
+        let segments = WhisperTranscriber::new(model, &ctx)
+            .transcribe(&audio_data)
+            .unwrap();
+        let text: String = segments
+            .iter()
+            .map(|segment| segment.text.as_str())
+            .collect();
+        assert!(
+            text.to_lowercase().contains("acid burns"),
+            "Unexpected transcript: {text}"
+        );
+    }
+
+    #[test]
+    fn memory_mapped_model_transcribes() {
+        let model_path = concat!(env!("CARGO_MANIFEST_DIR"), "/test_assets/ggml-tiny.en.bin");
+        let wav_path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/speech-16k-mono.wav"
+        );
+        let (audio_data, _) = read_wav(wav_path).unwrap();
+        let ctx = Context {
+            mmap_model: true,
+            ..Context::new(model_path.into(), Vec::new())
+        };
+
+        // NOTE: The cached weights may have been read from the file, so they're loaded directly.
+        let model_ctx = load_model_context(model_path, false, ctx.mmap_model).unwrap();
+        let model = ModelHandle::new(model_ctx.create_state().unwrap());
+        let mut transcriber = WhisperTranscriber::new(model, &ctx);
+        assert!(transcriber.mmap_model);
+
+        let text = join_segments(&transcriber.transcribe(&audio_data).unwrap(), true);
+        assert!(
+            text.to_lowercase().contains("acid burns"),
+            "Unexpected transcript: {text}"
+        );
+    }
+
+    #[test]
+    fn auto_detected_language_is_reported() {
+        let model_path = concat!(env!("CARGO_MANIFEST_DIR"), "/test_assets/ggml-tiny.bin");
+        let wav_path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/speech-16k-mono.wav"
+        );
+        let (audio_data, _) = read_wav(wav_path).unwrap();
+        let ctx = Context {
+            language: None,
+            report_language: true,
+            ..Context::new(model_path.into(), Vec::new())
+        };
+
+        let (model, _) = init_model(model_path, None, false, false).unwrap();
+        let mut transcriber = WhisperTranscriber::new(model, &ctx);
+        let transcript = transcribe(&mut transcriber, &audio_data, &ctx).unwrap();
+        assert_eq!(transcript.language.as_deref(), Some("en"));
+        assert!(
+            transcript.language_probability > 0.5 && transcript.language_probability <= 1.0,
+            "{}",
+            transcript.language_probability
+        );
+    }
+
+    #[test]
+    fn bounded_audio_context_is_applied_to_short_clips() {
+        assert_eq!(valid_audio_ctx(None), None);
+        assert_eq!(valid_audio_ctx(Some(0)), None);
+        assert_eq!(valid_audio_ctx(Some(MAX_AUDIO_CTX + 1)), None);
+        assert_eq!(valid_audio_ctx(Some(MAX_AUDIO_CTX)), Some(MAX_AUDIO_CTX));
+
+        let model_path = concat!(env!("CARGO_MANIFEST_DIR"), "/test_assets/ggml-tiny.en.bin");
+        let wav_path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/speech-16k-mono.wav"
+        );
+        let (audio_data, _) = read_wav(wav_path).unwrap();
+
+        // NOTE: The clip is ~3s long, and the context covers 10s of audio.
+        let ctx = Context {
+            audio_ctx: Some(500),
+            ..Context::new(model_path.into(), Vec::new())
+        };
+        let (model, _) = init_model(model_path, None, false, false).unwrap();
+        let mut transcriber = WhisperTranscriber::new(model, &ctx);
+        assert_eq!(transcriber.audio_ctx, Some(500));
+
+        let text = join_segments(&transcriber.transcribe(&audio_data).unwrap(), true);
+        assert!(
+            text.to_lowercase().contains("acid burns"),
+            "Unexpected transcript: {text}"
+        );
+
+        let ctx = Context {
+            audio_ctx: Some(-1),
+            ..ctx
+        };
+        let transcriber = WhisperTranscriber::new(transcriber.into_model(), &ctx);
+        assert_eq!(transcriber.audio_ctx, None);
+    }
+
+    #[test]
+    fn transcriber_pool_returns_every_concurrent_result() {
+        let model_path = concat!(env!("CARGO_MANIFEST_DIR"), "/test_assets/ggml-tiny.en.bin");
+        let wav_path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/speech-16k-mono.wav"
+        );
+        let (audio_data, _) = read_wav(wav_path).unwrap();
+        let ctx = Context::new(model_path.into(), Vec::new());
+        let pool = TranscriberPool::new(&ctx, 2).unwrap();
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let results = rt.block_on(futures::future::join_all(
+            (0..3).map(|_| pool.submit(audio_data.clone())),
+        ));
+        drop(pool);
+
+        assert_eq!(results.len(), 3);
+        for result in results {
+            let text = join_segments(&result.unwrap(), true);
+            assert!(
+                text.to_lowercase().contains("acid burns"),
+                "Unexpected transcript: {text}"
+            );
+        }
+    }
+
+    #[test]
+    fn model_handle_is_used_by_one_thread_at_a_time() {
+        let model_path = concat!(env!("CARGO_MANIFEST_DIR"), "/test_assets/ggml-tiny.en.bin");
+        let wav_path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/speech-16k-mono.wav"
+        );
+        let (audio_data, _) = read_wav(wav_path).unwrap();
+        let ctx = Context::new(model_path.into(), Vec::new());
+        let (model, _) = init_model(model_path, None, false, false).unwrap();
+
+        // NOTE: Like `crate::api::MODEL`, the handle is shared by taking it out of a global.
+        let shared = Arc::new(std::sync::Mutex::new(Some(model)));
+        let in_use = Arc::new(AtomicUsize::new(0));
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let (shared, in_use, ctx, audio_data) = (
+                    shared.clone(),
+                    in_use.clone(),
+                    ctx.clone(),
+                    audio_data.clone(),
+                );
+                thread::spawn(move || {
+                    loop {
+                        let Some(model) = shared.lock().unwrap().take() else {
+                            thread::yield_now();
+                            continue;
+                        };
+                        assert_eq!(in_use.fetch_add(1, Ordering::SeqCst), 0);
+                        let mut transcriber = WhisperTranscriber::new(model, &ctx);
+                        let segments = transcriber.transcribe(&audio_data);
+                        assert_eq!(in_use.fetch_sub(1, Ordering::SeqCst), 1);
+                        *shared.lock().unwrap() = Some(transcriber.into_model());
+                        return join_segments(&segments.unwrap(), true);
+                    }
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            let text = thread.join().unwrap();
+            assert!(
+                text.to_lowercase().contains("acid burns"),
+                "Unexpected transcript: {text}"
+            );
+        }
+        assert!(shared.lock().unwrap().is_some());
+    }
+
+    #[test]
+    fn is_speech_rejects_silence() {
+        assert!(!is_speech(
+            &vec![0.0; EXPECTED_SAMPLE_RATE],
+            DEFAULT_VAD_THRESHOLD
+        ));
+        assert!(!is_speech(
+            &vec![0.001; EXPECTED_SAMPLE_RATE],
+            DEFAULT_VAD_THRESHOLD
+        ));
+        assert!(!is_speech(&[], DEFAULT_VAD_THRESHOLD));
+    }
+
+    #[test]
+    fn is_speech_detects_loud_audio() {
+        assert!(is_speech(
+            &vec![0.5; EXPECTED_SAMPLE_RATE],
+            DEFAULT_VAD_THRESHOLD
+        ));
+
+        // NOTE: A single loud frame counts, even if the buffer's overall energy is low.
+        let frame_len = ms_to_samples(VAD_FRAME_MS, EXPECTED_SAMPLE_RATE);
+        let mut audio_data = vec![0.0; EXPECTED_SAMPLE_RATE];
+        audio_data[frame_len * 10..frame_len * 11].fill(0.05);
+        assert!(rms(&audio_data) < DEFAULT_VAD_THRESHOLD);
+        assert!(is_speech(&audio_data, DEFAULT_VAD_THRESHOLD));
+    }
+
+    /// Runs the AGC over a second of a sine with the given amplitude, in 100ms chunks, returning
+    /// the RMS of each processed chunk.
+    fn agc_levels(agc: &mut Agc, amplitude: f32) -> Vec<f32> {
+        sine(EXPECTED_SAMPLE_RATE)
+            .chunks(ms_to_samples(100, EXPECTED_SAMPLE_RATE))
+            .map(|chunk| {
+                let mut chunk: Vec<f32> = chunk.iter().map(|sample| sample * amplitude).collect();
+                agc.process(&mut chunk);
+                rms(&chunk)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn agc_converges_to_target_for_quiet_then_loud_speech() {
+        let config = ListenConfig {
+            agc_enabled: true,
+            vad_threshold: 0.001,
+            ..Default::default()
+        };
+        let target = config.agc_target_rms;
+        let mut agc = Agc::new(&config);
+
+        // NOTE: The gain is raised slowly, so quiet speech takes a few seconds to be boosted.
+        let quiet: Vec<f32> = (0..10).flat_map(|_| agc_levels(&mut agc, 0.02)).collect();
+        assert!(quiet[0] < target / 2.0, "{}", quiet[0]);
+        let level = *quiet.last().unwrap();
+        assert!((level - target).abs() < target * 0.05, "{level}");
+
+        // NOTE: The gain is lowered quickly, so loud speech isn't clipped for long.
+        let loud = agc_levels(&mut agc, 0.5);
+        assert!(loud[0] > target, "{}", loud[0]);
+        let level = *loud.last().unwrap();
+        assert!((level - target).abs() < target * 0.05, "{level}");
+    }
+
+    #[test]
+    fn endpointer_tells_short_pauses_from_endpoints() {
+        let config = ListenConfig::default();
+        let mut endpointer = Endpointer::new(&config, EXPECTED_SAMPLE_RATE);
+        let speech = vec![0.5; ms_to_samples(100, EXPECTED_SAMPLE_RATE)];
+        let silence = |ms| vec![0.0; ms_to_samples(ms, EXPECTED_SAMPLE_RATE)];
+
+        assert_eq!(endpointer.push(&silence(2_000)), SpeechState::Silence);
+        assert_eq!(endpointer.push(&speech), SpeechState::Speech);
+
+        // NOTE: Silences shorter than a short pause are still part of the speech.
+        assert_eq!(
+            endpointer.push(&silence(config.short_pause_ms / 2)),
+            SpeechState::Speech
+        );
+        assert_eq!(
+            endpointer.push(&silence(config.short_pause_ms / 2)),
+            SpeechState::Pause
+        );
+        assert_eq!(endpointer.push(&speech), SpeechState::Speech);
+        assert_eq!(
+            endpointer.push(&silence(config.endpoint_silence_ms - 1)),
+            SpeechState::Pause
+        );
+        assert_eq!(endpointer.push(&silence(1)), SpeechState::Endpoint);
+
+        endpointer.reset();
+        assert_eq!(endpointer.push(&silence(2_000)), SpeechState::Silence);
+    }
+
+    #[test]
+    fn segment_callback_sends_each_segment() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let sink_sent = sent.clone();
+        let mut callback = WhisperTranscriber::segment_callback(
+            Arc::new(move |text| {
+                sink_sent.lock().unwrap().push(text);
+            }),
+            Arc::new(Mutex::new(TranscriptBatcher::new(0))),
+        );
+        for (segment, text) in [" Hey Virgil,", " ", " turn on", " the lights."]
+            .into_iter()
+            .enumerate()
+        {
+            callback(SegmentCallbackData {
+                segment: segment as i32,
+                start_timestamp: segment as i64 * 100,
+                end_timestamp: (segment as i64 + 1) * 100,
+                text: text.into(),
+            });
+        }
+
+        assert_eq!(
+            *sent.lock().unwrap(),
+            vec!["Hey Virgil,", "turn on", "the lights."]
+        );
+    }
+
+    #[test]
+    fn segment_callback_sends_at_most_one_partial_per_interval() {
+        let interval = Duration::from_millis(50);
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let sink_sent = sent.clone();
+        let mut callback = WhisperTranscriber::segment_callback(
+            Arc::new(move |text| {
+                sink_sent.lock().unwrap().push(text);
+            }),
+            Arc::new(Mutex::new(TranscriptBatcher::new(
+                interval.as_millis() as usize
+            ))),
+        );
+
+        let start = Instant::now();
+        let mut segment = 0;
+        while start.elapsed() < interval * 5 {
+            callback(SegmentCallbackData {
+                segment,
+                start_timestamp: segment as i64 * 10,
+                end_timestamp: (segment as i64 + 1) * 10,
+                text: format!(" word{segment}"),
+            });
+            segment += 1;
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let elapsed = start.elapsed();
+
+        let sent = sent.lock().unwrap();
+        let max_partials = (elapsed.as_millis() / interval.as_millis()) as usize + 1;
+        assert!(
+            sent.len() > 1 && sent.len() <= max_partials,
+            "{} partials sent in {elapsed:?}",
+            sent.len()
+        );
+        assert_eq!(sent[0], "word0");
+        assert!(sent[1].starts_with("word1 word2 "), "{:?}", sent[1]);
+    }
+
+    #[test]
+    fn levenshtein_counts_edits() {
+        assert_eq!(levenshtein("virgil", "virgil"), 0);
+        assert_eq!(levenshtein("", "abc"), 3);
+        assert_eq!(levenshtein("abc", ""), 3);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("virgil", "vergil"), 1);
+    }
+
+    #[test]
+    fn wake_word_matcher_matches_exact_wake_word() {
+        let matcher = WakeWordMatcher::new(1);
+        assert_eq!(
+            matcher.find("Hey Virgil, what time is it?", "hey virgil"),
+            Some(WakeWordSpan {
+                start_idx: 0,
+                end_idx: 2
+            })
+        );
+    }
+
+    #[test]
+    fn wake_word_matcher_allows_one_edit() {
+        let matcher = WakeWordMatcher::new(1);
+        assert_eq!(
+            matcher.find("Okay, hey Vergil.", "hey virgil"),
+            Some(WakeWordSpan {
+                start_idx: 1,
+                end_idx: 3
+            })
+        );
+    }
+
+    #[test]
+    fn wake_word_matcher_rejects_two_edits() {
+        let matcher = WakeWordMatcher::new(1);
+        assert_eq!(matcher.find("Hey Vargel", "hey virgil"), None);
+        assert!(
+            WakeWordMatcher::new(2)
+                .find("Hey Vargel", "hey virgil")
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn wake_word_matcher_reports_span_of_first_match() {
+        let matcher = WakeWordMatcher::new(0);
+        assert_eq!(
+            matcher.find("so, virgil... virgil", "virgil"),
+            Some(WakeWordSpan {
+                start_idx: 1,
+                end_idx: 2
+            })
+        );
+        assert_eq!(matcher.find("", "virgil"), None);
+        assert_eq!(matcher.find("virgil", ""), None);
+    }
+
+    #[test]
+    fn normalize_transcript_collapses_whitespace() {
+        assert_eq!(
+            normalize_transcript("  hi \t there\n  you  "),
+            "hi there you"
+        );
+        assert_eq!(normalize_transcript(" \n "), "");
+    }
+
+    #[test]
+    fn strip_annotations_removes_blank_audio() {
+        assert_eq!(
+            normalize_transcript(&strip_annotations(" [BLANK_AUDIO]")),
+            ""
+        );
+    }
+
+    #[test]
+    fn strip_annotations_removes_parenthesized_annotations() {
+        assert_eq!(
+            normalize_transcript(&strip_annotations(" Hello (music) world")),
+            "Hello world"
+        );
+        assert_eq!(normalize_transcript(&strip_annotations("[A][B]ok")), "ok");
+    }
+
+    #[test]
+    fn strip_annotations_removes_nested_annotations() {
+        assert_eq!(
+            normalize_transcript(&strip_annotations("a [b (music) c] d")),
+            "a d"
+        );
+        assert_eq!(
+            normalize_transcript(&strip_annotations("a ([b]) [c [d]] e")),
+            "a e"
+        );
+    }
+
+    #[test]
+    fn strip_annotations_keeps_unclosed_brackets() {
+        assert_eq!(
+            normalize_transcript(&strip_annotations("a [b (music) c")),
+            "a [b c"
+        );
+        assert_eq!(strip_annotations("x)y("), "x)y(");
+    }
+
+    /// Encodes 16-bit PCM samples as a WAV file.
+    fn wav_bytes(samples: &[i16], channels: u16, sample_rate: u32) -> Vec<u8> {
+        let data_len = (samples.len() * 2) as u32;
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&WAV_FORMAT_PCM.to_le_bytes());
+        bytes.extend_from_slice(&channels.to_le_bytes());
+        bytes.extend_from_slice(&sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&(sample_rate * channels as u32 * 2).to_le_bytes());
+        bytes.extend_from_slice(&(channels * 2).to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&data_len.to_le_bytes());
+        for sample in samples {
+            bytes.extend_from_slice(&sample.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn wav_samples_to_f32_scales_16_bit_samples() {
+        let data: Vec<u8> = [0i16, 16_384, -16_384, i16::MIN, i16::MAX]
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        let samples = wav_samples_to_f32(WAV_FORMAT_PCM, 16, &data).unwrap();
+        assert_eq!(samples[..4], [0.0, 0.5, -0.5, -1.0]);
+        assert!((samples[4] - 1.0).abs() < 1e-4);
+
+        assert!(wav_samples_to_f32(WAV_FORMAT_PCM, 12, &data).is_err());
+        assert!(wav_samples_to_f32(0x0055, 16, &data).is_err());
+    }
+
+    #[test]
+    fn wav_samples_to_f32_maps_min_and_max_of_each_bit_depth() {
+        let pcm = |bits_per_sample, data: Vec<u8>| {
+            wav_samples_to_f32(WAV_FORMAT_PCM, bits_per_sample, &data).unwrap()
+        };
+        let int8 = pcm(8, vec![u8::MIN, 128, u8::MAX]);
+        let int16 = pcm(
+            16,
+            [i16::MIN, 0, i16::MAX]
+                .iter()
+                .flat_map(|sample| sample.to_le_bytes())
+                .collect(),
+        );
+        let int24 = pcm(
+            24,
+            [-(1 << 23), 0, (1 << 23) - 1]
+                .iter()
+                .flat_map(|sample: &i32| sample.to_le_bytes()[..3].to_vec())
+                .collect(),
+        );
+        let int32 = pcm(
+            32,
+            [i32::MIN, 0, i32::MAX]
+                .iter()
+                .flat_map(|sample| sample.to_le_bytes())
+                .collect(),
+        );
+        let float: Vec<u8> = [-1.0f32, 0.0, 1.0]
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        let float = wav_samples_to_f32(WAV_FORMAT_IEEE_FLOAT, 32, &float).unwrap();
+
+        for samples in [int8, int16, int24, int32, float] {
+            assert_eq!(samples[..2], [-1.0, 0.0]);
+            assert!(samples[2] <= 1.0 && samples[2] > 0.99, "{samples:?}");
+        }
+    }
+
+    #[test]
+    fn read_wav_decodes_16_bit_stereo_to_mono() {
+        let path = std::env::temp_dir().join(format!("virgil-read-wav-{}.wav", std::process::id()));
+        fs::write(&path, wav_bytes(&[16_384, 0, -16_384, -16_384], 2, 8_000)).unwrap();
+        let result = read_wav(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+
+        let (samples, sample_rate) = result.unwrap();
+        assert_eq!(sample_rate, 8_000);
+        assert_eq!(samples, [0.25, -0.5]);
+    }
+
+    #[test]
+    fn read_wav_reads_fixture() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/speech-16k-mono.wav"
+        );
+        let (samples, sample_rate) = read_wav(path).unwrap();
+        assert_eq!(sample_rate, EXPECTED_SAMPLE_RATE);
+        assert_eq!(samples.len(), 46_986);
+        assert!(is_speech(&samples, DEFAULT_VAD_THRESHOLD));
+    }
+
+    #[test]
+    fn read_wav_reads_24_bit_fixture() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/tone-24bit-stereo.wav"
+        );
+        let (samples, sample_rate) = read_wav(path).unwrap();
+
+        // NOTE: The fixture's frames are (half scale, 0), (min, min), (max, max) and
+        // (-quarter scale, quarter scale).
+        assert_eq!(sample_rate, 44_100);
+        assert_eq!(samples, [0.25, -1.0, 8_388_607.0 / 8_388_608.0, 0.0]);
+    }
+
+    #[test]
+    fn whisper_segment_timestamps_are_monotonic() {
+        let model_path = concat!(env!("CARGO_MANIFEST_DIR"), "/test_assets/ggml-tiny.en.bin");
+        let wav_path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/speech-16k-mono.wav"
+        );
+        let (speech, _) = read_wav(wav_path).unwrap();
+        let pause = vec![0.0; EXPECTED_SAMPLE_RATE];
+        let audio_data = [&speech[..], &pause, &speech, &pause, &speech].concat();
+
+        let (model, _) = init_model(model_path, None, false, false).unwrap();
+        let ctx = Context::new(model_path.into(), Vec::new());
+        let segments = WhisperTranscriber::new(model, &ctx)
+            .transcribe(&audio_data)
+            .unwrap();
+        assert!(
+            segments.len() > 1,
+            "Expected several segments: {segments:?}"
+        );
+        assert!(
+            segments
+                .iter()
+                .all(|segment| segment.start_ms <= segment.end_ms)
+        );
+        assert!(
+            segments
+                .windows(2)
+                .all(|pair| pair[0].start_ms < pair[1].start_ms
+                    && pair[0].end_ms <= pair[1].start_ms)
+        );
+    }
+
+    /// A segment with the given text, spanning a second.
+    fn text_segment(text: &str) -> Segment {
+        Segment {
+            text: text.into(),
+            start_ms: 0,
+            end_ms: 1_000,
+            confidence: 1.0,
+        }
+    }
+
+    /// The text of each segment.
+    fn segment_texts(segments: &[Segment]) -> Vec<&str> {
+        segments
+            .iter()
+            .map(|segment| segment.text.as_str())
+            .collect()
+    }
+
+    /// A segment with the text, spanning `start_ms..end_ms`.
+    fn timed_segment(text: &str, start_ms: u64, end_ms: u64, confidence: f32) -> Segment {
+        Segment {
+            text: text.into(),
+            start_ms,
+            end_ms,
+            confidence,
+        }
+    }
+
+    #[test]
+    fn segments_are_merged_into_sentences() {
+        let segments = [
+            timed_segment(" Turn on", 0, 500, 0.9),
+            timed_segment(" the lights.", 500, 1_000, 0.7),
+            timed_segment(" Is it", 1_200, 1_500, 0.6),
+            timed_segment(" raining?", 1_500, 2_000, 0.8),
+            timed_segment(" Wow!", 2_000, 2_400, 1.0),
+            timed_segment(" and then", 2_600, 3_000, 0.5),
+        ];
+        let sentences = merge_into_sentences(&segments);
+
+        assert_eq!(
+            segment_texts(&sentences),
+            [
+                " Turn on the lights.",
+                " Is it raining?",
+                " Wow!",
+                " and then"
+            ]
+        );
+        let spans: Vec<_> = sentences
+            .iter()
+            .map(|sentence| (sentence.start_ms, sentence.end_ms))
+            .collect();
+        assert_eq!(
+            spans,
+            [(0, 1_000), (1_200, 2_000), (2_000, 2_400), (2_600, 3_000)]
+        );
+        assert!((sentences[0].confidence - 0.8).abs() < 1e-6);
+        assert!((sentences[1].confidence - 0.7).abs() < 1e-6);
+        assert!(merge_into_sentences(&[]).is_empty());
+    }
+
+    #[test]
+    fn unpunctuated_sentences_are_cut_at_max_chars() {
+        // NOTE: Each segment is 10 characters (once trimmed), without terminal punctuation.
+        let segments = vec![text_segment(" lights off"); MAX_SENTENCE_CHARS / 10 + 1];
+        let sentences = merge_into_sentences(&segments);
+
+        assert_eq!(sentences.len(), 2);
+        assert_eq!(
+            sentences[0].text,
+            " lights off".repeat(MAX_SENTENCE_CHARS / 10)
+        );
+        assert_eq!(sentences[1].text, " lights off");
+    }
+
+    #[test]
+    fn repetitive_segments_are_dropped() {
+        let repetitive = " Thank you.".repeat(20);
+        assert!(compression_ratio(&repetitive) > DEFAULT_COMPRESSION_RATIO_THRESHOLD);
+
+        let ctx = Context::new(String::new(), Vec::new());
+        let segments = vec![
+            text_segment(" Turn on the lights."),
+            text_segment(&repetitive),
+        ];
+        let cleaned = clean_segments(segments.clone(), &ctx);
+        assert_eq!(segment_texts(&cleaned), [" Turn on the lights."]);
+
+        let ctx = Context {
+            compression_ratio_threshold: 0.0,
+            ..ctx
+        };
+        assert_eq!(clean_segments(segments, &ctx).len(), 2);
+    }
+
+    /// A transcriber that hears one segment a second into each chunk, recording the length of
+    /// each chunk it's given.
+    #[derive(Default)]
+    struct ChunkTranscriber {
+        chunks: Vec<usize>,
+    }
+
+    impl Transcriber for ChunkTranscriber {
+        fn transcribe(&mut self, audio_data: &[f32]) -> VirgilResult<Vec<Segment>> {
+            self.chunks.push(audio_data.len());
+            Ok(vec![Segment {
+                text: format!(" Chunk {}.", self.chunks.len()),
+                start_ms: 1_000,
+                end_ms: 2_000,
+                confidence: 1.0,
+            }])
+        }
+    }
+
+    #[test]
+    fn long_recordings_get_global_segment_times() {
+        let mut model = ChunkTranscriber::default();
+        let audio_data = vec![0.0; ms_to_samples(65_000, EXPECTED_SAMPLE_RATE)];
+        let segments = transcribe_chunk_segments(&mut model, &audio_data).unwrap();
+
+        let chunk_len = ms_to_samples(FILE_CHUNK_MS, EXPECTED_SAMPLE_RATE);
+        assert_eq!(model.chunks.len(), 3);
+        assert!(model.chunks.iter().all(|&len| len <= chunk_len));
+
+        // NOTE: Chunks start 29s apart, since each overlaps the previous one by 1s.
+        let times: Vec<_> = segments
+            .iter()
+            .map(|segment| (segment.text.as_str(), segment.start_ms, segment.end_ms))
+            .collect();
+        assert_eq!(
+            times,
+            [
+                (" Chunk 1.", 1_000, 2_000),
+                (" Chunk 2.", 30_000, 31_000),
+                (" Chunk 3.", 59_000, 60_000),
+            ]
+        );
+    }
+
+    #[test]
+    fn merged_chunk_segments_are_monotonic() {
+        let segment = |text: &str, start_ms, end_ms| Segment {
+            text: text.into(),
+            start_ms,
+            end_ms,
+            confidence: 1.0,
+        };
+        let merged = merge_chunk_segments(vec![
+            (
+                0,
+                vec![segment("a", 0, 10_000), segment("b", 10_000, 29_500)],
+            ),
+            // NOTE: "b" is heard again in the overlap, so it's dropped.
+            (29_000, vec![segment("b", 0, 500), segment("c", 600, 5_000)]),
+        ]);
+        let texts: Vec<_> = merged.iter().map(|segment| segment.text.as_str()).collect();
+        assert_eq!(texts, ["a", "b", "c"]);
+        assert_eq!((merged[2].start_ms, merged[2].end_ms), (29_600, 34_000));
+        assert!(
+            merged
+                .windows(2)
+                .all(|pair| pair[0].end_ms <= pair[1].start_ms)
+        );
+    }
+}